@@ -0,0 +1,93 @@
+//! Time to first token over a multi-turn session, with and without prefix caching.
+//!
+//! Run with `cargo bench -p ai --bench prefix_cache`. The model is taken from the peek config
+//! unless `PEEK_BENCH_MODEL` is set, and `PEEK_BENCH_TABLES` controls the size of the synthetic
+//! schema in the system prompt (default 200 tables).
+//!
+//! Routing is turned off so that every turn uses the same model, which is loaded before the
+//! first turn is timed. Like in the ui, the current date and the recently executed queries are
+//! sent as volatile context, and a query is added to them after every turn.
+
+use std::time::{Duration, Instant};
+
+use ai::{CurrentDate, LLM, RecentQueries};
+
+const PROMPTS: [&str; 5] = [
+    "Which tables hold information about customers?",
+    "Write a query that lists the ten most recent orders.",
+    "Now only include orders above 100.",
+    "How would I join that with the shipments table?",
+    "Summarise what we have done so far in one sentence.",
+];
+
+fn schema_prompt(tables: usize) -> String {
+    let schema = (0..tables)
+        .map(|i| {
+            format!(
+                "table_{i}(id uuid, name text, created_at timestamptz, amount numeric, parent_id uuid references table_{}.id)",
+                (i + 1) % tables
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    format!(
+        "You are a database expert working with a postgres database with this schema:\n{schema}"
+    )
+}
+
+async fn session(prefix_cache_size: usize, tables: usize) -> Result<Vec<Duration>, String> {
+    let mut conf = config::PeekConfig::get_or_default().ai;
+    if let Ok(model) = std::env::var("PEEK_BENCH_MODEL") {
+        conf.model = model;
+    }
+    conf.prefix_cache_size = prefix_cache_size;
+    conf.routing = config::RoutingConfig::default();
+
+    let mut llm = LLM::with_config(conf);
+    llm.preload().await?;
+    llm.set_system_prompt(schema_prompt(tables)).await;
+    let recent_queries = RecentQueries::new(10);
+    llm.add_context_provider(CurrentDate, 20);
+    llm.add_context_provider(recent_queries.clone(), 1000);
+
+    let mut timings = vec![];
+    for (turn, prompt) in PROMPTS.into_iter().enumerate() {
+        let start = Instant::now();
+        let mut first_token = None;
+
+        llm.stream_completion(prompt, |_| {
+            first_token.get_or_insert_with(|| start.elapsed());
+            async {}
+        })
+        .await?;
+
+        timings.push(first_token.unwrap_or_else(|| start.elapsed()));
+        recent_queries.push(format!("SELECT * FROM table_{turn} LIMIT 10"));
+    }
+
+    Ok(timings)
+}
+
+#[tokio::main]
+async fn main() -> Result<(), String> {
+    let tables = std::env::var("PEEK_BENCH_TABLES")
+        .ok()
+        .and_then(|tables| tables.parse().ok())
+        .unwrap_or(200);
+
+    let uncached = session(0, tables).await?;
+    let cached = session(16, tables).await?;
+
+    println!("{:>5} {:>14} {:>14}", "turn", "no cache", "prefix cache");
+    for (turn, (uncached, cached)) in uncached.iter().zip(&cached).enumerate() {
+        println!(
+            "{:>5} {:>12}ms {:>12}ms",
+            turn + 1,
+            uncached.as_millis(),
+            cached.as_millis()
+        );
+    }
+
+    Ok(())
+}
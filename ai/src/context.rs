@@ -0,0 +1,143 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+/// Supplies one section of context for the model. Providers are asked for their content before
+/// every request, so the section always reflects the current state.
+pub trait ContextProvider: Send + Sync {
+    /// Heading of the section, also identifies the provider
+    fn title(&self) -> &str;
+
+    /// Current content of the section, `None` leaves the section out
+    fn context(&self) -> Option<String>;
+
+    /// Whether the content changes between requests. Volatile sections are sent in the user turn
+    /// of the next prompt whenever they have changed, instead of in the system message, so that
+    /// they don't invalidate the cached prefix.
+    fn volatile(&self) -> bool {
+        false
+    }
+}
+
+/// Rough token count used for budgeting, most tokenizers average around four characters per token
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+/// Cut `text` down to roughly `budget` tokens
+pub(crate) fn truncate_to_budget(text: String, budget: usize) -> String {
+    if estimate_tokens(&text) <= budget {
+        return text;
+    }
+
+    let mut truncated: String = text.chars().take(budget.saturating_mul(4)).collect();
+    truncated.push_str("\n[truncated]");
+    truncated
+}
+
+/// Context that doesn't change, like a schema or a glossary
+#[derive(Debug, Clone)]
+pub struct StaticContext {
+    title: String,
+    content: String,
+}
+
+impl StaticContext {
+    pub fn new(title: impl Into<String>, content: impl Into<String>) -> Self {
+        Self {
+            title: title.into(),
+            content: content.into(),
+        }
+    }
+}
+
+impl ContextProvider for StaticContext {
+    fn title(&self) -> &str {
+        &self.title
+    }
+
+    fn context(&self) -> Option<String> {
+        Some(self.content.clone())
+    }
+}
+
+/// Today's date, so the model can resolve things like "last week"
+#[derive(Debug, Clone, Default)]
+pub struct CurrentDate;
+
+impl ContextProvider for CurrentDate {
+    fn title(&self) -> &str {
+        "Current date"
+    }
+
+    fn context(&self) -> Option<String> {
+        Some(chrono::Local::now().format("%Y-%m-%d (%A)").to_string())
+    }
+
+    fn volatile(&self) -> bool {
+        true
+    }
+}
+
+/// The most recently executed queries, newest last.
+///
+/// Clones share the same list, so a frontend can keep one to [`push`](Self::push) to after
+/// handing another to the LLM.
+#[derive(Debug, Clone)]
+pub struct RecentQueries {
+    queries: Arc<Mutex<VecDeque<String>>>,
+    capacity: usize,
+}
+
+impl RecentQueries {
+    /// Keep the last `capacity` queries, 0 keeps none and leaves the section out
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            queries: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+        }
+    }
+
+    pub fn push(&self, query: impl Into<String>) {
+        let mut queries = self
+            .queries
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+
+        if self.capacity == 0 {
+            return;
+        }
+        if queries.len() == self.capacity {
+            queries.pop_front();
+        }
+        queries.push_back(query.into());
+    }
+}
+
+impl ContextProvider for RecentQueries {
+    fn title(&self) -> &str {
+        "Recently executed queries"
+    }
+
+    fn context(&self) -> Option<String> {
+        let queries = self
+            .queries
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+
+        if queries.is_empty() {
+            return None;
+        }
+
+        Some(
+            queries
+                .iter()
+                .map(|query| format!("- {query}"))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        )
+    }
+
+    fn volatile(&self) -> bool {
+        true
+    }
+}
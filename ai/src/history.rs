@@ -0,0 +1,65 @@
+use mistralrs::{RequestBuilder, TextMessageRole, ToolCallResponse};
+
+/// A message in the conversation history
+#[derive(Debug, Clone)]
+pub enum ChatMessage {
+    /// A prompt from the user, with the volatile context of when it was sent if that changed
+    /// since the prompt before
+    User {
+        prompt: String,
+        context: Option<String>,
+    },
+    /// A reply from the model together with the tools it called
+    Assistant {
+        content: String,
+        tool_calls: Vec<ToolCallResponse>,
+    },
+    /// The result of the assistant tool call with the same id
+    ToolResult {
+        tool_call_id: String,
+        content: String,
+    },
+}
+
+impl ChatMessage {
+    /// Append this message to a request in the form the model's chat template expects
+    pub(crate) fn add_to(&self, builder: RequestBuilder) -> RequestBuilder {
+        match self {
+            Self::User {
+                prompt,
+                context: None,
+            } => builder.add_message(TextMessageRole::User, prompt),
+            Self::User {
+                prompt,
+                context: Some(context),
+            } => builder.add_message(
+                TextMessageRole::User,
+                format!("{context}\n\n## Prompt\n{prompt}"),
+            ),
+            Self::Assistant {
+                content,
+                tool_calls,
+            } if tool_calls.is_empty() => builder.add_message(TextMessageRole::Assistant, content),
+            Self::Assistant {
+                content,
+                tool_calls,
+            } => builder.add_message_with_tool_call(
+                TextMessageRole::Assistant,
+                content,
+                tool_calls.clone(),
+            ),
+            Self::ToolResult {
+                tool_call_id,
+                content,
+            } => builder.add_tool_message(content, tool_call_id),
+        }
+    }
+
+    /// The tool calls made in this message, empty unless it's from the assistant
+    pub fn tool_calls(&self) -> &[ToolCallResponse] {
+        match self {
+            Self::Assistant { tool_calls, .. } => tool_calls,
+            _ => &[],
+        }
+    }
+}
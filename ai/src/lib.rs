@@ -1,10 +1,25 @@
+use std::collections::HashSet;
 use std::fmt::Display;
 use std::future::Future;
 
-use mistralrs::{Model, RequestBuilder, Response, TextMessageRole, TextModelBuilder, ToolChoice};
+mod context;
+mod guard;
+mod history;
+mod models;
+
+use mistralrs::{RequestBuilder, Response, TextMessageRole, ToolChoice};
+
+use crate::context::truncate_to_budget;
+use crate::guard::UNTRUSTED_DATA_NOTICE;
+use crate::models::Models;
 
 // Re-export types that consumers will need to create and use tools
-pub use mistralrs::{Function, Tool, ToolType};
+pub use config::Step;
+pub use context::{ContextProvider, CurrentDate, RecentQueries, StaticContext, estimate_tokens};
+pub use guard::{detect_injection, wrap_untrusted};
+pub use history::ChatMessage;
+pub use mistralrs::{CalledFunction, Function, Tool, ToolCallResponse, ToolType};
+pub use models::DEFAULT_MODEL;
 pub use serde_json::{Value, json};
 pub use std::collections::HashMap;
 
@@ -16,6 +31,16 @@ pub struct ToolCallInfo {
     pub arguments: String,
 }
 
+impl From<&ToolCallResponse> for ToolCallInfo {
+    fn from(call: &ToolCallResponse) -> Self {
+        Self {
+            id: call.id.clone(),
+            name: call.function.name.clone(),
+            arguments: call.function.arguments.clone(),
+        }
+    }
+}
+
 /// Represents a chunk in the streaming response
 #[derive(Debug, Clone)]
 pub enum StreamChunk {
@@ -26,149 +51,322 @@ pub enum StreamChunk {
 }
 
 pub struct LLM {
-    model: Model,
-    history: Vec<(TextMessageRole, String)>,
+    models: Models,
+    system_prompt: Option<String>,
+    /// Providers whose sections follow the system prompt, with their token budget
+    context_providers: Vec<(Box<dyn ContextProvider>, usize)>,
+    history: Vec<ChatMessage>,
     tools: Vec<Tool>,
+    /// Names of the tools that can modify data
+    write_tools: HashSet<String>,
+    /// Number of failed tool results since the last user prompt
+    failed_attempts: usize,
+    /// Whether a tool result with instruction-like content has been read since the last user prompt
+    flagged_content_read: bool,
+}
+
+impl Default for LLM {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl LLM {
-    pub async fn new() -> Self {
-        let conf = config::PeekConfig::get_or_default();
-        let model = TextModelBuilder::new(conf.ai.model)
-            .with_dtype(mistralrs::ModelDType::F16)
-            .build()
-            .await
-            .expect("Couldn't get model");
+    /// Create an LLM from the user's config. Models are loaded the first time they are routed to.
+    pub fn new() -> Self {
+        Self::with_config(config::PeekConfig::get_or_default().ai)
+    }
 
+    pub fn with_config(conf: config::AIConfig) -> Self {
         LLM {
-            model,
+            models: Models::new(conf),
+            system_prompt: None,
+            context_providers: vec![],
             history: vec![],
             tools: vec![],
+            write_tools: HashSet::new(),
+            failed_attempts: 0,
+            flagged_content_read: false,
         }
     }
 
+    /// Load the default model now instead of on the first request that is routed to it
+    pub async fn preload(&mut self) -> Result<(), String> {
+        self.models.get(DEFAULT_MODEL).await.map(|_| ())
+    }
+
     pub fn add_tool(&mut self, tool: Tool) {
         self.tools.push(tool);
     }
 
-    /// Set all tools for the LLM, replacing any existing tools
+    /// Add a tool that can modify data. Once a tool result with instruction-like content has been
+    /// read, write tools are withheld from the model until the next user prompt.
+    pub fn add_write_tool(&mut self, tool: Tool) {
+        self.write_tools.insert(tool.function.name.clone());
+        self.tools.push(tool);
+    }
+
+    /// Whether the model may call this tool right now. Frontends should refuse calls to tools
+    /// that aren't allowed, the model can still ask for tools it hasn't been offered.
+    pub fn is_tool_allowed(&self, name: &str) -> bool {
+        !(self.flagged_content_read && self.write_tools.contains(name))
+    }
+
+    /// Set all tools for the LLM, replacing any existing tools. None of them can modify data,
+    /// add those with [`add_write_tool`](Self::add_write_tool) afterwards.
     pub fn set_tools(&mut self, tools: Vec<Tool>) {
         self.tools = tools;
+        self.write_tools.clear();
     }
 
+    /// Set the system prompt, replacing the previous one
     pub async fn set_system_prompt(&mut self, prompt: impl Display) {
-        self.history
-            .push((TextMessageRole::System, prompt.to_string()));
+        self.system_prompt = Some(prompt.to_string());
+    }
+
+    /// Add a provider whose output is sent with each request, cut down to `token_budget` tokens.
+    /// A provider with the same title as an existing one replaces it.
+    ///
+    /// Sections appear in the order the providers were added. The system message and the history
+    /// are cached as a prefix between turns, so [volatile](ContextProvider::volatile) sections go
+    /// into the user turn of the prompt they were current for instead.
+    pub fn add_context_provider(
+        &mut self,
+        provider: impl ContextProvider + 'static,
+        token_budget: usize,
+    ) {
+        let provider: Box<dyn ContextProvider> = Box::new(provider);
+
+        match self
+            .context_providers
+            .iter_mut()
+            .find(|(existing, _)| existing.title() == provider.title())
+        {
+            Some(existing) => *existing = (provider, token_budget),
+            None => self.context_providers.push((provider, token_budget)),
+        }
+    }
+
+    /// The sections of the context providers that are or aren't volatile
+    fn context_sections(&self, volatile: bool) -> impl Iterator<Item = String> {
+        self.context_providers
+            .iter()
+            .filter(move |(provider, _)| provider.volatile() == volatile)
+            .filter_map(|(provider, budget)| {
+                let context = provider.context()?;
+                Some(format!(
+                    "## {}\n{}",
+                    provider.title(),
+                    truncate_to_budget(context, *budget)
+                ))
+            })
+    }
+
+    /// Assemble the system prompt and the sections of the context providers that aren't volatile
+    fn system_message(&self) -> Option<String> {
+        let sections = self.context_sections(false);
+
+        let notice = (!self.tools.is_empty()).then(|| UNTRUSTED_DATA_NOTICE.to_string());
+
+        let message = self
+            .system_prompt
+            .iter()
+            .cloned()
+            .chain(notice)
+            .chain(sections)
+            .collect::<Vec<_>>()
+            .join("\n\n");
+
+        (!message.is_empty()).then_some(message)
+    }
+
+    /// The sections of volatile context providers, added to the next prompt when they change
+    fn volatile_message(&self) -> Option<String> {
+        let message = self.context_sections(true).collect::<Vec<_>>().join("\n\n");
+        (!message.is_empty()).then_some(message)
     }
 
     pub async fn stream_completion<F, Fut>(
         &mut self,
         prompt: impl Display,
-        mut on_chunk: F,
+        on_chunk: F,
     ) -> Result<Vec<ToolCallInfo>, String>
     where
         F: FnMut(StreamChunk) -> Fut,
         Fut: Future<Output = ()>,
     {
-        self.history
-            .push((TextMessageRole::User, prompt.to_string()));
+        // Each request has to start with everything the previous one sent for the model to
+        // reuse its cache, so context that changed goes into the new user turn rather than the
+        // system message. Chat templates don't all allow system messages after the first.
+        let context = self
+            .volatile_message()
+            .filter(|context| self.last_context() != Some(context.as_str()));
+        self.history.push(ChatMessage::User {
+            prompt: prompt.to_string(),
+            context,
+        });
+        self.failed_attempts = 0;
+        self.flagged_content_read = false;
 
-        let mut request_builder = self
-            .history
-            .iter()
-            .fold(RequestBuilder::new(), |builder, (role, content)| {
-                builder.add_message(role.clone(), content.clone())
-            });
+        self.respond(Step::Prompt, &[], true, on_chunk).await
+    }
 
-        if !self.tools.is_empty() {
-            request_builder = request_builder
-                .set_tools(self.tools.clone())
-                .set_tool_choice(ToolChoice::Auto);
-        }
+    /// Add a tool result to the conversation history and continue, for an answer with a single
+    /// tool call. See [`LLM::add_tool_results`].
+    pub async fn add_tool_result<F, Fut>(
+        &mut self,
+        tool_call_id: String,
+        result: Result<String, String>,
+        on_chunk: F,
+    ) -> Result<Vec<ToolCallInfo>, String>
+    where
+        F: FnMut(StreamChunk) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        self.add_tool_results(vec![(tool_call_id, result)], on_chunk)
+            .await
+    }
 
-        let request_builder = request_builder;
+    /// Add the results of all tool calls of an answer to the conversation history, by tool call
+    /// id, then continue. The model only answers once every call has its result, chat templates
+    /// don't allow anything else between the results. Its answer can call tools again.
+    ///
+    /// An `Err` result is passed on to the model as well, but counts as a failed attempt
+    /// towards the routing fallback. Either way the content is wrapped as untrusted data, and if
+    /// it contains instruction-like text write tools are blocked for the rest of the turn.
+    pub async fn add_tool_results<F, Fut>(
+        &mut self,
+        results: Vec<(String, Result<String, String>)>,
+        on_chunk: F,
+    ) -> Result<Vec<ToolCallInfo>, String>
+    where
+        F: FnMut(StreamChunk) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        let tools = results
+            .into_iter()
+            .filter_map(|(tool_call_id, result)| self.record_tool_result(tool_call_id, result))
+            .collect::<Vec<_>>();
+        let tools = tools.iter().map(String::as_str).collect::<Vec<_>>();
 
-        let mut stream = self
-            .model
-            .stream_chat_request(request_builder)
+        self.respond(Step::ToolResult, &tools, true, on_chunk).await
+    }
+
+    /// Answer the tool calls of the last answer with an error, then have the model answer with
+    /// what it has, without offering it tools. For frontends that stop running tools, so that no
+    /// call is left without a result.
+    pub async fn decline_tool_calls<F, Fut>(
+        &mut self,
+        reason: &str,
+        on_chunk: F,
+    ) -> Result<(), String>
+    where
+        F: FnMut(StreamChunk) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        let ids = self.history.last().map_or(vec![], |message| {
+            message
+                .tool_calls()
+                .iter()
+                .map(|call| call.id.clone())
+                .collect()
+        });
+
+        let tools = ids
+            .into_iter()
+            .filter_map(|id| self.record_tool_result(id, Err(reason.to_string())))
+            .collect::<Vec<_>>();
+        let tools = tools.iter().map(String::as_str).collect::<Vec<_>>();
+
+        self.respond(Step::ToolResult, &tools, false, on_chunk)
             .await
-            .map_err(|e| e.to_string())?;
+            .map(drop)
+    }
 
-        let mut full_response = String::new();
-        let mut tool_calls: Vec<ToolCallInfo> = vec![];
+    /// Add a tool result to the history, returning the name of the tool that was called
+    fn record_tool_result(
+        &mut self,
+        tool_call_id: String,
+        result: Result<String, String>,
+    ) -> Option<String> {
+        let content = result.unwrap_or_else(|err| {
+            self.failed_attempts += 1;
+            err
+        });
 
-        while let Some(chunk) = stream.next().await {
-            if let Response::Chunk(chunk_response) = chunk {
-                if let Some(choice) = chunk_response.choices.first()
-                    && let Some(content) = &choice.delta.content
-                {
-                    full_response.push_str(content);
-                    on_chunk(StreamChunk::Text(content.clone())).await;
-                }
-                if let Some(choice) = chunk_response.choices.first()
-                    && let Some(tool) = &choice.delta.tool_calls
-                    && let Some(call) = tool.first()
-                {
-                    let tool_call_info = ToolCallInfo {
-                        id: call.id.clone(),
-                        name: call.function.name.clone(),
-                        arguments: call.function.arguments.clone(),
-                    };
-                    tool_calls.push(tool_call_info.clone());
-                    on_chunk(StreamChunk::ToolCall(tool_call_info)).await;
-                }
-            }
-        }
+        let tool = self
+            .history
+            .iter()
+            .rev()
+            .flat_map(ChatMessage::tool_calls)
+            .find(|call| call.id == tool_call_id)
+            .map(|call| call.function.name.clone());
 
-        self.history
-            .push((TextMessageRole::Assistant, full_response.clone()));
+        let flagged = detect_injection(&content);
+        self.flagged_content_read |= !flagged.is_empty();
 
-        Ok(tool_calls)
+        let content = wrap_untrusted(
+            tool.as_deref().unwrap_or("unknown"),
+            &tool_call_id,
+            &content,
+            &flagged,
+        );
+
+        self.history.push(ChatMessage::ToolResult {
+            tool_call_id,
+            content,
+        });
+        tool
     }
 
-    /// Add a tool result to the conversation history and continue
-    pub async fn add_tool_result<F, Fut>(
+    /// Send the history to the model routed to for this step and stream back its answer
+    async fn respond<F, Fut>(
         &mut self,
-        tool_call_id: String,
-        result: String,
+        step: Step,
+        answered_tools: &[&str],
+        offer_tools: bool,
         mut on_chunk: F,
     ) -> Result<Vec<ToolCallInfo>, String>
     where
         F: FnMut(StreamChunk) -> Fut,
         Fut: Future<Output = ()>,
     {
-        // Add tool result to history
-        self.history.push((
-            TextMessageRole::Tool,
-            serde_json::json!({
-                "tool_call_id": tool_call_id,
-                "content": result,
-            })
-            .to_string(),
-        ));
+        let request_builder = match self.system_message() {
+            Some(message) => RequestBuilder::new().add_message(TextMessageRole::System, message),
+            None => RequestBuilder::new(),
+        };
 
-        // Build request with updated history
         let mut request_builder = self
             .history
             .iter()
-            .fold(RequestBuilder::new(), |builder, (role, content)| {
-                builder.add_message(role.clone(), content.clone())
-            });
+            .fold(request_builder, |builder, message| message.add_to(builder));
+
+        let tools: Vec<Tool> = self
+            .tools
+            .iter()
+            .filter(|tool| offer_tools && self.is_tool_allowed(&tool.function.name))
+            .cloned()
+            .collect();
 
-        if !self.tools.is_empty() {
+        if !tools.is_empty() {
             request_builder = request_builder
-                .set_tools(self.tools.clone())
+                .set_tools(tools)
                 .set_tool_choice(ToolChoice::Auto);
         }
 
-        let mut stream = self
-            .model
+        let model_name = self
+            .models
+            .route(step, answered_tools, self.failed_attempts)
+            .to_string();
+        let model = self.models.get(&model_name).await?;
+
+        let mut stream = model
             .stream_chat_request(request_builder)
             .await
             .map_err(|e| e.to_string())?;
 
         let mut full_response = String::new();
-        let mut tool_calls: Vec<ToolCallInfo> = vec![];
+        let mut tool_calls: Vec<ToolCallResponse> = vec![];
 
         while let Some(chunk) = stream.next().await {
             if let Response::Chunk(chunk_response) = chunk {
@@ -179,24 +377,40 @@ impl LLM {
                     on_chunk(StreamChunk::Text(content.clone())).await;
                 }
                 if let Some(choice) = chunk_response.choices.first()
-                    && let Some(tool) = &choice.delta.tool_calls
-                    && let Some(call) = tool.first()
+                    && let Some(calls) = &choice.delta.tool_calls
                 {
-                    let tool_call_info = ToolCallInfo {
-                        id: call.id.clone(),
-                        name: call.function.name.clone(),
-                        arguments: call.function.arguments.clone(),
-                    };
-                    tool_calls.push(tool_call_info.clone());
-                    on_chunk(StreamChunk::ToolCall(tool_call_info)).await;
+                    for call in calls {
+                        tool_calls.push(call.clone());
+                        on_chunk(StreamChunk::ToolCall(ToolCallInfo::from(call))).await;
+                    }
                 }
             }
         }
 
-        self.history
-            .push((TextMessageRole::Assistant, full_response.clone()));
+        let tool_call_infos = tool_calls.iter().map(ToolCallInfo::from).collect();
+
+        self.history.push(ChatMessage::Assistant {
+            content: full_response,
+            tool_calls,
+        });
+
+        Ok(tool_call_infos)
+    }
+
+    /// The volatile context that was last sent with a prompt
+    fn last_context(&self) -> Option<&str> {
+        self.history.iter().rev().find_map(|message| match message {
+            ChatMessage::User {
+                context: Some(context),
+                ..
+            } => Some(context.as_str()),
+            _ => None,
+        })
+    }
 
-        Ok(tool_calls)
+    /// The conversation so far, without the system message
+    pub fn history(&self) -> &[ChatMessage] {
+        &self.history
     }
 
     /// Get the tools that are configured for this LLM
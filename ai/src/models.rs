@@ -0,0 +1,135 @@
+use std::collections::{BTreeSet, HashMap};
+
+use config::{AIConfig, RoutingConfig, Step};
+use mistralrs::{Model, TextModelBuilder};
+
+/// Name under which the default model from [`AIConfig::model`] is registered
+pub const DEFAULT_MODEL: &str = "default";
+
+/// A model that is only downloaded and loaded the first time it is used
+struct LazyModel {
+    id: String,
+    model: Option<Model>,
+}
+
+impl LazyModel {
+    async fn get(&mut self, prefix_cache_n: Option<usize>) -> Result<&Model, String> {
+        let model = match self.model.take() {
+            Some(model) => model,
+            None => TextModelBuilder::new(&self.id)
+                .with_dtype(mistralrs::ModelDType::F16)
+                .with_prefix_cache_n(prefix_cache_n)
+                .build()
+                .await
+                .map_err(|e| format!("Couldn't load model {}: {e}", self.id))?,
+        };
+
+        Ok(self.model.insert(model))
+    }
+}
+
+/// All configured models together with the policy that picks one of them per request
+pub(crate) struct Models {
+    models: HashMap<String, LazyModel>,
+    routing: RoutingConfig,
+    /// Every request re-sends the whole history, so the prefix cache is what keeps the system
+    /// prompt and earlier turns from being processed again on each turn
+    prefix_cache_n: Option<usize>,
+    /// Set when the config can't be used as it is, every request fails with it
+    config_error: Option<String>,
+}
+
+impl Models {
+    pub(crate) fn new(conf: AIConfig) -> Self {
+        // Routes to `default` would silently go to `ai.model` instead of the model configured
+        // under that name
+        let reserved_name = conf.models.contains_key(DEFAULT_MODEL).then(|| {
+            format!(
+                "`{DEFAULT_MODEL}` in [ai.models] is reserved for `ai.model`, give that model another name"
+            )
+        });
+        // A typo in a route would otherwise quietly send its requests to the default model
+        let unknown_routes = conf
+            .routing
+            .steps
+            .values()
+            .chain(conf.routing.tools.values())
+            .filter(|name| *name != DEFAULT_MODEL && !conf.models.contains_key(*name))
+            .map(|name| format!("`{name}`"))
+            .collect::<BTreeSet<_>>();
+        let config_error = reserved_name.or_else(|| {
+            (!unknown_routes.is_empty()).then(|| {
+                format!(
+                    "[ai.routing] names models that aren't in [ai.models]: {}",
+                    unknown_routes.into_iter().collect::<Vec<_>>().join(", ")
+                )
+            })
+        });
+
+        let mut models: HashMap<String, LazyModel> = conf
+            .models
+            .into_iter()
+            .map(|(name, id)| (name, LazyModel { id, model: None }))
+            .collect();
+
+        models.insert(
+            DEFAULT_MODEL.to_string(),
+            LazyModel {
+                id: conf.model,
+                model: None,
+            },
+        );
+
+        Self {
+            models,
+            routing: conf.routing,
+            prefix_cache_n: (conf.prefix_cache_size > 0).then_some(conf.prefix_cache_size),
+            config_error,
+        }
+    }
+
+    /// Pick the model name for a request.
+    ///
+    /// After `fallback_after` failed attempts the default model is used, otherwise a route for a
+    /// tool whose result is being answered wins over a route for the step type. With results of
+    /// several tools the first one that has a route decides.
+    pub(crate) fn route(&self, step: Step, tools: &[&str], failed_attempts: usize) -> &str {
+        if self
+            .routing
+            .fallback_after
+            .is_some_and(|limit| failed_attempts >= limit)
+        {
+            return DEFAULT_MODEL;
+        }
+
+        let routed = tools
+            .iter()
+            .filter(|_| step == Step::ToolResult)
+            .find_map(|tool| self.routing.tools.get(*tool))
+            .or_else(|| self.routing.steps.get(&step));
+
+        match routed {
+            Some(name) if self.models.contains_key(name) => name,
+            _ => DEFAULT_MODEL,
+        }
+    }
+
+    /// Get a model by name, loading it if this is the first time it is used
+    pub(crate) async fn get(&mut self, name: &str) -> Result<&Model, String> {
+        if let Some(error) = &self.config_error {
+            return Err(error.clone());
+        }
+
+        let name = if self.models.contains_key(name) {
+            name
+        } else {
+            DEFAULT_MODEL
+        };
+
+        self.models
+            .get_mut(name)
+            .ok_or_else(|| format!("No model named {name}"))?
+            .get(self.prefix_cache_n)
+            .await
+    }
+}
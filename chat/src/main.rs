@@ -3,16 +3,23 @@ use gpui::{
     WindowBounds, WindowOptions, actions, div, prelude::*, px, rgb, size,
 };
 use gpui_component::{
+    button::{Button, ButtonVariants},
     h_flex,
+    input::{Input, InputEvent, InputState},
     select::{Select, SelectEvent, SelectState},
     v_flex,
 };
+use std::sync::Arc;
+use tokio::sync::{OnceCell, oneshot};
 
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 struct ConnectionInfo {
     workspace_name: String,
     connection_name: String,
     url: String,
+    connection: Arc<config::DatabaseConnection>,
+    /// Connected on the first query, then reused by the ones after it
+    database: Arc<OnceCell<Box<dyn db::Database>>>,
 }
 
 impl ConnectionInfo {
@@ -20,32 +27,64 @@ impl ConnectionInfo {
         format!("[{}] {}", self.workspace_name, self.connection_name)
     }
 
+    /// The configured timeouts, e.g. "statement timeout 30s, lock timeout 5s"
+    fn timeouts(&self) -> Option<String> {
+        let timeouts = [
+            ("statement timeout", self.connection.statement_timeout_secs),
+            ("lock timeout", self.connection.lock_timeout_secs),
+        ]
+        .into_iter()
+        .filter_map(|(name, secs)| secs.map(|secs| format!("{name} {secs}s")))
+        .collect::<Vec<_>>();
+
+        (!timeouts.is_empty()).then(|| timeouts.join(", "))
+    }
+
     fn connection_url(&self) -> &str {
         &self.url
     }
 }
 
+/// What the query box is doing
+enum QueryStatus {
+    Idle,
+    /// There is nothing to cancel until the query has been sent
+    Running(Option<db::CancelHandle>),
+    Done(String),
+}
+
 struct ChatWindow {
     connections: Vec<ConnectionInfo>,
     connection_selector: Entity<SelectState<Vec<SharedString>>>,
     selected_index: usize,
+    query_input: Entity<InputState>,
+    query_status: QueryStatus,
+    /// sqlx needs tokio, which gpui's executor isn't
+    runtime: Arc<tokio::runtime::Runtime>,
 }
 
 impl ChatWindow {
-    fn new(window: &mut Window, cx: &mut Context<Self>) -> Self {
+    fn new(
+        runtime: Arc<tokio::runtime::Runtime>,
+        window: &mut Window,
+        cx: &mut Context<Self>,
+    ) -> Self {
         let conf = config::PeekConfig::get_or_default();
 
         let connections: Vec<ConnectionInfo> = conf
             .workspaces
-            .iter()
+            .into_iter()
             .flat_map(|workspace| {
+                let workspace_name = workspace.name;
                 workspace
                     .connections
-                    .iter()
+                    .into_iter()
                     .map(|connection| ConnectionInfo {
-                        workspace_name: workspace.name.clone(),
+                        workspace_name: workspace_name.clone(),
                         connection_name: connection.name.clone(),
                         url: connection.url.clone(),
+                        connection: Arc::new(connection),
+                        database: Arc::default(),
                     })
                     .collect::<Vec<_>>()
             })
@@ -82,10 +121,87 @@ impl ChatWindow {
         )
         .detach();
 
+        let query_input = cx.new(|cx| InputState::new(window, cx).placeholder("SELECT ..."));
+        cx.subscribe_in(&query_input, window, |view, _state, event, _window, cx| {
+            if let InputEvent::PressEnter { .. } = event {
+                view.run_query(cx);
+            }
+        })
+        .detach();
+
         Self {
             connections,
             connection_selector,
             selected_index: 0,
+            query_input,
+            query_status: QueryStatus::Idle,
+            runtime,
+        }
+    }
+
+    /// Run the query in the query box on the current connection, counting the rows it returns
+    fn run_query(&mut self, cx: &mut Context<Self>) {
+        let query = self.query_input.read(cx).value().trim().to_string();
+        let Some(ConnectionInfo {
+            connection,
+            database,
+            ..
+        }) = self.get_current_connection().cloned()
+        else {
+            return;
+        };
+        if query.is_empty() || matches!(self.query_status, QueryStatus::Running(_)) {
+            return;
+        }
+
+        let (sent, cancel_handle) = oneshot::channel();
+        let task = self.runtime.spawn(async move {
+            let database = database
+                .get_or_try_init(|| db::connect(&connection))
+                .await?;
+            let mut stream = database
+                .stream_results(&query, &[], db::StreamLimits::default())
+                .await?;
+            let _ = sent.send(stream.cancel_handle());
+
+            let mut rows = 0;
+            while let Some(row) = stream.next().await {
+                row?;
+                rows += 1;
+            }
+            Ok::<_, db::DbError>(rows)
+        });
+        self.query_status = QueryStatus::Running(None);
+        cx.notify();
+
+        cx.spawn(async move |view, cx| {
+            if let Ok(cancel) = cancel_handle.await {
+                let _ = view.update(cx, |view, cx| {
+                    if let QueryStatus::Running(handle) = &mut view.query_status {
+                        *handle = Some(cancel);
+                        cx.notify();
+                    }
+                });
+            }
+
+            let status = match task.await {
+                Ok(Ok(rows)) => format!("{rows} rows"),
+                Ok(Err(e)) => e.to_string(),
+                Err(e) => e.to_string(),
+            };
+            let _ = view.update(cx, |view, cx| {
+                view.query_status = QueryStatus::Done(status);
+                cx.notify();
+            });
+        })
+        .detach();
+    }
+
+    /// Cancel the running query on the server, which then ends it with an error
+    fn cancel_query(&mut self) {
+        if let QueryStatus::Running(Some(cancel)) = &self.query_status {
+            let cancel = cancel.clone();
+            self.runtime.spawn(async move { cancel.cancel().await });
         }
     }
 
@@ -118,11 +234,48 @@ impl ChatWindow {
     }
 }
 
+impl ChatWindow {
+    fn render_query_box(&mut self, cx: &mut Context<Self>) -> impl IntoElement {
+        let button = match self.query_status {
+            QueryStatus::Running(_) => Button::new("cancel")
+                .label("Cancel")
+                .danger()
+                .on_click(cx.listener(|view, _, _, _| view.cancel_query())),
+            _ => Button::new("run")
+                .label("Run")
+                .primary()
+                .on_click(cx.listener(|view, _, _, cx| view.run_query(cx))),
+        };
+        let status = match &self.query_status {
+            QueryStatus::Idle => None,
+            QueryStatus::Running(_) => Some("Running...".to_string()),
+            QueryStatus::Done(status) => Some(status.clone()),
+        };
+
+        v_flex()
+            .w_full()
+            .p_4()
+            .gap_2()
+            .child(
+                h_flex()
+                    .gap_2()
+                    .child(Input::new(&self.query_input))
+                    .child(button),
+            )
+            .children(
+                status.map(|status| div().text_sm().text_color(rgb(0x0088_8888)).child(status)),
+            )
+    }
+}
+
 impl Render for ChatWindow {
     fn render(&mut self, window: &mut Window, cx: &mut Context<Self>) -> impl IntoElement {
         let display_name = self
             .get_current_connection()
             .map_or_else(|| "No connection".to_string(), ConnectionInfo::display_name);
+        let timeouts = self
+            .get_current_connection()
+            .and_then(ConnectionInfo::timeouts);
 
         v_flex()
             .size_full()
@@ -134,15 +287,27 @@ impl Render for ChatWindow {
                     .p_4()
                     .items_center()
                     .justify_center()
-                    .child(format!("Chat interface for: {display_name}")),
+                    .child(format!("Chat interface for: {display_name}"))
+                    .children(timeouts.map(|timeouts| {
+                        div().text_sm().text_color(rgb(0x0088_8888)).child(timeouts)
+                    })),
             )
+            .child(self.render_query_box(cx))
     }
 }
 
 actions!(window, [Quit]);
 
 fn main() {
-    Application::new().run(|cx: &mut App| {
+    let runtime = match tokio::runtime::Runtime::new() {
+        Ok(runtime) => Arc::new(runtime),
+        Err(e) => {
+            eprintln!("Error starting the async runtime: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    Application::new().run(move |cx: &mut App| {
         // Initialize gpui-component theme system
         gpui_component::init(cx);
 
@@ -158,7 +323,7 @@ fn main() {
                 }),
                 ..Default::default()
             },
-            |window, cx| cx.new(|cx| ChatWindow::new(window, cx)),
+            |window, cx| cx.new(|cx| ChatWindow::new(runtime, window, cx)),
         )
         .unwrap();
 
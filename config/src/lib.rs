@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct PeekConfig {
@@ -8,17 +9,61 @@ pub struct PeekConfig {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AIConfig {
+    /// The default model. Routes that don't name a model, and every fallback, use this one.
     pub model: String,
+    /// Additional models by name, e.g. `sql = "Qwen/Qwen2.5-Coder-1.5B-Instruct"`. The name
+    /// `default` is reserved for `model`.
+    #[serde(default)]
+    pub models: HashMap<String, String>,
+    #[serde(default)]
+    pub routing: RoutingConfig,
+    /// Number of sequences whose KV cache is kept around so that a repeated prefix (the system
+    /// prompt and earlier turns) doesn't have to be processed again. 0 disables prefix caching.
+    #[serde(default = "default_prefix_cache_size")]
+    pub prefix_cache_size: usize,
+}
+
+fn default_prefix_cache_size() -> usize {
+    16
 }
 
 impl Default for AIConfig {
     fn default() -> Self {
         Self {
             model: "cyankiwi/Ministral-3-8B-Instruct-2512-AWQ-4bit".to_string(),
+            models: HashMap::new(),
+            routing: RoutingConfig::default(),
+            prefix_cache_size: default_prefix_cache_size(),
         }
     }
 }
 
+/// Decides which of the configured models answers a given request.
+/// Model names refer to keys in [`AIConfig::models`], or are `default` for the default model.
+/// Any other name is reported as a configuration error.
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct RoutingConfig {
+    /// Model per step type
+    #[serde(default)]
+    pub steps: HashMap<Step, String>,
+    /// Model that continues the conversation after a given tool has returned,
+    /// takes precedence over `steps`
+    #[serde(default)]
+    pub tools: HashMap<String, String>,
+    /// Use the default model once this many attempts have failed within a turn
+    pub fallback_after: Option<usize>,
+}
+
+/// The kind of request that is sent to the model
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Step {
+    /// Answering a new prompt from the user
+    Prompt,
+    /// Continuing after a tool result has been added to the conversation
+    ToolResult,
+}
+
 impl PeekConfig {
     pub fn get_or_default() -> Self {
         let Ok(home_dir) = std::env::var("HOME") else {
@@ -46,13 +91,124 @@ pub struct DatabaseConnection {
     pub color: String,
     pub url: String,
     pub ssh: Option<SSHConfig>,
+    #[serde(default)]
+    pub tls: TlsConfig,
+    #[serde(default)]
+    pub pool: PoolConfig,
+    #[serde(default)]
+    pub schemas: SchemaFilter,
+    /// Abort statements that run longer than this many seconds. Not supported by SQLite.
+    pub statement_timeout_secs: Option<u64>,
+    /// Give up waiting for a lock after this many seconds
+    pub lock_timeout_secs: Option<u64>,
+    #[serde(default)]
+    pub access: Access,
 }
 
-#[derive(Debug, Serialize, Deserialize, Default)]
+/// Whether a connection may change data. Read-only is enforced by the database itself, not by
+/// looking at the statements.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Access {
+    ReadOnly,
+    #[default]
+    ReadWrite,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PoolConfig {
+    /// Maximum number of connections opened to the database
+    #[serde(default = "default_max_connections")]
+    pub max_connections: u32,
+    /// Close connections that have been idle for this many seconds, 0 keeps them open
+    #[serde(default = "default_idle_timeout_secs")]
+    pub idle_timeout_secs: u64,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            max_connections: default_max_connections(),
+            idle_timeout_secs: default_idle_timeout_secs(),
+        }
+    }
+}
+
+fn default_max_connections() -> u32 {
+    5
+}
+
+fn default_idle_timeout_secs() -> u64 {
+    600
+}
+
+/// Which Postgres schemas are introspected. System schemas are always left out.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct SchemaFilter {
+    /// Only introspect these schemas, all of them if empty
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// Never introspect these schemas
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+/// An SSH server the database is reached through, e.g. a bastion host. The database's host and
+/// port in the connection URL are then as seen from the SSH server.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct SSHConfig {
     pub host: String,
+    #[serde(default = "default_ssh_port")]
     pub port: u16,
     pub username: String,
+    /// Used when there is no key, or the server doesn't accept it
     pub password: Option<String>,
+    /// Path of a private key file, e.g. `~/.ssh/id_ed25519`
     pub ssh_key: Option<String>,
+    /// Passphrase the key file is encrypted with
+    pub ssh_key_passphrase: Option<String>,
+    /// File with the host keys the server's key is checked against, `~/.ssh/known_hosts` by
+    /// default
+    pub known_hosts: Option<String>,
+}
+
+fn default_ssh_port() -> u16 {
+    22
+}
+
+/// How the connection to the database is encrypted. These take precedence over `sslmode` and
+/// the like in the URL. Paths may start with `~/`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct TlsConfig {
+    /// Left to the URL if not set, which defaults to `prefer`
+    pub mode: Option<TlsMode>,
+    /// PEM file with the CA certificates the server's certificate is checked against, in
+    /// addition to the common web CAs
+    pub root_cert: Option<String>,
+    /// PEM file with a certificate to authenticate with, needs `client_key`
+    pub client_cert: Option<String>,
+    /// PEM file with the private key of `client_cert`
+    pub client_key: Option<String>,
+    /// Name the server's certificate is checked against with `verify_full`, instead of the host
+    /// in the URL. Only supported by Postgres.
+    pub server_name: Option<String>,
+}
+
+/// Whether TLS is used, and what is verified. Named like Postgres' `sslmode`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TlsMode {
+    /// Never use TLS
+    Disable,
+    /// Treated like `disable`, the driver doesn't fall back from plain connections
+    Allow,
+    /// Use TLS if the server supports it, without checking its certificate
+    Prefer,
+    /// Always use TLS, without checking the server's certificate
+    Require,
+    /// Check that the server's certificate is signed by a trusted CA. The driver currently
+    /// checks its name as well, set `server_name` if it isn't the host in the URL.
+    VerifyCa,
+    /// Also check that the certificate is for the server's name
+    VerifyFull,
 }
@@ -0,0 +1,119 @@
+//! Schemas kept on disk between runs, so that starting up doesn't have to introspect the
+//! database again while its schema hasn't changed.
+
+use crate::{Database, DbError, Schema};
+use config::DatabaseConnection;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+
+/// A directory of cached schemas, one file per connection.
+///
+/// A cached schema is only used while the database's [`Database::schema_fingerprint`] is the
+/// same as when it was saved. Databases without a fingerprint are always introspected.
+pub struct SchemaCache {
+    dir: PathBuf,
+}
+
+/// What is saved for a connection
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    /// Version of peek that saved the schema, the format can change between versions
+    version: String,
+    fingerprint: String,
+    schema: Schema,
+}
+
+impl SchemaCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// The cache in the user's cache directory, `$XDG_CACHE_HOME/peek/schemas` or
+    /// `~/.cache/peek/schemas`
+    pub fn user() -> Option<Self> {
+        let cache_home = match std::env::var("XDG_CACHE_HOME") {
+            Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(std::env::var("HOME").ok()?).join(".cache"),
+        };
+        Some(Self::new(cache_home.join("peek").join("schemas")))
+    }
+
+    /// The schema of the connection's database, from the cache while it's still current
+    pub async fn get_schema(
+        &self,
+        database: &dyn Database,
+        connection: &DatabaseConnection,
+    ) -> Result<Schema, DbError> {
+        let Some(fingerprint) = database.schema_fingerprint().await? else {
+            return database.get_schema().await;
+        };
+        match self.load(connection) {
+            Some(entry) if entry.fingerprint == fingerprint => Ok(entry.schema),
+            _ => self.introspect(database, connection, fingerprint).await,
+        }
+    }
+
+    /// Introspect the database again and replace its cached schema
+    pub async fn refresh(
+        &self,
+        database: &dyn Database,
+        connection: &DatabaseConnection,
+    ) -> Result<Schema, DbError> {
+        match database.schema_fingerprint().await? {
+            Some(fingerprint) => self.introspect(database, connection, fingerprint).await,
+            None => database.get_schema().await,
+        }
+    }
+
+    /// The fingerprint is taken before introspecting, so a change made in between makes the
+    /// saved schema stale on the next run rather than being missed.
+    async fn introspect(
+        &self,
+        database: &dyn Database,
+        connection: &DatabaseConnection,
+        fingerprint: String,
+    ) -> Result<Schema, DbError> {
+        let entry = Entry {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            fingerprint,
+            schema: database.get_schema().await?,
+        };
+        // The cache only saves time, not being able to write it isn't worth failing over
+        let _ = self.save(connection, &entry);
+        Ok(entry.schema)
+    }
+
+    fn load(&self, connection: &DatabaseConnection) -> Option<Entry> {
+        let json = std::fs::read(self.path(connection)).ok()?;
+        let entry: Entry = serde_json::from_slice(&json).ok()?;
+        (entry.version == env!("CARGO_PKG_VERSION")).then_some(entry)
+    }
+
+    fn save(&self, connection: &DatabaseConnection, entry: &Entry) -> std::io::Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        let path = self.path(connection);
+        // Written next to the cached schema and moved over it, so that another peek reading it
+        // never sees half a file
+        let partial = path.with_extension(format!("json.{}", std::process::id()));
+        std::fs::write(&partial, serde_json::to_vec(entry)?)?;
+        std::fs::rename(&partial, &path)
+    }
+
+    /// Connections are told apart by everything that decides which schema they see. The key is
+    /// hashed since the URL can contain a password.
+    fn path(&self, connection: &DatabaseConnection) -> PathBuf {
+        let mut key = Sha256::new();
+        key.update(&connection.url);
+        if let Some(ssh) = &connection.ssh {
+            key.update(format!("\0{}@{}:{}", ssh.username, ssh.host, ssh.port));
+        }
+        for schema in &connection.schemas.include {
+            key.update(format!("\0+{schema}"));
+        }
+        for schema in &connection.schemas.exclude {
+            key.update(format!("\0-{schema}"));
+        }
+        self.dir.join(format!("{:x}.json", key.finalize()))
+    }
+}
@@ -0,0 +1,176 @@
+use sqlx::postgres::{PgDatabaseError, PgErrorPosition};
+use std::fmt::Display;
+
+/// Errors from setting up or using a database connection
+#[derive(Debug)]
+pub enum DbError {
+    /// The connection URL has a scheme that no backend handles
+    UnsupportedScheme(String),
+    /// The connection settings are invalid, e.g. a malformed URL
+    Configuration(String),
+    /// The database couldn't be reached, or the connection was lost
+    Connection(String),
+    /// The database rejected the credentials
+    Auth(ServerError),
+    /// The TLS handshake failed
+    Tls(String),
+    /// The SSH tunnel to the database couldn't be opened
+    Ssh(String),
+    /// The database reported an error for a statement
+    Server(ServerError),
+    /// A value from the database couldn't be decoded
+    Decode(String),
+    /// Anything else
+    Other(String),
+}
+
+/// An error reported by the database server, with everything it told us about it
+#[derive(Debug, Clone, Default)]
+pub struct ServerError {
+    /// The SQLSTATE code, e.g. `42P01` for an undefined table
+    pub code: Option<String>,
+    pub message: String,
+    pub detail: Option<String>,
+    pub hint: Option<String>,
+    /// Character offset into the statement where the error was detected, starting at 1
+    pub position: Option<usize>,
+}
+
+impl DbError {
+    /// The server error, if this error came from the server
+    pub fn server_error(&self) -> Option<&ServerError> {
+        match self {
+            Self::Auth(error) | Self::Server(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<sqlx::Error> for DbError {
+    fn from(error: sqlx::Error) -> Self {
+        match error {
+            sqlx::Error::Database(error) => {
+                let mut server_error = ServerError {
+                    code: error.code().map(|code| code.to_string()),
+                    message: error.message().to_string(),
+                    ..ServerError::default()
+                };
+
+                if let Some(pg_error) = error.try_downcast_ref::<PgDatabaseError>() {
+                    server_error.detail = pg_error.detail().map(str::to_string);
+                    server_error.hint = pg_error.hint().map(str::to_string);
+                    server_error.position = pg_error.position().map(|position| match position {
+                        PgErrorPosition::Original(position)
+                        | PgErrorPosition::Internal { position, .. } => position,
+                    });
+                }
+
+                // SQLSTATE class 28 is "invalid authorization specification"
+                if server_error
+                    .code
+                    .as_deref()
+                    .is_some_and(|code| code.starts_with("28"))
+                {
+                    Self::Auth(server_error)
+                } else {
+                    Self::Server(server_error)
+                }
+            }
+            sqlx::Error::Configuration(error) => Self::Configuration(error.to_string()),
+            sqlx::Error::Tls(error) => Self::Tls(error.to_string()),
+            sqlx::Error::Io(error) => match error
+                .get_ref()
+                .and_then(|inner| inner.downcast_ref::<rustls::Error>())
+            {
+                Some(error) => Self::Tls(tls_message(error)),
+                None => Self::Connection(error.to_string()),
+            },
+            sqlx::Error::PoolTimedOut => {
+                Self::Connection("timed out waiting for a connection".to_string())
+            }
+            sqlx::Error::PoolClosed => {
+                Self::Connection("the connection pool is closed".to_string())
+            }
+            sqlx::Error::ColumnDecode { .. } | sqlx::Error::Decode(_) => {
+                Self::Decode(error.to_string())
+            }
+            error => Self::Other(error.to_string()),
+        }
+    }
+}
+
+/// What went wrong in the handshake, and which setting likely fixes it
+fn tls_message(error: &rustls::Error) -> String {
+    use rustls::{AlertDescription, CertificateError};
+
+    match error {
+        rustls::Error::InvalidCertificate(CertificateError::UnknownIssuer) => format!(
+            "{error}, the server's certificate isn't signed by a known CA. \
+             Set tls.root_cert to the certificate of the CA that signed it."
+        ),
+        rustls::Error::InvalidCertificate(
+            CertificateError::NotValidForName | CertificateError::NotValidForNameContext { .. },
+        ) => format!(
+            "{error}. Connect with one of the names the certificate is for, or set \
+             tls.server_name to it."
+        ),
+        rustls::Error::AlertReceived(
+            AlertDescription::CertificateRequired | AlertDescription::BadCertificate,
+        ) => format!(
+            "{error}, the server didn't accept the client certificate. Check tls.client_cert \
+             and tls.client_key."
+        ),
+        error => error.to_string(),
+    }
+}
+
+impl From<russh::Error> for DbError {
+    fn from(error: russh::Error) -> Self {
+        Self::Ssh(error.to_string())
+    }
+}
+
+impl From<russh::keys::Error> for DbError {
+    fn from(error: russh::keys::Error) -> Self {
+        Self::Ssh(error.to_string())
+    }
+}
+
+impl Display for ServerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.code {
+            Some(code) => write!(f, "ERROR {code}: {}", self.message)?,
+            None => write!(f, "ERROR: {}", self.message)?,
+        }
+        if let Some(detail) = &self.detail {
+            write!(f, "\nDETAIL: {detail}")?;
+        }
+        if let Some(hint) = &self.hint {
+            write!(f, "\nHINT: {hint}")?;
+        }
+        if let Some(position) = self.position {
+            write!(f, "\nPOSITION: {position}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for DbError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnsupportedScheme(scheme) => {
+                write!(f, "Unsupported database URL scheme \"{scheme}\"")
+            }
+            Self::Configuration(message) => write!(f, "Invalid connection settings: {message}"),
+            Self::Connection(message) => write!(f, "Could not connect to the database: {message}"),
+            Self::Auth(error) => write!(f, "Authentication failed: {error}"),
+            Self::Tls(message) => write!(f, "TLS handshake failed: {message}"),
+            Self::Ssh(message) => write!(f, "SSH tunnel failed: {message}"),
+            Self::Server(error) => write!(f, "{error}"),
+            Self::Decode(message) => write!(f, "Could not decode value: {message}"),
+            Self::Other(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+impl std::error::Error for DbError {}
@@ -1,36 +1,396 @@
+mod cache;
+mod error;
+pub mod mysql;
+mod param;
 pub mod postgres;
+mod relay;
+mod schema;
+mod script;
+pub mod sqlite;
+mod ssh;
+mod statement;
+mod stream;
+mod tls;
+mod transaction;
+
+use crate::transaction::check_access_mode;
 
 use async_trait::async_trait;
+use config::{DatabaseConnection, PoolConfig};
 use serde_json::Value;
-use std::collections::HashMap;
+use sqlx::pool::PoolOptions;
+use sqlx::{Column as _, ConnectOptions, Connection, Either, Pool, Statement, TypeInfo};
+use std::fmt::{Display, Write};
+use std::ops::{Deref, DerefMut};
+use std::path::PathBuf;
+use std::time::Duration;
+
+pub use cache::SchemaCache;
+pub use error::{DbError, ServerError};
+pub use param::Param;
+pub use schema::{
+    Change, Column, ColumnDiff, CustomType, CustomTypeKind, ForeignKey, Index, RelationKind,
+    Schema, SchemaDiff, Table, TableDiff, UniqueConstraint,
+};
+pub use script::{
+    ScriptOptions, ScriptOutcome, ScriptStatement, StatementOutcome, split_statements,
+};
+pub use stream::{CancelHandle, Limit, RowStream, StreamLimits};
+pub use transaction::{Changes, can_preview, is_read_only, modifies_rows};
+
+/// Open a connection with the backend that matches the scheme of the connection's URL. The
+/// backend gets the whole connection, so that it can apply the connection's options.
+pub async fn connect(connection: &DatabaseConnection) -> Result<Box<dyn Database>, DbError> {
+    let database: Box<dyn Database> = match Backend::from_url(&connection.url)? {
+        Backend::Postgres => Box::new(postgres::PostgresDatabase::new(connection).await?),
+        Backend::MySql => Box::new(mysql::MySqlDatabase::new(connection).await?),
+        Backend::Sqlite => Box::new(sqlite::SqliteDatabase::new(connection).await?),
+    };
+
+    Ok(database)
+}
+
+/// The kinds of databases peek can connect to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Postgres,
+    MySql,
+    Sqlite,
+}
+
+impl Backend {
+    /// Pick the backend from the scheme of a connection URL, e.g. `postgres://` or `sqlite:`
+    pub fn from_url(url: &str) -> Result<Self, DbError> {
+        let scheme = url.split(':').next().unwrap_or_default();
+
+        match scheme {
+            "postgres" | "postgresql" => Ok(Self::Postgres),
+            "mysql" | "mariadb" => Ok(Self::MySql),
+            "sqlite" => Ok(Self::Sqlite),
+            _ => Err(DbError::UnsupportedScheme(scheme.to_string())),
+        }
+    }
+}
+
+impl Display for Backend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Postgres => write!(f, "postgres"),
+            Self::MySql => write!(f, "mysql"),
+            Self::Sqlite => write!(f, "sqlite"),
+        }
+    }
+}
+
+/// Open a connection pool.
+///
+/// The pool retries failing connections until it times out, which would hide why the database
+/// couldn't be reached, so one connection is opened directly first to surface that error.
+pub(crate) async fn connect_pool<DB: sqlx::Database>(
+    pool_options: PoolOptions<DB>,
+    options: <DB::Connection as sqlx::Connection>::Options,
+) -> Result<Pool<DB>, DbError> {
+    options.connect().await?.close().await?;
+    Ok(pool_options.connect_lazy_with(options))
+}
+
+/// Paths in the config may start with `~/`
+pub(crate) fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), std::env::var("HOME")) {
+        (Some(rest), Ok(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// Pool settings shared by all backends
+pub(crate) fn pool_options<DB: sqlx::Database>(conf: &PoolConfig) -> PoolOptions<DB> {
+    PoolOptions::new()
+        .max_connections(conf.max_connections.max(1))
+        .idle_timeout(
+            (conf.idle_timeout_secs > 0).then(|| Duration::from_secs(conf.idle_timeout_secs)),
+        )
+}
 
-/// Trait defining the interface for database operations
+/// A connection that runs a statement in a read-only transaction of its own on read-only
+/// connections.
+///
+/// Setting the session default isn't enough: a statement could turn it off, or end the
+/// transaction itself (e.g. `COMMIT` in a `DO` block) and write in the next one. Within an
+/// explicit read-only transaction neither is possible.
+pub(crate) enum Guarded<'c, DB: sqlx::Database> {
+    ReadWrite(&'c mut DB::Connection),
+    ReadOnly(sqlx::Transaction<'c, DB>),
+}
+
+impl<'c, DB: sqlx::Database> Guarded<'c, DB> {
+    /// Start the read-only transaction with `begin_read_only`, if given, after checking that
+    /// `query` doesn't change the access mode
+    pub(crate) async fn begin(
+        connection: &'c mut DB::Connection,
+        begin_read_only: Option<&'static str>,
+        query: &str,
+    ) -> Result<Self, DbError> {
+        match begin_read_only {
+            Some(statement) => {
+                check_access_mode(query)?;
+                Ok(Self::ReadOnly(connection.begin_with(statement).await?))
+            }
+            None => Ok(Self::ReadWrite(connection)),
+        }
+    }
+
+    /// End the read-only transaction. Dropping the guard instead rolls it back.
+    pub(crate) async fn commit(self) -> Result<(), DbError> {
+        if let Self::ReadOnly(transaction) = self {
+            transaction.commit().await?;
+        }
+        Ok(())
+    }
+}
+
+impl<DB: sqlx::Database> Deref for Guarded<'_, DB> {
+    type Target = DB::Connection;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Self::ReadWrite(connection) => connection,
+            Self::ReadOnly(transaction) => transaction,
+        }
+    }
+}
+
+impl<DB: sqlx::Database> DerefMut for Guarded<'_, DB> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        match self {
+            Self::ReadWrite(connection) => connection,
+            Self::ReadOnly(transaction) => transaction,
+        }
+    }
+}
+
+/// Trait defining the interface for database operations.
+///
+/// Implementations are backed by a connection pool, so a single instance can be shared and
+/// queried concurrently. Every call may run on a different connection; use a [`Session`] for
+/// anything that relies on connection state.
 #[async_trait]
 pub trait Database: Send + Sync {
+    /// The kind of database this is connected to
+    fn backend(&self) -> Backend;
+
     /// Execute a query and return results as JSON
     /// The format will be a vector of tuples, where the tuple is in the format of
     /// [column_name, value, column_type]
-    async fn get_results(&mut self, query: &str) -> Result<DatabaseResult, String>;
+    async fn get_results(&self, query: &str) -> Result<DatabaseResult, DbError> {
+        self.get_results_with(query, &[]).await
+    }
+
+    /// Same as [`Database::get_results`], with a param for each `$1`/`?` placeholder
+    async fn get_results_with(
+        &self,
+        query: &str,
+        params: &[Param],
+    ) -> Result<DatabaseResult, DbError>;
+
+    /// Run a query and take its rows as they arrive instead of all at once, stopping at the
+    /// given limits. The query can be stopped on the server with [`RowStream::cancel_handle`].
+    async fn stream_results(
+        &self,
+        query: &str,
+        params: &[Param],
+        limits: StreamLimits,
+    ) -> Result<RowStream, DbError>;
+
+    /// Prepare a query without running it, to find out which parameters it takes and which
+    /// columns it returns
+    async fn describe(&self, query: &str) -> Result<QueryDescription, DbError>;
+
+    /// Execute an sql statement and report what it did
+    async fn execute(&self, query: &str) -> Result<ExecuteOutcome, DbError> {
+        self.execute_with(query, &[]).await
+    }
+
+    /// Same as [`Database::execute`], with a param for each `$1`/`?` placeholder
+    async fn execute_with(&self, query: &str, params: &[Param]) -> Result<ExecuteOutcome, DbError>;
+
+    /// Take a dedicated connection from the pool, for transactions, `SET`, temporary tables and
+    /// anything else that has to run on the same connection
+    async fn session(&self) -> Result<Box<dyn Session>, DbError>;
 
-    /// Execute an sql statement and return whatever the statement returns
-    async fn execute(&mut self, query: &str) -> Result<String, String>;
+    /// Start a transaction on a dedicated connection
+    async fn begin(&self) -> Result<Box<dyn Transaction>, DbError>;
 
-    /// Get the database schema information
-    /// Returns a list of all tables and their columns as well as a list of all references
-    /// from each column to each table.column as map, where the key is the column.
-    async fn get_schema(
+    /// Run an INSERT, UPDATE, DELETE or MERGE in a transaction, collect what it changed and roll
+    /// it back again. Other statements are refused, see [`can_preview`].
+    async fn dry_run(&self, query: &str, params: &[Param]) -> Result<Changes, DbError> {
+        let mut transaction = self.begin().await?;
+        let changes = transaction.preview(query, params).await?;
+        transaction.rollback().await?;
+        Ok(changes)
+    }
+
+    /// Split a script into its statements and run them one after the other on a connection of
+    /// their own, see [`split_statements`]
+    async fn run_script(
+        &self,
+        script: &str,
+        options: ScriptOptions,
+    ) -> Result<ScriptOutcome, DbError> {
+        script::run_script(self, script, options).await
+    }
+
+    /// Get the tables and views of the database with their columns, keys, indexes and
+    /// references, along with user-defined types
+    async fn get_schema(&self) -> Result<Schema, DbError>;
+
+    /// A value that is cheap to get and changes whenever the schema may have, which tells
+    /// whether a [`SchemaCache`] entry is still current. `None` when the backend has no such
+    /// value, its schema is then always introspected.
+    async fn schema_fingerprint(&self) -> Result<Option<String>, DbError> {
+        Ok(None)
+    }
+}
+
+/// A single pooled connection that stays checked out until it is dropped
+#[async_trait]
+pub trait Session: Send {
+    /// Same as [`Database::get_results`], on this session's connection
+    async fn get_results(&mut self, query: &str) -> Result<DatabaseResult, DbError> {
+        self.get_results_with(query, &[]).await
+    }
+
+    /// Same as [`Database::get_results_with`], on this session's connection
+    async fn get_results_with(
+        &mut self,
+        query: &str,
+        params: &[Param],
+    ) -> Result<DatabaseResult, DbError>;
+
+    /// Same as [`Database::describe`], on this session's connection
+    async fn describe(&mut self, query: &str) -> Result<QueryDescription, DbError>;
+
+    /// Same as [`Database::execute`], on this session's connection
+    async fn execute(&mut self, query: &str) -> Result<ExecuteOutcome, DbError> {
+        self.execute_with(query, &[]).await
+    }
+
+    /// Same as [`Database::execute_with`], on this session's connection
+    async fn execute_with(
         &mut self,
-    ) -> Result<
-        (
-            HashMap<String, Vec<(String, String)>>,
-            HashMap<String, Vec<String>>,
-        ),
-        String,
-    >;
+        query: &str,
+        params: &[Param],
+    ) -> Result<ExecuteOutcome, DbError>;
+}
+
+/// A transaction that is rolled back when it is dropped without being committed.
+///
+/// On MySQL, DDL statements such as `CREATE TABLE` commit the transaction implicitly.
+#[async_trait]
+pub trait Transaction: Send {
+    /// Same as [`Database::get_results`], within the transaction
+    async fn get_results(&mut self, query: &str) -> Result<DatabaseResult, DbError> {
+        self.get_results_with(query, &[]).await
+    }
+
+    /// Same as [`Database::get_results_with`], within the transaction
+    async fn get_results_with(
+        &mut self,
+        query: &str,
+        params: &[Param],
+    ) -> Result<DatabaseResult, DbError>;
+
+    /// Same as [`Database::execute`], within the transaction
+    async fn execute(&mut self, query: &str) -> Result<ExecuteOutcome, DbError> {
+        self.execute_with(query, &[]).await
+    }
+
+    /// Same as [`Database::execute_with`], within the transaction
+    async fn execute_with(
+        &mut self,
+        query: &str,
+        params: &[Param],
+    ) -> Result<ExecuteOutcome, DbError>;
+
+    /// Run a data-changing statement and return the number of rows it changed along with the
+    /// first of them, as they are now. The changes stay until the transaction is ended.
+    async fn preview(&mut self, query: &str, params: &[Param]) -> Result<Changes, DbError>;
+
+    async fn commit(self: Box<Self>) -> Result<(), DbError>;
+
+    async fn rollback(self: Box<Self>) -> Result<(), DbError>;
 }
 
 #[derive(Debug)]
 pub struct DatabaseResult {
+    /// Column names and types, also when there are no rows
     pub headers: Vec<(String, String)>,
     pub rows: Vec<Vec<Value>>,
 }
+
+/// What a statement run with [`Database::execute`] did
+#[derive(Debug, Clone)]
+pub struct ExecuteOutcome {
+    /// In the form Postgres reports it, e.g. `UPDATE 3412` or `CREATE TABLE`. It is derived
+    /// from the statement, so it may not match the server's tag for every statement.
+    pub command_tag: String,
+    pub rows_affected: u64,
+    /// Notices and warnings the server sent while running the statement (Postgres and MySQL)
+    pub notices: Vec<String>,
+    pub elapsed: Duration,
+}
+
+impl Display for ExecuteOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({} ms)", self.command_tag, self.elapsed.as_millis())?;
+        for notice in &self.notices {
+            write!(f, "\n{notice}")?;
+        }
+        Ok(())
+    }
+}
+
+/// What a prepared statement takes and returns
+#[derive(Debug)]
+pub struct QueryDescription {
+    /// Types of the `$1`/`?` parameters, `None` where the database doesn't say (SQLite)
+    pub parameters: Vec<Option<String>>,
+    /// Names and types of the result columns, empty for statements that return no rows
+    pub columns: Vec<(String, String)>,
+}
+
+impl QueryDescription {
+    pub(crate) fn new<'q, S: Statement<'q>>(statement: &S) -> Self {
+        let parameters = match statement.parameters() {
+            Some(Either::Left(types)) => types
+                .iter()
+                .map(|type_info| Some(type_info.name().to_string()))
+                .collect(),
+            Some(Either::Right(count)) => vec![None; count],
+            None => vec![],
+        };
+
+        let columns = statement
+            .columns()
+            .iter()
+            .map(|column| {
+                (
+                    column.name().to_string(),
+                    column.type_info().name().to_string(),
+                )
+            })
+            .collect();
+
+        Self {
+            parameters,
+            columns,
+        }
+    }
+}
+
+/// Format binary data the way Postgres prints `bytea`, e.g. `\x0aff`
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::from("\\x"), |mut hex, byte| {
+        let _ = write!(hex, "{byte:02x}");
+        hex
+    })
+}
@@ -0,0 +1,565 @@
+use crate::param::{BindParam, bind_params, check_params};
+use crate::relay::Tunnel;
+use crate::schema::{
+    Column, ForeignKey, Index, RelationKind, Schema, SchemaBuilder, UniqueConstraint,
+};
+use crate::ssh::tunnel;
+use crate::statement::command_tag;
+use crate::stream::{CancelHandle, stream_results};
+use crate::transaction::{Changes, check_statement, preview};
+use crate::{
+    Backend, DatabaseResult, DbError, ExecuteOutcome, Guarded, Param, QueryDescription, RowStream,
+    StreamLimits, connect_pool, hex, pool_options, tls,
+};
+
+use super::{Database, Session, Transaction};
+use config::{Access, DatabaseConnection};
+use serde_json::{Value, json};
+use sqlx::mysql::types::MySqlTime;
+use sqlx::mysql::{MySqlArguments, MySqlQueryResult, MySqlRow, MySqlTypeInfo};
+use sqlx::pool::PoolConnection;
+use sqlx::query::Query;
+use sqlx::{Column as _, Executor, MySql, MySqlConnection, MySqlPool, Row, TypeInfo, ValueRef};
+use std::time::Instant;
+
+pub struct MySqlDatabase {
+    pool: MySqlPool,
+    read_only: bool,
+    /// Open for as long as the pool may connect through it
+    _tunnel: Option<Tunnel>,
+}
+
+const BEGIN_READ_ONLY: &str = "START TRANSACTION READ ONLY";
+
+/// A single connection taken from the pool for as long as the session lives
+pub struct MySqlSession {
+    connection: PoolConnection<MySql>,
+    read_only: bool,
+}
+
+/// A transaction on a connection of its own, rolled back when dropped
+pub struct MySqlTransaction {
+    transaction: sqlx::Transaction<'static, MySql>,
+    read_only: bool,
+}
+
+impl MySqlDatabase {
+    pub async fn new(connection: &DatabaseConnection) -> Result<Self, DbError> {
+        let (tunnel, url) = tunnel(connection, 3306).await?;
+        let options = tls::mysql(url.parse()?, &connection.tls)?;
+
+        // There are no connect options for session variables, so they're set on every new
+        // connection instead
+        let statement_timeout = connection.statement_timeout_secs;
+        let lock_timeout = connection.lock_timeout_secs;
+        let read_only = connection.access == Access::ReadOnly;
+        let pool_options = pool_options(&connection.pool).after_connect(move |connection, _| {
+            Box::pin(set_session_variables(
+                connection,
+                statement_timeout,
+                lock_timeout,
+                read_only,
+            ))
+        });
+
+        let pool = connect_pool(pool_options, options).await?;
+        Ok(Self {
+            pool,
+            read_only,
+            _tunnel: tunnel,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl Database for MySqlDatabase {
+    fn backend(&self) -> Backend {
+        Backend::MySql
+    }
+
+    async fn get_results_with(
+        &self,
+        query: &str,
+        params: &[Param],
+    ) -> Result<DatabaseResult, DbError> {
+        let mut connection = self.pool.acquire().await?;
+        let mut connection = Guarded::<MySql>::begin(
+            &mut connection,
+            self.read_only.then_some(BEGIN_READ_ONLY),
+            query,
+        )
+        .await?;
+        let results = get_results(&mut connection, query, params).await?;
+        connection.commit().await?;
+        Ok(results)
+    }
+
+    async fn stream_results(
+        &self,
+        query: &str,
+        params: &[Param],
+        limits: StreamLimits,
+    ) -> Result<RowStream, DbError> {
+        let mut connection = self.pool.acquire().await?;
+        let connection_id = sqlx::query_scalar("SELECT CONNECTION_ID()")
+            .fetch_one(&mut *connection)
+            .await?;
+        let cancel = CancelHandle::mysql(self.pool.clone(), connection_id);
+        let begin_read_only = self.read_only.then_some(BEGIN_READ_ONLY);
+        stream_results(
+            connection,
+            query,
+            params.to_vec(),
+            limits,
+            decode_row,
+            cancel,
+            begin_read_only,
+        )
+        .await
+    }
+
+    async fn describe(&self, query: &str) -> Result<QueryDescription, DbError> {
+        describe(&self.pool, query).await
+    }
+
+    async fn execute_with(&self, query: &str, params: &[Param]) -> Result<ExecuteOutcome, DbError> {
+        let mut connection = self.pool.acquire().await?;
+        let mut connection = Guarded::<MySql>::begin(
+            &mut connection,
+            self.read_only.then_some(BEGIN_READ_ONLY),
+            query,
+        )
+        .await?;
+        let result = execute(&mut connection, query, params).await?;
+        connection.commit().await?;
+        Ok(result)
+    }
+
+    async fn session(&self) -> Result<Box<dyn Session>, DbError> {
+        let connection = self.pool.acquire().await?;
+        Ok(Box::new(MySqlSession {
+            connection,
+            read_only: self.read_only,
+        }))
+    }
+
+    async fn begin(&self) -> Result<Box<dyn Transaction>, DbError> {
+        let transaction = if self.read_only {
+            self.pool.begin_with(BEGIN_READ_ONLY).await?
+        } else {
+            self.pool.begin().await?
+        };
+        Ok(Box::new(MySqlTransaction {
+            transaction,
+            read_only: self.read_only,
+        }))
+    }
+
+    async fn get_schema(&self) -> Result<Schema, DbError> {
+        let mut schema = SchemaBuilder::default();
+
+        // information_schema columns can come back with a binary collation, hence the casts.
+        // COLUMN_TYPE includes the labels of ENUM and SET columns as well as UNSIGNED.
+        let columns = sqlx::query(
+            r#"SELECT
+                CAST(c.TABLE_NAME AS CHAR) AS table_name,
+                CAST(t.TABLE_TYPE AS CHAR) AS table_type,
+                CAST(NULLIF(t.TABLE_COMMENT, '') AS CHAR) AS table_comment,
+                CAST(c.COLUMN_NAME AS CHAR) AS column_name,
+                CAST(c.COLUMN_TYPE AS CHAR) AS column_type,
+                c.IS_NULLABLE = 'YES' AS nullable,
+                CAST(c.COLUMN_DEFAULT AS CHAR) AS column_default,
+                CAST(NULLIF(c.COLUMN_COMMENT, '') AS CHAR) AS column_comment
+            FROM information_schema.COLUMNS c
+            JOIN information_schema.TABLES t
+              ON t.TABLE_SCHEMA = c.TABLE_SCHEMA AND t.TABLE_NAME = c.TABLE_NAME
+            WHERE c.TABLE_SCHEMA = DATABASE()
+            ORDER BY c.TABLE_NAME, c.ORDINAL_POSITION;"#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        for row in columns {
+            let table = schema.table(None, row.try_get("table_name")?);
+            if row.try_get::<String, _>("table_type")? == "VIEW" {
+                table.kind = RelationKind::View;
+            }
+            table.comment = row.try_get("table_comment")?;
+            table.columns.push(Column {
+                name: row.try_get("column_name")?,
+                data_type: row.try_get("column_type")?,
+                nullable: row.try_get("nullable")?,
+                default: row.try_get("column_default")?,
+                comment: row.try_get("column_comment")?,
+            });
+        }
+
+        let constraints = sqlx::query(
+            r#"SELECT
+                CAST(k.TABLE_NAME AS CHAR) AS table_name,
+                CAST(k.CONSTRAINT_NAME AS CHAR) AS constraint_name,
+                CAST(tc.CONSTRAINT_TYPE AS CHAR) AS constraint_type,
+                CAST(k.COLUMN_NAME AS CHAR) AS column_name,
+                CAST(k.REFERENCED_TABLE_NAME AS CHAR) AS referenced_table,
+                CAST(k.REFERENCED_COLUMN_NAME AS CHAR) AS referenced_column
+            FROM information_schema.KEY_COLUMN_USAGE k
+            JOIN information_schema.TABLE_CONSTRAINTS tc
+              ON tc.CONSTRAINT_SCHEMA = k.CONSTRAINT_SCHEMA
+             AND tc.TABLE_NAME = k.TABLE_NAME
+             AND tc.CONSTRAINT_NAME = k.CONSTRAINT_NAME
+            WHERE k.TABLE_SCHEMA = DATABASE()
+              AND tc.CONSTRAINT_TYPE IN ('PRIMARY KEY', 'UNIQUE', 'FOREIGN KEY')
+            ORDER BY k.TABLE_NAME, k.CONSTRAINT_NAME, k.ORDINAL_POSITION;"#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        for row in constraints {
+            let Some(table) = schema.existing_table(None, row.try_get("table_name")?) else {
+                continue;
+            };
+            let name: String = row.try_get("constraint_name")?;
+            let column: String = row.try_get("column_name")?;
+
+            // One row per column, so multi-column constraints are collected across rows
+            match row.try_get::<String, _>("constraint_type")?.as_str() {
+                "PRIMARY KEY" => table.primary_key.push(column),
+                "UNIQUE" => match table.unique_constraints.last_mut() {
+                    Some(unique) if unique.name == name => unique.columns.push(column),
+                    _ => table.unique_constraints.push(UniqueConstraint {
+                        name,
+                        columns: vec![column],
+                    }),
+                },
+                _ => {
+                    let referenced_column: String = row.try_get("referenced_column")?;
+                    match table.foreign_keys.last_mut() {
+                        Some(foreign_key) if foreign_key.name.as_ref() == Some(&name) => {
+                            foreign_key.columns.push(column);
+                            foreign_key.referenced_columns.push(referenced_column);
+                        }
+                        _ => table.foreign_keys.push(ForeignKey {
+                            name: Some(name),
+                            columns: vec![column],
+                            referenced_schema: None,
+                            referenced_table: row.try_get("referenced_table")?,
+                            referenced_columns: vec![referenced_column],
+                        }),
+                    }
+                }
+            }
+        }
+
+        // Indexes named after a constraint back that constraint and are already covered.
+        // COLUMN_NAME is NULL for functional key parts, which have an EXPRESSION instead.
+        let indexes = sqlx::query(
+            r#"SELECT
+                CAST(s.TABLE_NAME AS CHAR) AS table_name,
+                CAST(s.INDEX_NAME AS CHAR) AS index_name,
+                s.NON_UNIQUE = 0 AS is_unique,
+                CAST(s.COLUMN_NAME AS CHAR) AS column_name
+            FROM information_schema.STATISTICS s
+            WHERE s.TABLE_SCHEMA = DATABASE()
+              AND NOT EXISTS (
+                  SELECT 1 FROM information_schema.TABLE_CONSTRAINTS tc
+                  WHERE tc.CONSTRAINT_SCHEMA = s.TABLE_SCHEMA
+                    AND tc.TABLE_NAME = s.TABLE_NAME
+                    AND tc.CONSTRAINT_NAME = s.INDEX_NAME
+              )
+            ORDER BY s.TABLE_NAME, s.INDEX_NAME, s.SEQ_IN_INDEX;"#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        for row in indexes {
+            let Some(table) = schema.existing_table(None, row.try_get("table_name")?) else {
+                continue;
+            };
+            let name: String = row.try_get("index_name")?;
+            let column: String = row
+                .try_get::<Option<String>, _>("column_name")?
+                .unwrap_or_else(|| "<expression>".to_string());
+
+            match table.indexes.last_mut() {
+                Some(index) if index.name == name => index.columns.push(column),
+                _ => table.indexes.push(Index {
+                    name,
+                    columns: vec![column],
+                    unique: row.try_get("is_unique")?,
+                }),
+            }
+        }
+
+        Ok(schema.build())
+    }
+}
+
+#[async_trait::async_trait]
+impl Session for MySqlSession {
+    async fn get_results_with(
+        &mut self,
+        query: &str,
+        params: &[Param],
+    ) -> Result<DatabaseResult, DbError> {
+        let begin_read_only = self.read_only.then_some(BEGIN_READ_ONLY);
+        let mut connection =
+            Guarded::<MySql>::begin(&mut self.connection, begin_read_only, query).await?;
+        let results = get_results(&mut connection, query, params).await?;
+        connection.commit().await?;
+        Ok(results)
+    }
+
+    async fn describe(&mut self, query: &str) -> Result<QueryDescription, DbError> {
+        describe(&mut *self.connection, query).await
+    }
+
+    async fn execute_with(
+        &mut self,
+        query: &str,
+        params: &[Param],
+    ) -> Result<ExecuteOutcome, DbError> {
+        let begin_read_only = self.read_only.then_some(BEGIN_READ_ONLY);
+        let mut connection =
+            Guarded::<MySql>::begin(&mut self.connection, begin_read_only, query).await?;
+        let result = execute(&mut connection, query, params).await?;
+        connection.commit().await?;
+        Ok(result)
+    }
+}
+
+#[async_trait::async_trait]
+impl Transaction for MySqlTransaction {
+    async fn get_results_with(
+        &mut self,
+        query: &str,
+        params: &[Param],
+    ) -> Result<DatabaseResult, DbError> {
+        check_statement(query, self.read_only)?;
+        get_results(&mut self.transaction, query, params).await
+    }
+
+    async fn execute_with(
+        &mut self,
+        query: &str,
+        params: &[Param],
+    ) -> Result<ExecuteOutcome, DbError> {
+        check_statement(query, self.read_only)?;
+        execute(&mut self.transaction, query, params).await
+    }
+
+    async fn preview(&mut self, query: &str, params: &[Param]) -> Result<Changes, DbError> {
+        check_statement(query, self.read_only)?;
+        preview::<MySql>(
+            &mut self.transaction,
+            query,
+            params,
+            false,
+            decode_row,
+            MySqlQueryResult::rows_affected,
+        )
+        .await
+    }
+
+    async fn commit(self: Box<Self>) -> Result<(), DbError> {
+        Ok(self.transaction.commit().await?)
+    }
+
+    async fn rollback(self: Box<Self>) -> Result<(), DbError> {
+        Ok(self.transaction.rollback().await?)
+    }
+}
+
+/// Set the statement and lock timeouts (in seconds) and the access mode of a new connection
+async fn set_session_variables(
+    connection: &mut MySqlConnection,
+    statement_timeout: Option<u64>,
+    lock_timeout: Option<u64>,
+    read_only: bool,
+) -> Result<(), sqlx::Error> {
+    if let Some(secs) = statement_timeout {
+        let version: String = sqlx::query_scalar("SELECT VERSION()")
+            .fetch_one(&mut *connection)
+            .await?;
+        // MariaDB has its own variable, in seconds rather than milliseconds
+        let statement = if version.contains("MariaDB") {
+            format!("SET SESSION max_statement_time = {secs}")
+        } else {
+            format!("SET SESSION max_execution_time = {}", secs * 1000)
+        };
+        connection.execute(statement.as_str()).await?;
+    }
+
+    if let Some(secs) = lock_timeout {
+        let statement =
+            format!("SET SESSION innodb_lock_wait_timeout = {secs}, lock_wait_timeout = {secs}");
+        connection.execute(statement.as_str()).await?;
+    }
+
+    // Statements get a read-only transaction of their own as well, see `Guarded`
+    if read_only {
+        connection
+            .execute("SET SESSION TRANSACTION READ ONLY")
+            .await?;
+    }
+
+    Ok(())
+}
+
+async fn get_results(
+    connection: &mut MySqlConnection,
+    query: &str,
+    params: &[Param],
+) -> Result<DatabaseResult, DbError> {
+    // Preparing first gives us the columns even when no rows come back
+    let statement = connection.prepare(query).await?;
+    check_params(&statement, params)?;
+    let headers = QueryDescription::new(&statement).columns;
+    let rows = bind_params(&statement, params)
+        .fetch_all(&mut *connection)
+        .await?;
+
+    let mut results = DatabaseResult {
+        headers,
+        rows: vec![],
+    };
+
+    for row in rows {
+        results.rows.push(decode_row(&row)?);
+    }
+
+    Ok(results)
+}
+
+/// MySQL doesn't report the types of placeholders, it converts the params itself
+impl BindParam for MySql {
+    fn bind_param<'q>(
+        query: Query<'q, MySql, MySqlArguments>,
+        param: Param,
+        _type_info: Option<&MySqlTypeInfo>,
+    ) -> Query<'q, MySql, MySqlArguments> {
+        match param {
+            Param::Null => query.bind(None::<String>),
+            Param::Bool(value) => query.bind(value),
+            Param::Int(value) => query.bind(value),
+            Param::Float(value) => query.bind(value),
+            Param::Text(value) => query.bind(value),
+            Param::Bytes(value) => query.bind(value),
+            Param::Json(value) => query.bind(value),
+        }
+    }
+}
+
+/// Decode every column of a row into JSON
+fn decode_row(row: &MySqlRow) -> Result<Vec<Value>, DbError> {
+    let mut row_data: Vec<Value> = Vec::new();
+
+    for (i, col) in row.columns().iter().enumerate() {
+        if row.try_get_raw(i)?.is_null() {
+            row_data.push(Value::Null);
+            continue;
+        }
+
+        let type_name = col.type_info().name();
+        let value = match type_name {
+            "BOOLEAN" => row.try_get::<bool, _>(i).map(|v| json!(v)),
+
+            "TINYINT" | "SMALLINT" | "MEDIUMINT" | "INT" | "BIGINT" => {
+                row.try_get::<i64, _>(i).map(|v| json!(v))
+            }
+
+            // YEAR and BIT are sent as unsigned integers without always being flagged as such
+            "YEAR" | "BIT" => row.try_get_unchecked::<u64, _>(i).map(|v| json!(v)),
+
+            unsigned if unsigned.ends_with("UNSIGNED") => {
+                row.try_get::<u64, _>(i).map(|v| json!(v))
+            }
+
+            "FLOAT" => row.try_get::<f32, _>(i).map(|v| json!(v)),
+
+            "DOUBLE" => row.try_get::<f64, _>(i).map(|v| json!(v)),
+
+            // Sent as text with up to 65 digits, more than rust_decimal holds, so it stays a
+            // string like Postgres' NUMERIC
+            "DECIMAL" => row.try_get_unchecked::<String, _>(i).map(|v| json!(v)),
+
+            "DATE" => row
+                .try_get::<chrono::NaiveDate, _>(i)
+                .map(|v| json!(v.format("%Y-%m-%d").to_string())),
+
+            // TIME is a duration in MySQL and can be negative or longer than a day
+            "TIME" => row.try_get::<MySqlTime, _>(i).map(|v| json!(v.to_string())),
+
+            "DATETIME" => row
+                .try_get::<chrono::NaiveDateTime, _>(i)
+                .map(|dt| json!(dt.format("%Y-%m-%dT%H:%M:%S%.f").to_string())),
+
+            "TIMESTAMP" => row
+                .try_get::<chrono::DateTime<chrono::Utc>, _>(i)
+                .map(|dt| json!(dt.to_rfc3339())),
+
+            "JSON" => row.try_get::<Value, _>(i),
+
+            "SET" => row.try_get_unchecked::<String, _>(i).map(|v| {
+                json!(
+                    v.split(',')
+                        .filter(|member| !member.is_empty())
+                        .collect::<Vec<_>>()
+                )
+            }),
+
+            "BINARY" | "VARBINARY" | "TINYBLOB" | "BLOB" | "MEDIUMBLOB" | "LONGBLOB"
+            | "GEOMETRY" => row.try_get::<Vec<u8>, _>(i).map(|bytes| json!(hex(&bytes))),
+
+            // CHAR, VARCHAR, the TEXT types and ENUM
+            _ => row.try_get_unchecked::<String, _>(i).map(|v| json!(v)),
+        }
+        .map_err(|e| DbError::Decode(format!("column {} ({type_name}): {e}", col.name())))?;
+
+        row_data.push(value);
+    }
+
+    Ok(row_data)
+}
+
+async fn describe<'e, E>(executor: E, query: &'e str) -> Result<QueryDescription, DbError>
+where
+    E: Executor<'e, Database = MySql>,
+{
+    let statement = executor.prepare(query).await?;
+    Ok(QueryDescription::new(&statement))
+}
+
+async fn execute(
+    connection: &mut MySqlConnection,
+    query: &str,
+    params: &[Param],
+) -> Result<ExecuteOutcome, DbError> {
+    let started = Instant::now();
+    let statement = connection.prepare(query).await?;
+    check_params(&statement, params)?;
+    let result = bind_params(&statement, params)
+        .execute(&mut *connection)
+        .await?;
+    let elapsed = started.elapsed();
+
+    // Warnings stay around until the next statement, a failure to read them isn't worth failing
+    // a statement that has already run over
+    let notices = connection
+        .fetch_all("SHOW WARNINGS")
+        .await
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|row| {
+            let level: String = row.try_get("Level").ok()?;
+            let message: String = row.try_get("Message").ok()?;
+            Some(format!("{level}: {message}"))
+        })
+        .collect();
+
+    Ok(ExecuteOutcome {
+        command_tag: command_tag(query, result.rows_affected()),
+        rows_affected: result.rows_affected(),
+        notices,
+        elapsed,
+    })
+}
@@ -0,0 +1,134 @@
+use crate::DbError;
+
+use serde_json::Value;
+use sqlx::query::Query;
+use sqlx::{Either, Statement};
+
+/// A value for a `$1`/`?` placeholder.
+///
+/// Values are converted to the type the database expects where it says which one that is
+/// (Postgres), e.g. a text param for a `date` placeholder is parsed as a date. A JSON array
+/// fills an array placeholder, such as the one of `id = ANY($1)`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Param {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Text(String),
+    Bytes(Vec<u8>),
+    Json(Value),
+}
+
+impl From<Value> for Param {
+    /// Arrays and objects are kept as JSON, everything else becomes the matching scalar.
+    /// Integers past the range of `i64` become text, so that no digit is lost on the way to a
+    /// `numeric` or unsigned placeholder.
+    fn from(value: Value) -> Self {
+        match value {
+            Value::Null => Self::Null,
+            Value::Bool(value) => Self::Bool(value),
+            Value::Number(number) => match number.as_i64() {
+                Some(value) => Self::Int(value),
+                None if number.is_u64() => Self::Text(number.to_string()),
+                None => Self::Float(number.as_f64().unwrap_or(f64::NAN)),
+            },
+            Value::String(value) => Self::Text(value),
+            value => Self::Json(value),
+        }
+    }
+}
+
+impl From<bool> for Param {
+    fn from(value: bool) -> Self {
+        Self::Bool(value)
+    }
+}
+
+impl From<i64> for Param {
+    fn from(value: i64) -> Self {
+        Self::Int(value)
+    }
+}
+
+impl From<f64> for Param {
+    fn from(value: f64) -> Self {
+        Self::Float(value)
+    }
+}
+
+impl From<&str> for Param {
+    fn from(value: &str) -> Self {
+        Self::Text(value.to_string())
+    }
+}
+
+impl From<String> for Param {
+    fn from(value: String) -> Self {
+        Self::Text(value)
+    }
+}
+
+impl From<Vec<u8>> for Param {
+    fn from(value: Vec<u8>) -> Self {
+        Self::Bytes(value)
+    }
+}
+
+impl<T: Into<Param>> From<Option<T>> for Param {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Self::Null, Into::into)
+    }
+}
+
+/// How a backend adds a param to a query, given the type of its placeholder where the database
+/// reports it
+pub(crate) trait BindParam: sqlx::Database {
+    fn bind_param<'q>(
+        query: Query<'q, Self, Self::Arguments<'q>>,
+        param: Param,
+        type_info: Option<&Self::TypeInfo>,
+    ) -> Query<'q, Self, Self::Arguments<'q>>;
+}
+
+/// Make sure there is a param for every placeholder of a prepared statement, and no more
+pub(crate) fn check_params<'q, S: Statement<'q>>(
+    statement: &S,
+    params: &[Param],
+) -> Result<(), DbError> {
+    let placeholders = match statement.parameters() {
+        Some(Either::Left(types)) => types.len(),
+        Some(Either::Right(count)) => count,
+        None => 0,
+    };
+
+    if placeholders != params.len() {
+        return Err(DbError::Other(format!(
+            "The query has {placeholders} placeholders, but {} params were given",
+            params.len()
+        )));
+    }
+    Ok(())
+}
+
+/// Bind the params of a prepared statement, see [`check_params`]
+pub(crate) fn bind_params<'s, 'q, DB, S>(
+    statement: &'s S,
+    params: &[Param],
+) -> Query<'s, DB, DB::Arguments<'s>>
+where
+    DB: BindParam,
+    S: Statement<'q, Database = DB>,
+{
+    let types = match statement.parameters() {
+        Some(Either::Left(types)) => types.iter().map(Some).collect(),
+        _ => vec![None; params.len()],
+    };
+
+    params
+        .iter()
+        .zip(types)
+        .fold(statement.query(), |query, (param, type_info)| {
+            DB::bind_param(query, param.clone(), type_info)
+        })
+}
@@ -1,231 +1,604 @@
-use crate::DatabaseResult;
-
-use super::Database;
-use serde_json::{Value, json};
-use sqlx::{Column, Connection, PgConnection, Row, TypeInfo};
-use std::{collections::HashMap, fmt::Display};
+mod decode;
+mod encode;
+mod notices;
+
+use crate::param::{BindParam, bind_params, check_params};
+use crate::relay::{self, Listener, Tunnel};
+use crate::schema::{
+    Column, CustomType, CustomTypeKind, ForeignKey, Index, RelationKind, Schema, SchemaBuilder,
+    UniqueConstraint,
+};
+use crate::statement::{command_tag, first_keyword};
+use crate::stream::{CancelHandle, stream_results};
+use crate::transaction::{Changes, check_statement, preview};
+use crate::{
+    Backend, DatabaseResult, DbError, ExecuteOutcome, Guarded, Param, QueryDescription, RowStream,
+    StreamLimits, connect_pool, pool_options, ssh, tls,
+};
+
+use super::{Database, Session, Transaction};
+use config::{Access, DatabaseConnection, SchemaFilter};
+use decode::Decoded;
+use encode::Encoded;
+use notices::Notices;
+use serde_json::Value;
+use sqlx::pool::PoolConnection;
+use sqlx::postgres::{PgArguments, PgConnectOptions, PgQueryResult, PgRow, PgTypeInfo};
+use sqlx::query::Query;
+use sqlx::{Decode, Executor, PgConnection, PgPool, Postgres, Row};
+use std::time::Instant;
+use tracing::instrument::WithSubscriber;
 
 pub struct PostgresDatabase {
-    connection: PgConnection,
+    pool: PgPool,
+    schemas: SchemaFilter,
+    read_only: bool,
+    /// Open for as long as the pool may connect through it
+    _tunnel: Option<Tunnel>,
+}
+
+const BEGIN_READ_ONLY: &str = "BEGIN READ ONLY";
+
+/// Limits introspection to the configured schemas, given `$1` (include) and `$2` (exclude).
+/// System schemas are left out, including the per-session temporary ones.
+const NAMESPACE_FILTER: &str = r#"n.nspname NOT IN ('pg_catalog', 'information_schema')
+              AND n.nspname NOT LIKE 'pg\_toast%'
+              AND n.nspname NOT LIKE 'pg\_temp\_%'
+              AND (cardinality($1::text[]) = 0 OR n.nspname = ANY($1::text[]))
+              AND n.nspname <> ALL($2::text[])"#;
+
+/// Changes whenever a catalog that introspection reads from does. Every statement that creates,
+/// changes or drops an object inserts, updates or deletes catalog rows, which changes their count
+/// or the sum of their `xmin`, the id of the transaction that wrote them. This only scans the
+/// catalogs, which is much cheaper than the joins `information_schema` needs.
+const CATALOG_FINGERPRINT: &str = r#"SELECT concat_ws(' ', VARIADIC ARRAY[
+    (SELECT count(*) || ':' || coalesce(sum(xmin::text::bigint), 0) FROM pg_namespace),
+    (SELECT count(*) || ':' || coalesce(sum(xmin::text::bigint), 0) FROM pg_class),
+    (SELECT count(*) || ':' || coalesce(sum(xmin::text::bigint), 0) FROM pg_attribute),
+    (SELECT count(*) || ':' || coalesce(sum(xmin::text::bigint), 0) FROM pg_attrdef),
+    (SELECT count(*) || ':' || coalesce(sum(xmin::text::bigint), 0) FROM pg_type),
+    (SELECT count(*) || ':' || coalesce(sum(xmin::text::bigint), 0) FROM pg_enum),
+    (SELECT count(*) || ':' || coalesce(sum(xmin::text::bigint), 0) FROM pg_constraint),
+    (SELECT count(*) || ':' || coalesce(sum(xmin::text::bigint), 0) FROM pg_index),
+    (SELECT count(*) || ':' || coalesce(sum(xmin::text::bigint), 0) FROM pg_description)
+])"#;
+
+/// A single connection taken from the pool for as long as the session lives
+pub struct PostgresSession {
+    connection: PoolConnection<Postgres>,
+    read_only: bool,
+}
+
+/// A transaction on a connection of its own, rolled back when dropped
+pub struct PostgresTransaction {
+    transaction: sqlx::Transaction<'static, Postgres>,
+    read_only: bool,
 }
 
 impl PostgresDatabase {
-    pub async fn new(url: impl Display) -> Self {
-        let connection = sqlx::PgConnection::connect(&url.to_string()).await.unwrap();
-        Self { connection }
+    pub async fn new(connection: &DatabaseConnection) -> Result<Self, DbError> {
+        let options = tls::postgres(connection.url.parse()?, &connection.tls)?;
+        let (tunnel, mut options) = relay(connection, options).await?;
+
+        let mut settings = vec![];
+        if let Some(secs) = connection.statement_timeout_secs {
+            settings.push(("statement_timeout", format!("{secs}s")));
+        }
+        if let Some(secs) = connection.lock_timeout_secs {
+            settings.push(("lock_timeout", format!("{secs}s")));
+        }
+        // Statements get a read-only transaction of their own as well, see `Guarded`
+        let read_only = connection.access == Access::ReadOnly;
+        if read_only {
+            settings.push(("default_transaction_read_only", "on".to_string()));
+        }
+        options = options.options(settings);
+
+        let pool = connect_pool(pool_options(&connection.pool), options).await?;
+        Ok(Self {
+            pool,
+            schemas: connection.schemas.clone(),
+            read_only,
+            _tunnel: tunnel,
+        })
     }
 }
 
+/// Connect through a local Unix socket if the database is behind an SSH server, or its
+/// certificate is checked against another name. The driver checks the certificate against the
+/// host it connects to; over a Unix socket it uses the host only for that.
+async fn relay(
+    connection: &DatabaseConnection,
+    options: PgConnectOptions,
+) -> Result<(Option<Tunnel>, PgConnectOptions), DbError> {
+    let server_name = connection.tls.server_name.as_deref();
+    if connection.ssh.is_none() && server_name.is_none() {
+        return Ok((None, options));
+    }
+    if options.get_socket().is_some() {
+        return Err(DbError::Configuration(
+            "the URL is for a Unix socket, which can't be reached through SSH or have a server name"
+                .to_string(),
+        ));
+    }
+
+    let host = options.get_host().to_string();
+    let port = options.get_port();
+    let listener = Listener::postgres_socket(port)?;
+    let mut options = options.host(server_name.unwrap_or(&host));
+    if let Some(dir) = listener.socket_dir() {
+        options = options.socket(dir);
+    }
+
+    let tunnel = match &connection.ssh {
+        Some(ssh) => ssh::forward(ssh, host, port, listener).await?,
+        None => relay::direct(listener, host, port),
+    };
+    Ok((Some(tunnel), options))
+}
+
 #[async_trait::async_trait]
 impl Database for PostgresDatabase {
-    async fn get_results(&mut self, query: &str) -> Result<DatabaseResult, String> {
-        let rows = sqlx::query(query)
-            .fetch_all(&mut self.connection)
-            .await
-            .map_err(|e| e.to_string())?;
-
-        let mut results = DatabaseResult {
-            headers: vec![],
-            rows: vec![],
-        };
+    fn backend(&self) -> Backend {
+        Backend::Postgres
+    }
 
-        if let Some(first) = rows.first() {
-            for col in first.columns().iter() {
-                let col_name = col.name();
-                let type_name = col.type_info().name();
+    async fn get_results_with(
+        &self,
+        query: &str,
+        params: &[Param],
+    ) -> Result<DatabaseResult, DbError> {
+        let mut connection = self.pool.acquire().await?;
+        let mut connection = Guarded::<Postgres>::begin(
+            &mut connection,
+            self.read_only.then_some(BEGIN_READ_ONLY),
+            query,
+        )
+        .await?;
+        let results = get_results(&mut connection, query, params).await?;
+        connection.commit().await?;
+        Ok(results)
+    }
 
-                results
-                    .headers
-                    .push((col_name.to_string(), type_name.to_string()));
-            }
+    async fn stream_results(
+        &self,
+        query: &str,
+        params: &[Param],
+        limits: StreamLimits,
+    ) -> Result<RowStream, DbError> {
+        let mut connection = self.pool.acquire().await?;
+        let pid = sqlx::query_scalar("SELECT pg_backend_pid()")
+            .fetch_one(&mut *connection)
+            .await?;
+        let cancel = CancelHandle::postgres(self.pool.clone(), pid);
+        let begin_read_only = self.read_only.then_some(BEGIN_READ_ONLY);
+        stream_results(
+            connection,
+            query,
+            params.to_vec(),
+            limits,
+            decode_row,
+            cancel,
+            begin_read_only,
+        )
+        .await
+    }
+
+    async fn describe(&self, query: &str) -> Result<QueryDescription, DbError> {
+        describe(&self.pool, query).await
+    }
+
+    async fn execute_with(&self, query: &str, params: &[Param]) -> Result<ExecuteOutcome, DbError> {
+        let mut connection = self.pool.acquire().await?;
+        let mut connection = Guarded::<Postgres>::begin(
+            &mut connection,
+            self.read_only.then_some(BEGIN_READ_ONLY),
+            query,
+        )
+        .await?;
+        let result = execute(&mut connection, query, params).await?;
+        connection.commit().await?;
+        Ok(result)
+    }
+
+    async fn session(&self) -> Result<Box<dyn Session>, DbError> {
+        let connection = self.pool.acquire().await?;
+        Ok(Box::new(PostgresSession {
+            connection,
+            read_only: self.read_only,
+        }))
+    }
+
+    async fn begin(&self) -> Result<Box<dyn Transaction>, DbError> {
+        let transaction = if self.read_only {
+            self.pool.begin_with(BEGIN_READ_ONLY).await?
         } else {
-            return Ok(results);
+            self.pool.begin().await?
+        };
+        Ok(Box::new(PostgresTransaction {
+            transaction,
+            read_only: self.read_only,
+        }))
+    }
+
+    async fn get_schema(&self) -> Result<Schema, DbError> {
+        let mut schema = SchemaBuilder::default();
+        let include = &self.schemas.include;
+        let exclude = &self.schemas.exclude;
+
+        let types = sqlx::query(&format!(
+            r#"SELECT
+                n.nspname::text AS schema_name,
+                t.typname::text AS type_name,
+                t.typtype::text AS type_type,
+                ARRAY(
+                    SELECT e.enumlabel::text
+                    FROM pg_enum e
+                    WHERE e.enumtypid = t.oid
+                    ORDER BY e.enumsortorder
+                ) AS labels,
+                format_type(t.typbasetype, t.typtypmod) AS base_type,
+                NOT t.typnotnull AS nullable,
+                t.typdefault AS type_default,
+                ARRAY(
+                    SELECT pg_get_constraintdef(con.oid, true)
+                    FROM pg_constraint con
+                    WHERE con.contypid = t.oid
+                    ORDER BY con.conname
+                ) AS checks
+            FROM pg_type t
+            JOIN pg_namespace n ON n.oid = t.typnamespace
+            WHERE t.typtype IN ('e', 'd')
+              AND {NAMESPACE_FILTER}
+            ORDER BY n.nspname, t.typname;"#
+        ))
+        .bind(include)
+        .bind(exclude)
+        .fetch_all(&self.pool)
+        .await?;
+
+        for row in types {
+            let kind = match row.try_get::<String, _>("type_type")?.as_str() {
+                "e" => CustomTypeKind::Enum {
+                    labels: row.try_get("labels")?,
+                },
+                _ => CustomTypeKind::Domain {
+                    base_type: row.try_get("base_type")?,
+                    nullable: row.try_get("nullable")?,
+                    default: row.try_get("type_default")?,
+                    checks: row.try_get("checks")?,
+                },
+            };
+
+            schema.add_type(CustomType {
+                schema: Some(row.try_get("schema_name")?),
+                name: row.try_get("type_name")?,
+                kind,
+            });
         }
 
-        for row in rows {
-            let mut row_data: Vec<Value> = Vec::new();
-
-            for (i, col) in row.columns().iter().enumerate() {
-                let type_name = col.type_info().name();
-                let value: Value = match type_name {
-                    "UUID" => row
-                        .try_get::<uuid::Uuid, _>(i)
-                        .map(|v| json!(v))
-                        .unwrap_or(Value::Null),
-
-                    "TEXT" | "VARCHAR" | "CHAR" => row
-                        .try_get::<String, _>(i)
-                        .map(|v| json!(v))
-                        .unwrap_or(Value::Null),
-
-                    "DATE" => row
-                        .try_get::<chrono::NaiveDate, _>(i)
-                        .map(|v| json!(v.format("%Y-%m-%d").to_string()))
-                        .unwrap_or(Value::Null),
-
-                    "TIMESTAMP" => row
-                        .try_get::<chrono::NaiveDateTime, _>(i)
-                        .map(|dt| json!(dt.format("%Y-%m-%dT%H:%M:%S").to_string()))
-                        .unwrap_or(Value::Null),
-
-                    "TIMESTAMPTZ" => row
-                        .try_get::<chrono::DateTime<chrono::Utc>, _>(i)
-                        .map(|dt| json!(dt.to_rfc3339()))
-                        .unwrap_or(Value::Null),
-
-                    "INT2" => row
-                        .try_get::<i16, _>(i)
-                        .map(|v| json!(v))
-                        .unwrap_or(Value::Null),
-
-                    "INT4" => row
-                        .try_get::<i32, _>(i)
-                        .map(|v| json!(v))
-                        .unwrap_or(Value::Null),
-
-                    "INT8" => row
-                        .try_get::<i64, _>(i)
-                        .map(|v| json!(v))
-                        .unwrap_or(Value::Null),
-
-                    "FLOAT4" | "FLOAT8" | "NUMERIC" => row
-                        .try_get::<rust_decimal::Decimal, _>(i)
-                        .map(|v| json!(v))
-                        .unwrap_or(Value::Null),
-
-                    "JSON" | "JSONB" => row.try_get::<Value, _>(i).unwrap_or(Value::Null),
-
-                    "BOOL" => row
-                        .try_get::<bool, _>(i)
-                        .map(|v| json!(v))
-                        .unwrap_or(Value::Null),
-
-                    _ => match row
-                        .try_get_raw(i)
-                        .map(|raw| raw.as_bytes())
-                        .map_err(|_| "".to_string())?
-                    {
-                        Ok(bytes) => match std::str::from_utf8(bytes) {
-                            Ok(s) => json!(s),
-                            Err(_) => Value::Null,
-                        },
-                        Err(_) => Value::Null,
-                    },
-                };
-
-                row_data.push(value);
+        let columns = sqlx::query(&format!(
+            r#"SELECT
+                n.nspname::text AS schema_name,
+                c.relname::text AS table_name,
+                c.relkind::text AS relkind,
+                obj_description(c.oid, 'pg_class') AS table_comment,
+                a.attname::text AS column_name,
+                format_type(a.atttypid, a.atttypmod) AS data_type,
+                NOT a.attnotnull AS nullable,
+                pg_get_expr(d.adbin, d.adrelid) AS column_default,
+                col_description(c.oid, a.attnum) AS column_comment
+            FROM pg_class c
+            JOIN pg_namespace n ON n.oid = c.relnamespace
+            JOIN pg_attribute a ON a.attrelid = c.oid
+            LEFT JOIN pg_attrdef d ON d.adrelid = c.oid AND d.adnum = a.attnum
+            WHERE c.relkind IN ('r', 'p', 'v', 'm', 'f')
+              AND a.attnum > 0
+              AND NOT a.attisdropped
+              AND {NAMESPACE_FILTER}
+            ORDER BY n.nspname, c.relname, a.attnum;"#
+        ))
+        .bind(include)
+        .bind(exclude)
+        .fetch_all(&self.pool)
+        .await?;
+
+        for row in columns {
+            let table = schema.table(row.try_get("schema_name")?, row.try_get("table_name")?);
+            table.kind = match row.try_get::<String, _>("relkind")?.as_str() {
+                "p" => RelationKind::PartitionedTable,
+                "v" => RelationKind::View,
+                "m" => RelationKind::MaterializedView,
+                "f" => RelationKind::ForeignTable,
+                _ => RelationKind::Table,
+            };
+            table.comment = row.try_get("table_comment")?;
+            table.columns.push(Column {
+                name: row.try_get("column_name")?,
+                data_type: row.try_get("data_type")?,
+                nullable: row.try_get("nullable")?,
+                default: row.try_get("column_default")?,
+                comment: row.try_get("column_comment")?,
+            });
+        }
+
+        let constraints = sqlx::query(&format!(
+            r#"SELECT
+                n.nspname::text AS schema_name,
+                c.relname::text AS table_name,
+                con.conname::text AS constraint_name,
+                con.contype::text AS constraint_type,
+                ARRAY(
+                    SELECT a.attname::text
+                    FROM unnest(con.conkey) WITH ORDINALITY AS k(attnum, ord)
+                    JOIN pg_attribute a ON a.attrelid = con.conrelid AND a.attnum = k.attnum
+                    ORDER BY k.ord
+                ) AS columns,
+                rn.nspname::text AS referenced_schema,
+                rc.relname::text AS referenced_table,
+                ARRAY(
+                    SELECT a.attname::text
+                    FROM unnest(con.confkey) WITH ORDINALITY AS k(attnum, ord)
+                    JOIN pg_attribute a ON a.attrelid = con.confrelid AND a.attnum = k.attnum
+                    ORDER BY k.ord
+                ) AS referenced_columns
+            FROM pg_constraint con
+            JOIN pg_class c ON c.oid = con.conrelid
+            JOIN pg_namespace n ON n.oid = c.relnamespace
+            LEFT JOIN pg_class rc ON rc.oid = con.confrelid
+            LEFT JOIN pg_namespace rn ON rn.oid = rc.relnamespace
+            WHERE con.contype IN ('p', 'u', 'f')
+              AND {NAMESPACE_FILTER}
+            ORDER BY n.nspname, c.relname, con.conname;"#
+        ))
+        .bind(include)
+        .bind(exclude)
+        .fetch_all(&self.pool)
+        .await?;
+
+        for row in constraints {
+            let Some(table) =
+                schema.existing_table(row.try_get("schema_name")?, row.try_get("table_name")?)
+            else {
+                continue;
+            };
+            let name: String = row.try_get("constraint_name")?;
+            let columns: Vec<String> = row.try_get("columns")?;
+
+            match row.try_get::<String, _>("constraint_type")?.as_str() {
+                "p" => {
+                    table.primary_key = columns;
+                    table.primary_key_name = Some(name);
+                }
+                "u" => table
+                    .unique_constraints
+                    .push(UniqueConstraint { name, columns }),
+                _ => table.foreign_keys.push(ForeignKey {
+                    name: Some(name),
+                    columns,
+                    referenced_schema: row.try_get("referenced_schema")?,
+                    referenced_table: row
+                        .try_get::<Option<String>, _>("referenced_table")?
+                        .unwrap_or_default(),
+                    referenced_columns: row.try_get("referenced_columns")?,
+                }),
             }
+        }
 
-            results.rows.push(row_data);
+        // Indexes that back a constraint are already covered by the constraint
+        let indexes = sqlx::query(&format!(
+            r#"SELECT
+                n.nspname::text AS schema_name,
+                t.relname::text AS table_name,
+                i.relname::text AS index_name,
+                ix.indisunique AS is_unique,
+                ARRAY(
+                    SELECT pg_get_indexdef(ix.indexrelid, k, true)
+                    FROM generate_series(1, ix.indnkeyatts::int) AS k
+                    ORDER BY k
+                ) AS columns
+            FROM pg_index ix
+            JOIN pg_class i ON i.oid = ix.indexrelid
+            JOIN pg_class t ON t.oid = ix.indrelid
+            JOIN pg_namespace n ON n.oid = t.relnamespace
+            WHERE NOT EXISTS (
+                  SELECT 1 FROM pg_constraint con WHERE con.conindid = ix.indexrelid
+              )
+              AND {NAMESPACE_FILTER}
+            ORDER BY n.nspname, t.relname, i.relname;"#
+        ))
+        .bind(include)
+        .bind(exclude)
+        .fetch_all(&self.pool)
+        .await?;
+
+        for row in indexes {
+            if let Some(table) =
+                schema.existing_table(row.try_get("schema_name")?, row.try_get("table_name")?)
+            {
+                table.indexes.push(Index {
+                    name: row.try_get("index_name")?,
+                    columns: row.try_get("columns")?,
+                    unique: row.try_get("is_unique")?,
+                });
+            }
         }
 
+        Ok(schema.build())
+    }
+
+    async fn schema_fingerprint(&self) -> Result<Option<String>, DbError> {
+        let fingerprint = sqlx::query_scalar(CATALOG_FINGERPRINT)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(Some(fingerprint))
+    }
+}
+
+#[async_trait::async_trait]
+impl Session for PostgresSession {
+    async fn get_results_with(
+        &mut self,
+        query: &str,
+        params: &[Param],
+    ) -> Result<DatabaseResult, DbError> {
+        let begin_read_only = self.read_only.then_some(BEGIN_READ_ONLY);
+        let mut connection =
+            Guarded::<Postgres>::begin(&mut self.connection, begin_read_only, query).await?;
+        let results = get_results(&mut connection, query, params).await?;
+        connection.commit().await?;
         Ok(results)
     }
 
-    async fn execute(&mut self, query: &str) -> Result<String, String> {
-        sqlx::query(query)
-            .execute(&mut self.connection)
-            .await
-            .map_err(|e| e.to_string())?;
+    async fn describe(&mut self, query: &str) -> Result<QueryDescription, DbError> {
+        describe(&mut *self.connection, query).await
+    }
 
-        Ok("ok".to_string())
+    async fn execute_with(
+        &mut self,
+        query: &str,
+        params: &[Param],
+    ) -> Result<ExecuteOutcome, DbError> {
+        let begin_read_only = self.read_only.then_some(BEGIN_READ_ONLY);
+        let mut connection =
+            Guarded::<Postgres>::begin(&mut self.connection, begin_read_only, query).await?;
+        let result = execute(&mut connection, query, params).await?;
+        connection.commit().await?;
+        Ok(result)
     }
+}
 
-    async fn get_schema(
+#[async_trait::async_trait]
+impl Transaction for PostgresTransaction {
+    async fn get_results_with(
         &mut self,
-    ) -> Result<
-        (
-            HashMap<String, Vec<(String, String)>>,
-            HashMap<String, Vec<String>>,
-        ),
-        String,
-    > {
-        let columns = sqlx::query(
-            r#"SELECT
-                c.table_name,
-                c.column_name,
-                c.udt_name AS pg_type
-            FROM information_schema.columns c
-            WHERE c.table_schema = 'public'
-
-            UNION ALL
-
-            SELECT
-                c.relname AS table_name,
-                a.attname AS column_name,
-                t.typname AS pg_type
-            FROM pg_class c
-            JOIN pg_namespace n ON n.oid = c.relnamespace
-            JOIN pg_attribute a ON a.attrelid = c.oid
-            JOIN pg_type t ON t.oid = a.atttypid
-            WHERE c.relpersistence = 't'
-              AND a.attnum > 0
-              AND NOT a.attisdropped;"#,
-        )
-        .fetch_all(&mut self.connection)
-        .await
-        .map_err(|_| "Could not get columns".to_string())?;
-
-        let mut schema_map = HashMap::new();
-
-        let column_map = columns
-            .into_iter()
-            .map(|row| {
-                (
-                    row.get::<String, _>(0),
-                    row.get::<String, _>(1),
-                    row.get::<String, _>(2),
-                )
-            })
-            .collect::<Vec<(String, String, String)>>();
-
-        for (table_name, column_name, column_type) in &column_map {
-            schema_map
-                .entry(table_name.clone())
-                .or_insert(Vec::new())
-                .push((column_name.clone(), column_type.clone()));
-        }
+        query: &str,
+        params: &[Param],
+    ) -> Result<DatabaseResult, DbError> {
+        check_statement(query, self.read_only)?;
+        get_results(&mut self.transaction, query, params).await
+    }
+
+    async fn execute_with(
+        &mut self,
+        query: &str,
+        params: &[Param],
+    ) -> Result<ExecuteOutcome, DbError> {
+        check_statement(query, self.read_only)?;
+        execute(&mut self.transaction, query, params).await
+    }
 
-        let fk_rows = sqlx::query(
-            r#"
-                SELECT
-                    tc.table_name AS referencing_table,
-                    kcu.column_name AS referencing_column,
-                    ccu.table_name AS referenced_table,
-                    ccu.column_name AS referenced_column
-                FROM
-                    information_schema.table_constraints AS tc
-                JOIN information_schema.key_column_usage AS kcu
-                    ON tc.constraint_name = kcu.constraint_name
-                    AND tc.table_schema = kcu.table_schema
-                JOIN information_schema.constraint_column_usage AS ccu
-                    ON ccu.constraint_name = tc.constraint_name
-                    AND ccu.table_schema = tc.table_schema
-                WHERE tc.constraint_type = 'FOREIGN KEY'
-                  AND tc.table_schema = 'public';
-                "#,
+    async fn preview(&mut self, query: &str, params: &[Param]) -> Result<Changes, DbError> {
+        check_statement(query, self.read_only)?;
+        // MERGE only has RETURNING since Postgres 17, before that only its rows are counted
+        let returning = first_keyword(query) != "MERGE"
+            || self
+                .transaction
+                .server_version_num()
+                .is_some_and(|version| version >= 170_000);
+        preview::<Postgres>(
+            &mut self.transaction,
+            query,
+            params,
+            returning,
+            decode_row,
+            PgQueryResult::rows_affected,
         )
-        .fetch_all(&mut self.connection)
         .await
-        .map_err(|_| "Could not get foreign key info".to_string())?;
+    }
 
-        let mut fk_map: HashMap<String, Vec<String>> = HashMap::new();
+    async fn commit(self: Box<Self>) -> Result<(), DbError> {
+        Ok(self.transaction.commit().await?)
+    }
 
-        for row in fk_rows {
-            let referencing_table: String = row.get("referencing_table");
-            let referencing_column: String = row.get("referencing_column");
-            let referenced_table: String = row.get("referenced_table");
-            let referenced_column: String = row.get("referenced_column");
+    async fn rollback(self: Box<Self>) -> Result<(), DbError> {
+        Ok(self.transaction.rollback().await?)
+    }
+}
 
-            let referenced_key = format!("{}.{}", referenced_table, referenced_column);
-            let referencing_key = format!("{}.{}", referencing_table, referencing_column);
+async fn get_results(
+    connection: &mut PgConnection,
+    query: &str,
+    params: &[Param],
+) -> Result<DatabaseResult, DbError> {
+    // Preparing first gives us the columns even when no rows come back
+    let statement = connection.prepare(query).await?;
+    check_params(&statement, params)?;
+    let headers = QueryDescription::new(&statement).columns;
+    let rows = bind_params(&statement, params)
+        .fetch_all(&mut *connection)
+        .await?;
+
+    let mut results = DatabaseResult {
+        headers,
+        rows: vec![],
+    };
+
+    for row in rows {
+        results.rows.push(decode_row(&row)?);
+    }
 
-            fk_map
-                .entry(referenced_key)
-                .or_default()
-                .push(referencing_key);
-        }
+    Ok(results)
+}
+
+/// Params are converted to the types of their placeholders, see [`Encoded`]
+impl BindParam for Postgres {
+    fn bind_param<'q>(
+        query: Query<'q, Postgres, PgArguments>,
+        param: Param,
+        type_info: Option<&PgTypeInfo>,
+    ) -> Query<'q, Postgres, PgArguments> {
+        query.bind(Encoded {
+            param,
+            type_info: type_info.cloned(),
+        })
+    }
+}
+
+/// Decode every column of a row into JSON
+fn decode_row(row: &PgRow) -> Result<Vec<Value>, DbError> {
+    let mut row_data: Vec<Value> = Vec::new();
+
+    for (i, column) in row.columns().iter().enumerate() {
+        let value = Decoded::decode(row.try_get_raw(i)?)
+            .map_err(|e| DbError::Decode(format!("column {}: {e}", sqlx::Column::name(column))))?;
+
+        row_data.push(value.0);
+    }
+
+    Ok(row_data)
+}
+
+async fn describe<'e, E>(executor: E, query: &'e str) -> Result<QueryDescription, DbError>
+where
+    E: Executor<'e, Database = Postgres>,
+{
+    let statement = executor.prepare(query).await?;
+    Ok(QueryDescription::new(&statement))
+}
 
-        Ok((schema_map, fk_map))
+async fn execute(
+    connection: &mut PgConnection,
+    query: &str,
+    params: &[Param],
+) -> Result<ExecuteOutcome, DbError> {
+    let notices = Notices::new();
+    let started = Instant::now();
+    let result = async {
+        let statement = connection.prepare(query).await?;
+        check_params(&statement, params)?;
+        let result = bind_params(&statement, params)
+            .execute(&mut *connection)
+            .await?;
+        Ok::<_, DbError>(result)
     }
+    .with_subscriber(notices.clone())
+    .await?;
+
+    Ok(ExecuteOutcome {
+        command_tag: command_tag(query, result.rows_affected()),
+        rows_affected: result.rows_affected(),
+        notices: notices.take(),
+        elapsed: started.elapsed(),
+    })
 }
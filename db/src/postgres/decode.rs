@@ -0,0 +1,478 @@
+use crate::hex;
+
+use serde_json::{Map, Value, json};
+use sqlx::decode::Decode;
+use sqlx::error::BoxDynError;
+use sqlx::postgres::types::{Oid, PgInterval, PgMoney, PgRange, PgRecordDecoder, PgTimeTz};
+use sqlx::postgres::{PgHasArrayType, PgTypeInfo, PgTypeKind, PgValueFormat, PgValueRef, Postgres};
+use sqlx::types::ipnetwork::IpNetwork;
+use sqlx::types::mac_address::MacAddress;
+use sqlx::{Type, TypeInfo, ValueRef};
+use std::fmt::Write;
+use std::ops::Bound;
+
+/// A value of any type, decoded into JSON.
+///
+/// Implementing [`Decode`] lets sqlx hand us the elements of arrays and ranges and the fields of
+/// composite types, whatever their type, so nested values are decoded the same way as columns.
+pub(super) struct Decoded(pub(super) Value);
+
+impl Type<Postgres> for Decoded {
+    fn type_info() -> PgTypeInfo {
+        PgTypeInfo::with_name("unknown")
+    }
+
+    fn compatible(_ty: &PgTypeInfo) -> bool {
+        true
+    }
+}
+
+impl PgHasArrayType for Decoded {
+    fn array_type_info() -> PgTypeInfo {
+        PgTypeInfo::with_name("unknown")
+    }
+
+    fn array_compatible(_ty: &PgTypeInfo) -> bool {
+        true
+    }
+}
+
+impl<'r> Decode<'r, Postgres> for Decoded {
+    fn decode(value: PgValueRef<'r>) -> Result<Self, BoxDynError> {
+        let type_info = value.type_info().into_owned();
+        decode_as(value, &type_info).map(Self)
+    }
+}
+
+/// Decode a value, `type_info` differs from the value's own type for domains
+fn decode_as(value: PgValueRef<'_>, type_info: &PgTypeInfo) -> Result<Value, BoxDynError> {
+    if value.is_null() {
+        return Ok(Value::Null);
+    }
+
+    // Record fields are only sent as text by unprepared queries, which we don't run
+    if value.format() == PgValueFormat::Text {
+        return Ok(json!(value.as_str()?));
+    }
+
+    match type_info.kind() {
+        PgTypeKind::Array(_) => {
+            // sqlx only decodes one-dimensional arrays, the number of dimensions comes first
+            if let [a, b, c, d, ..] = *value.as_bytes()? {
+                let dimensions = i32::from_be_bytes([a, b, c, d]);
+                if dimensions > 1 {
+                    return Err(format!(
+                        "arrays with {dimensions} dimensions can't be decoded, cast them to text"
+                    )
+                    .into());
+                }
+            }
+            let elements = decode::<Vec<Decoded>>(value)?;
+            return Ok(Value::Array(elements.into_iter().map(|e| e.0).collect()));
+        }
+        PgTypeKind::Range(_) => return decode_range(value),
+        PgTypeKind::Composite(fields) => {
+            let mut decoder = PgRecordDecoder::new(value)?;
+            let mut object = Map::new();
+            for (name, _) in fields.iter() {
+                object.insert(name.clone(), decoder.try_decode::<Decoded>()?.0);
+            }
+            return Ok(Value::Object(object));
+        }
+        PgTypeKind::Domain(base) => return decode_as(value, base),
+        PgTypeKind::Enum(_) => return Ok(json!(value.as_str()?)),
+        PgTypeKind::Simple | PgTypeKind::Pseudo => {}
+    }
+
+    let value = match type_info.name() {
+        "BOOL" => json!(decode::<bool>(value)?),
+        "INT2" => json!(decode::<i16>(value)?),
+        "INT4" => json!(decode::<i32>(value)?),
+        "INT8" => json!(decode::<i64>(value)?),
+        "OID" => json!(decode::<Oid>(value)?.0),
+        // Widening would show the float's binary error, e.g. 1.100000023841858 for 1.1
+        "FLOAT4" => {
+            let value = decode::<f32>(value)?;
+            float(value.to_string().parse().unwrap_or(f64::from(value)))
+        }
+        "FLOAT8" => float(decode::<f64>(value)?),
+
+        // Arbitrary precision, so it stays a string like the decimals of other backends
+        "NUMERIC" => json!(numeric(value.as_bytes()?)?),
+
+        // The number of fractional digits depends on lc_monetary, it's 2 for most locales
+        "MONEY" => json!(decode::<PgMoney>(value)?.to_decimal(2)),
+
+        "UUID" => json!(decode::<uuid::Uuid>(value)?),
+
+        "DATE" => match infinity(value.as_bytes()?) {
+            Some(infinity) => json!(infinity),
+            None => json!(
+                decode::<chrono::NaiveDate>(value)?
+                    .format("%Y-%m-%d")
+                    .to_string()
+            ),
+        },
+
+        "TIME" => json!(
+            decode::<chrono::NaiveTime>(value)?
+                .format("%H:%M:%S%.f")
+                .to_string()
+        ),
+
+        "TIMETZ" => {
+            let time = decode::<PgTimeTz<chrono::NaiveTime, chrono::FixedOffset>>(value)?;
+            json!(format!(
+                "{}{}",
+                time.time.format("%H:%M:%S%.f"),
+                time.offset
+            ))
+        }
+
+        "TIMESTAMP" => match infinity(value.as_bytes()?) {
+            Some(infinity) => json!(infinity),
+            None => json!(
+                decode::<chrono::NaiveDateTime>(value)?
+                    .format("%Y-%m-%dT%H:%M:%S%.f")
+                    .to_string()
+            ),
+        },
+
+        "TIMESTAMPTZ" => match infinity(value.as_bytes()?) {
+            Some(infinity) => json!(infinity),
+            None => json!(decode::<chrono::DateTime<chrono::Utc>>(value)?.to_rfc3339()),
+        },
+
+        "INTERVAL" => json!(interval(&decode::<PgInterval>(value)?)),
+
+        "BYTEA" => json!(hex(value.as_bytes()?)),
+
+        // Postgres leaves out the prefix length of single host addresses
+        "INET" => match decode::<IpNetwork>(value)? {
+            network if network.prefix() == max_prefix(&network) => json!(network.ip()),
+            network => json!(network.to_string()),
+        },
+        "CIDR" => json!(decode::<IpNetwork>(value)?.to_string()),
+
+        "MACADDR" => json!(decode::<MacAddress>(value)?.to_string().to_lowercase()),
+        "MACADDR8" => json!(
+            value
+                .as_bytes()?
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect::<Vec<_>>()
+                .join(":")
+        ),
+
+        "BIT" | "VARBIT" => json!(bits(value.as_bytes()?)?),
+
+        "JSON" | "JSONB" => decode::<Value>(value)?,
+
+        // Binary JSONPATH is the text form after a version byte
+        "JSONPATH" => json!(std::str::from_utf8(
+            value.as_bytes()?.get(1..).unwrap_or_default()
+        )?),
+
+        "POINT" | "LSEG" | "BOX" | "LINE" | "CIRCLE" | "PATH" | "POLYGON" => {
+            json!(geometry(type_info.name(), value.as_bytes()?)?)
+        }
+
+        "tsvector" => json!(tsvector(value.as_bytes()?)?),
+
+        "VOID" => Value::Null,
+
+        "RECORD" => decode_record(value)?,
+
+        // Types whose binary format is their text
+        "TEXT" | "VARCHAR" | "CHAR" | "\"CHAR\"" | "NAME" | "UNKNOWN" | "xml" | "citext" => {
+            json!(value.as_str()?)
+        }
+
+        // Extension types often send their text as well. Anything else is a binary format that
+        // would come out as garbage, so it has to be cast to text instead.
+        name => match std::str::from_utf8(value.as_bytes()?) {
+            Ok(text) if !text.chars().any(|c| c.is_control() && !c.is_whitespace()) => json!(text),
+            _ => {
+                return Err(
+                    format!("values of type {name} can't be decoded, cast them to text").into(),
+                );
+            }
+        },
+    };
+
+    Ok(value)
+}
+
+/// [`Decode::decode`] for Postgres, sqlx implements it for several databases
+fn decode<'r, T: Decode<'r, Postgres>>(value: PgValueRef<'r>) -> Result<T, BoxDynError> {
+    T::decode(value)
+}
+
+/// Anonymous records, e.g. `SELECT (1, 'a')`, have no field names and become arrays
+fn decode_record(value: PgValueRef<'_>) -> Result<Value, BoxDynError> {
+    let count = match value.as_bytes()? {
+        [a, b, c, d, ..] => u32::from_be_bytes([*a, *b, *c, *d]),
+        _ => return Err("record is too short".into()),
+    };
+
+    let mut decoder = PgRecordDecoder::new(value)?;
+    let fields = (0..count)
+        .map(|_| decoder.try_decode::<Decoded>().map(|field| field.0))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Value::Array(fields))
+}
+
+/// Ranges are shown the way Postgres prints them, e.g. `[1,10)` or `empty`
+fn decode_range(value: PgValueRef<'_>) -> Result<Value, BoxDynError> {
+    // The first byte holds the range's flags, 0x01 marks an empty range
+    if value
+        .as_bytes()?
+        .first()
+        .is_some_and(|flags| flags & 0x01 != 0)
+    {
+        return Ok(json!("empty"));
+    }
+
+    let range = decode::<PgRange<Decoded>>(value)?;
+    let bound = |value: &Decoded| match &value.0 {
+        Value::String(text) => text.clone(),
+        value => value.to_string(),
+    };
+
+    let mut text = String::new();
+    match &range.start {
+        Bound::Included(value) => write!(text, "[{}", bound(value))?,
+        Bound::Excluded(value) => write!(text, "({}", bound(value))?,
+        Bound::Unbounded => text.push('('),
+    }
+    text.push(',');
+    match &range.end {
+        Bound::Included(value) => write!(text, "{}]", bound(value))?,
+        Bound::Excluded(value) => write!(text, "{})", bound(value))?,
+        Bound::Unbounded => text.push(')'),
+    }
+
+    Ok(json!(text))
+}
+
+/// JSON has no NaN or infinity, those are spelled the way Postgres spells them
+fn float(value: f64) -> Value {
+    if value.is_nan() {
+        json!("NaN")
+    } else if value.is_infinite() {
+        json!(if value > 0.0 { "Infinity" } else { "-Infinity" })
+    } else {
+        json!(value)
+    }
+}
+
+/// `infinity` and `-infinity` dates and timestamps, which are stored as the extremes of the
+/// underlying integer and can't be represented by chrono
+fn infinity(bytes: &[u8]) -> Option<&'static str> {
+    match bytes {
+        [0x7f, 0xff, 0xff, 0xff] | [0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff] => {
+            Some("infinity")
+        }
+        [0x80, 0, 0, 0] | [0x80, 0, 0, 0, 0, 0, 0, 0] => Some("-infinity"),
+        _ => None,
+    }
+}
+
+/// Format a binary NUMERIC: a header of digit count, weight, sign and display scale,
+/// followed by the digits in base 10000
+fn numeric(bytes: &[u8]) -> Result<String, BoxDynError> {
+    let mut words = bytes
+        .chunks_exact(2)
+        .map(|word| u16::from_be_bytes([word[0], word[1]]));
+    let mut next = || words.next().ok_or("numeric is too short");
+
+    let count = next()?;
+    // The weight is the power of 10000 of the first digit and can be negative
+    let weight = i32::from(next()?.cast_signed());
+    let sign = next()?;
+    let scale = usize::from(next()?);
+    let digits = (0..count).map(|_| next()).collect::<Result<Vec<_>, _>>()?;
+
+    match sign {
+        0xc000 => return Ok("NaN".to_string()),
+        0xd000 => return Ok("Infinity".to_string()),
+        0xf000 => return Ok("-Infinity".to_string()),
+        _ => {}
+    }
+
+    let digit = |position: i32| {
+        usize::try_from(position)
+            .ok()
+            .and_then(|position| digits.get(position))
+            .copied()
+            .unwrap_or(0)
+    };
+
+    let mut text = String::new();
+    if sign == 0x4000 {
+        text.push('-');
+    }
+
+    if weight < 0 {
+        text.push('0');
+    } else {
+        write!(text, "{}", digit(0))?;
+        for position in 1..=weight {
+            write!(text, "{:04}", digit(position))?;
+        }
+    }
+
+    if scale > 0 {
+        let mut fraction = String::new();
+        let mut position = weight + 1;
+        while fraction.len() < scale {
+            write!(fraction, "{:04}", digit(position))?;
+            position += 1;
+        }
+        fraction.truncate(scale);
+        write!(text, ".{fraction}")?;
+    }
+
+    Ok(text)
+}
+
+/// Format an interval the way Postgres does by default, e.g. `1 year 2 mons 3 days 04:05:06`
+fn interval(interval: &PgInterval) -> String {
+    let plural = |value: i32| if value == 1 { "" } else { "s" };
+
+    let mut parts = vec![];
+    let (years, months) = (interval.months / 12, interval.months % 12);
+    if years != 0 {
+        parts.push(format!("{years} year{}", plural(years)));
+    }
+    if months != 0 {
+        parts.push(format!("{months} mon{}", plural(months)));
+    }
+    if interval.days != 0 {
+        parts.push(format!("{} day{}", interval.days, plural(interval.days)));
+    }
+
+    if interval.microseconds != 0 || parts.is_empty() {
+        let sign = if interval.microseconds < 0 { "-" } else { "" };
+        let microseconds = interval.microseconds.unsigned_abs();
+        let seconds = microseconds / 1_000_000;
+
+        let mut time = format!(
+            "{sign}{:02}:{:02}:{:02}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        );
+        let fraction = microseconds % 1_000_000;
+        if fraction > 0 {
+            let fraction = format!("{fraction:06}");
+            write!(time, ".{}", fraction.trim_end_matches('0')).unwrap_or_default();
+        }
+        parts.push(time);
+    }
+
+    parts.join(" ")
+}
+
+/// Format a geometric type the way Postgres prints it, e.g. `(1,2)` for a point. The binary
+/// formats are the float8 coordinates, paths and polygons are prefixed with their point count.
+fn geometry(name: &str, bytes: &[u8]) -> Result<String, BoxDynError> {
+    let (closed, bytes) = match name {
+        // Paths start with whether they are closed
+        "PATH" => match bytes.split_first() {
+            Some((closed, rest)) => (*closed != 0, rest),
+            None => return Err("path is too short".into()),
+        },
+        _ => (true, bytes),
+    };
+    let bytes = match name {
+        "PATH" | "POLYGON" => bytes.get(4..).ok_or("geometry is too short")?,
+        _ => bytes,
+    };
+
+    let numbers = bytes
+        .chunks_exact(8)
+        .map(|number| f64::from_be_bytes(number.try_into().unwrap_or_default()))
+        .collect::<Vec<_>>();
+    let points = numbers
+        .chunks_exact(2)
+        .map(|point| format!("({},{})", point[0], point[1]))
+        .collect::<Vec<_>>();
+
+    Ok(match (name, &numbers[..]) {
+        ("POINT" | "BOX", _) => points.join(","),
+        ("LSEG", _) => format!("[{}]", points.join(",")),
+        ("LINE", [a, b, c]) => format!("{{{a},{b},{c}}}"),
+        ("CIRCLE", [x, y, radius]) => format!("<({x},{y}),{radius}>"),
+        ("PATH", _) if !closed => format!("[{}]", points.join(",")),
+        ("PATH" | "POLYGON", _) => format!("({})", points.join(",")),
+        _ => return Err(format!("{} is malformed", name.to_lowercase()).into()),
+    })
+}
+
+/// Format a tsvector the way Postgres prints it, e.g. `'cat':3 'fat':2A`. The binary format is
+/// the lexeme count, then each lexeme NUL-terminated with its positions. The top two bits of a
+/// position are its weight.
+fn tsvector(bytes: &[u8]) -> Result<String, BoxDynError> {
+    let (count, mut rest) = bytes.split_at_checked(4).ok_or("tsvector is too short")?;
+    let count = u32::from_be_bytes(count.try_into()?);
+
+    let mut lexemes = vec![];
+    for _ in 0..count {
+        let end = rest
+            .iter()
+            .position(|byte| *byte == 0)
+            .ok_or("tsvector lexeme isn't terminated")?;
+        let lexeme = std::str::from_utf8(&rest[..end])?;
+        let mut text = format!("'{}'", lexeme.replace('\\', "\\\\").replace('\'', "''"));
+
+        let (positions, tail) = rest[end + 1..]
+            .split_at_checked(2)
+            .ok_or("tsvector is too short")?;
+        let positions = usize::from(u16::from_be_bytes(positions.try_into()?));
+        let (positions, tail) = tail
+            .split_at_checked(positions * 2)
+            .ok_or("tsvector is too short")?;
+        rest = tail;
+
+        for (index, position) in positions.chunks_exact(2).enumerate() {
+            let position = u16::from_be_bytes([position[0], position[1]]);
+            let weight = match position >> 14 {
+                3 => "A",
+                2 => "B",
+                1 => "C",
+                _ => "",
+            };
+            let separator = if index == 0 { ':' } else { ',' };
+            write!(text, "{separator}{}{weight}", position & 0x3fff)?;
+        }
+        lexemes.push(text);
+    }
+
+    Ok(lexemes.join(" "))
+}
+
+fn max_prefix(network: &IpNetwork) -> u8 {
+    match network {
+        IpNetwork::V4(_) => 32,
+        IpNetwork::V6(_) => 128,
+    }
+}
+
+/// Format a BIT or VARBIT as its digits: the length in bits followed by the bits, packed
+fn bits(bytes: &[u8]) -> Result<String, BoxDynError> {
+    let (length, bits) = bytes.split_at_checked(4).ok_or("bit string is too short")?;
+    let length = u32::from_be_bytes(length.try_into()?);
+
+    Ok((0..length as usize)
+        .map(|bit| {
+            let byte = bits.get(bit / 8).copied().unwrap_or(0);
+            if byte & (0x80 >> (bit % 8)) == 0 {
+                '0'
+            } else {
+                '1'
+            }
+        })
+        .collect())
+}
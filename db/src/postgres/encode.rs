@@ -0,0 +1,252 @@
+use crate::Param;
+
+use rust_decimal::Decimal;
+use serde_json::{Value, json};
+use sqlx::encode::{Encode, IsNull};
+use sqlx::error::BoxDynError;
+use sqlx::postgres::types::{Oid, PgMoney};
+use sqlx::postgres::{PgArgumentBuffer, PgTypeInfo, PgTypeKind, Postgres};
+use sqlx::types::ipnetwork::IpNetwork;
+use sqlx::types::mac_address::MacAddress;
+use sqlx::{Type, TypeInfo};
+
+/// A param encoded as the type Postgres inferred for its placeholder.
+///
+/// Params are sent in binary, so the value has to be converted to exactly that type; the
+/// server won't cast e.g. text to a date the way it does for literals.
+pub(super) struct Encoded {
+    pub(super) param: Param,
+    /// The type of the placeholder, as reported when the statement was prepared
+    pub(super) type_info: Option<PgTypeInfo>,
+}
+
+impl Type<Postgres> for Encoded {
+    fn type_info() -> PgTypeInfo {
+        PgTypeInfo::with_name("unknown")
+    }
+
+    fn compatible(_ty: &PgTypeInfo) -> bool {
+        true
+    }
+}
+
+impl Encode<'_, Postgres> for Encoded {
+    fn encode_by_ref(&self, buf: &mut PgArgumentBuffer) -> Result<IsNull, BoxDynError> {
+        match &self.type_info {
+            Some(type_info) => encode_as(&self.param, type_info, buf),
+            None => Err("the type of the placeholder is unknown".into()),
+        }
+    }
+
+    fn produces(&self) -> Option<PgTypeInfo> {
+        self.type_info.clone()
+    }
+}
+
+/// Encode a param, `type_info` differs from the placeholder's own type for domains
+fn encode_as(
+    param: &Param,
+    type_info: &PgTypeInfo,
+    buf: &mut PgArgumentBuffer,
+) -> Result<IsNull, BoxDynError> {
+    if *param == Param::Null {
+        return Ok(IsNull::Yes);
+    }
+
+    match type_info.kind() {
+        PgTypeKind::Domain(base) => return encode_as(param, base, buf),
+        // Binary enums are their label
+        PgTypeKind::Enum(_) => return encode(text(param)?, buf),
+        PgTypeKind::Array(element) => return encode_array(param, type_info, element, buf),
+        PgTypeKind::Range(_) | PgTypeKind::Composite(_) => return Err(unsupported(type_info)),
+        PgTypeKind::Simple | PgTypeKind::Pseudo => {}
+    }
+
+    match type_info.name() {
+        "BOOL" => encode(boolean(param)?, buf),
+        "INT2" => encode(i16::try_from(integer(param)?)?, buf),
+        "INT4" => encode(i32::try_from(integer(param)?)?, buf),
+        "INT8" => encode(integer(param)?, buf),
+        "OID" => encode(Oid(u32::try_from(integer(param)?)?), buf),
+        "FLOAT4" => encode(float(param)? as f32, buf),
+        "FLOAT8" => encode(float(param)?, buf),
+        "NUMERIC" => encode(decimal(param)?, buf),
+        "MONEY" => encode(PgMoney::from_decimal(decimal(param)?, 2), buf),
+        "UUID" => encode(text(param)?.trim().parse::<uuid::Uuid>()?, buf),
+        "DATE" => encode(text(param)?.trim().parse::<chrono::NaiveDate>()?, buf),
+        "TIME" => encode(text(param)?.trim().parse::<chrono::NaiveTime>()?, buf),
+        "TIMESTAMP" => encode(timestamp(param)?, buf),
+
+        // Without an offset the time is taken as UTC, not in the session's time zone
+        "TIMESTAMPTZ" => {
+            let text = text(param)?.trim().replacen(' ', "T", 1);
+            match chrono::DateTime::parse_from_rfc3339(&text) {
+                Ok(timestamp) => encode(timestamp, buf),
+                Err(_) => encode(timestamp(param)?.and_utc(), buf),
+            }
+        }
+
+        "BYTEA" => encode(bytes(param)?, buf),
+        "INET" | "CIDR" => encode(text(param)?.trim().parse::<IpNetwork>()?, buf),
+        "MACADDR" => encode(text(param)?.trim().parse::<MacAddress>()?, buf),
+        "JSON" | "JSONB" => encode(json_value(param), buf),
+
+        "INTERVAL" | "TIMETZ" | "MACADDR8" | "BIT" | "VARBIT" | "JSONPATH" | "RECORD" => {
+            Err(unsupported(type_info))
+        }
+
+        // TEXT, VARCHAR, CHAR, NAME, XML, CITEXT and other text-like types
+        _ => encode(text(param)?, buf),
+    }
+}
+
+/// A JSON array such as `[1, 2]`, or text holding one, for an array placeholder, e.g. in
+/// `id = ANY($1)`. The elements are encoded as the element type like params of their own,
+/// nested arrays for arrays of more dimensions aren't supported.
+fn encode_array(
+    param: &Param,
+    type_info: &PgTypeInfo,
+    element: &PgTypeInfo,
+    buf: &mut PgArgumentBuffer,
+) -> Result<IsNull, BoxDynError> {
+    let Value::Array(values) = json_value(param) else {
+        return Err(format!(
+            "params of type {} are JSON arrays, e.g. [1, 2]",
+            type_info.name()
+        )
+        .into());
+    };
+
+    let element_oid = element
+        .oid()
+        .ok_or_else(|| format!("the oid of {} is unknown", element.name()))?;
+
+    // One dimension without flags, then the element type and the length with a lower bound
+    // of 1, followed by each element with its size, or -1 for null
+    buf.extend(1_i32.to_be_bytes());
+    buf.extend(0_i32.to_be_bytes());
+    buf.extend(element_oid.0.to_be_bytes());
+    buf.extend(i32::try_from(values.len())?.to_be_bytes());
+    buf.extend(1_i32.to_be_bytes());
+
+    for value in values {
+        let offset = buf.len();
+        buf.extend(0_i32.to_be_bytes());
+        let size = match encode_as(&Param::from(value), element, buf)? {
+            IsNull::No => i32::try_from(buf.len() - offset - 4)?,
+            IsNull::Yes => -1,
+        };
+        buf[offset..offset + 4].copy_from_slice(&size.to_be_bytes());
+    }
+
+    Ok(IsNull::No)
+}
+
+/// [`Encode::encode_by_ref`] for Postgres, sqlx implements it for several databases
+fn encode<'q, T: Encode<'q, Postgres>>(
+    value: T,
+    buf: &mut PgArgumentBuffer,
+) -> Result<IsNull, BoxDynError> {
+    value.encode_by_ref(buf)
+}
+
+fn unsupported(type_info: &PgTypeInfo) -> BoxDynError {
+    let name = type_info.name();
+    format!(
+        "params of type {name} aren't supported, cast the placeholder instead: $1::text::{name}"
+    )
+    .into()
+}
+
+fn text(param: &Param) -> Result<String, BoxDynError> {
+    Ok(match param {
+        Param::Null => String::new(),
+        Param::Bool(value) => value.to_string(),
+        Param::Int(value) => value.to_string(),
+        Param::Float(value) => value.to_string(),
+        Param::Text(value) => value.clone(),
+        Param::Bytes(value) => String::from_utf8(value.clone())?,
+        Param::Json(value) => value.to_string(),
+    })
+}
+
+fn boolean(param: &Param) -> Result<bool, BoxDynError> {
+    match param {
+        Param::Bool(value) => Ok(*value),
+        Param::Int(value) => Ok(*value != 0),
+        _ => match text(param)?.trim().to_lowercase().as_str() {
+            "t" | "true" | "y" | "yes" | "on" | "1" => Ok(true),
+            "f" | "false" | "n" | "no" | "off" | "0" => Ok(false),
+            other => Err(format!("{other:?} is not a boolean").into()),
+        },
+    }
+}
+
+fn integer(param: &Param) -> Result<i64, BoxDynError> {
+    match param {
+        Param::Int(value) => Ok(*value),
+        Param::Bool(value) => Ok(i64::from(*value)),
+        // Past the range of `i64` the cast would saturate, parsing the text fails instead
+        Param::Float(value)
+            if value.fract() == 0.0 && (i64::MIN as f64..-(i64::MIN as f64)).contains(value) =>
+        {
+            Ok(*value as i64)
+        }
+        _ => Ok(text(param)?.trim().parse()?),
+    }
+}
+
+fn float(param: &Param) -> Result<f64, BoxDynError> {
+    match param {
+        Param::Float(value) => Ok(*value),
+        Param::Int(value) => Ok(*value as f64),
+        _ => Ok(text(param)?.trim().parse()?),
+    }
+}
+
+fn decimal(param: &Param) -> Result<Decimal, BoxDynError> {
+    match param {
+        Param::Int(value) => Ok(Decimal::from(*value)),
+        Param::Float(value) => Ok(Decimal::try_from(*value)?),
+        _ => {
+            let text = text(param)?;
+            let text = text.trim();
+            Ok(Decimal::from_str_exact(text).or_else(|_| Decimal::from_scientific(text))?)
+        }
+    }
+}
+
+/// `2024-01-31 12:00:00` as well as `2024-01-31T12:00:00`
+fn timestamp(param: &Param) -> Result<chrono::NaiveDateTime, BoxDynError> {
+    Ok(text(param)?.trim().replacen(' ', "T", 1).parse()?)
+}
+
+/// Binary data, or text such as `\x00ff`, the way Postgres prints `bytea`
+fn bytes(param: &Param) -> Result<Vec<u8>, BoxDynError> {
+    match param {
+        Param::Bytes(value) => Ok(value.clone()),
+        _ => {
+            let text = text(param)?;
+            match text.strip_prefix("\\x") {
+                Some(hex) if hex.len() % 2 == 0 && hex.is_ascii() => (0..hex.len())
+                    .step_by(2)
+                    .map(|i| Ok(u8::from_str_radix(&hex[i..i + 2], 16)?))
+                    .collect(),
+                _ => Ok(text.into_bytes()),
+            }
+        }
+    }
+}
+
+/// Text that is valid JSON is taken as such, e.g. `{"a": 1}`, anything else as a JSON string
+fn json_value(param: &Param) -> Value {
+    match param {
+        Param::Json(value) => value.clone(),
+        Param::Text(text) => serde_json::from_str(text).unwrap_or_else(|_| json!(text)),
+        Param::Null => Value::Null,
+        Param::Bool(value) => json!(value),
+        Param::Int(value) => json!(value),
+        Param::Float(value) => json!(value),
+        Param::Bytes(value) => json!(String::from_utf8_lossy(value)),
+    }
+}
@@ -0,0 +1,106 @@
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::subscriber::Interest;
+use tracing::{Dispatch, Event, Metadata, Subscriber};
+
+/// sqlx doesn't hand out the notices it receives, it only reports them as tracing events with
+/// the notice as their message. A change to that in sqlx leaves the notices empty.
+const NOTICE_TARGET: &str = "sqlx::postgres::notice";
+
+/// Collects the server's notices and warnings while it is the subscriber of a future, see
+/// [`tracing::instrument::WithSubscriber`]. Everything else, notices included, goes on to the
+/// subscriber that was the default when it was created, so the app's own tracing still works.
+#[derive(Debug, Clone)]
+pub(super) struct Notices {
+    notices: Arc<Mutex<Vec<String>>>,
+    inner: Dispatch,
+}
+
+impl Notices {
+    pub(super) fn new() -> Self {
+        Self {
+            notices: Arc::default(),
+            inner: tracing::dispatcher::get_default(Dispatch::clone),
+        }
+    }
+
+    pub(super) fn take(&self) -> Vec<String> {
+        self.notices
+            .lock()
+            .map(|mut notices| notices.split_off(0))
+            .unwrap_or_default()
+    }
+}
+
+impl Subscriber for Notices {
+    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+        if metadata.target() == NOTICE_TARGET {
+            Interest::always()
+        } else {
+            self.inner.register_callsite(metadata)
+        }
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.target() == NOTICE_TARGET || self.inner.enabled(metadata)
+    }
+
+    fn new_span(&self, attributes: &Attributes<'_>) -> Id {
+        self.inner.new_span(attributes)
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        self.inner.record(span, values);
+    }
+
+    fn record_follows_from(&self, span: &Id, follows: &Id) {
+        self.inner.record_follows_from(span, follows);
+    }
+
+    fn event(&self, event: &Event<'_>) {
+        if event.metadata().target() == NOTICE_TARGET {
+            let mut message = Message(None);
+            event.record(&mut message);
+            if let (Some(message), Ok(mut notices)) = (message.0, self.notices.lock()) {
+                notices.push(message);
+            }
+        }
+        if self.inner.enabled(event.metadata()) {
+            self.inner.event(event);
+        }
+    }
+
+    fn enter(&self, span: &Id) {
+        self.inner.enter(span);
+    }
+
+    fn exit(&self, span: &Id) {
+        self.inner.exit(span);
+    }
+
+    fn clone_span(&self, id: &Id) -> Id {
+        self.inner.clone_span(id)
+    }
+
+    fn try_close(&self, id: Id) -> bool {
+        self.inner.try_close(id)
+    }
+}
+
+struct Message(Option<String>);
+
+impl Visit for Message {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.0 = Some(value.to_string());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        if field.name() == "message" {
+            self.0 = Some(format!("{value:?}"));
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use crate::DbError;
+
+use std::io;
+use std::net::Ipv4Addr;
+use std::path::Path;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::{JoinHandle, JoinSet};
+
+/// A local end the database driver connects to, whose connections are forwarded to the
+/// database, for as long as the tunnel lives
+#[derive(Debug)]
+pub(crate) struct Tunnel {
+    forwarding: JoinHandle<()>,
+}
+
+impl Tunnel {
+    /// Forward the listener's connections with `forward` until the tunnel is dropped
+    pub(crate) fn spawn<F>(listener: Listener, forward: impl FnOnce(Listener) -> F) -> Self
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        Self {
+            forwarding: tokio::spawn(forward(listener)),
+        }
+    }
+}
+
+impl Drop for Tunnel {
+    fn drop(&mut self) {
+        self.forwarding.abort();
+    }
+}
+
+/// A connection accepted by a [`Listener`]
+pub(crate) trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+pub(crate) enum Listener {
+    /// A port on 127.0.0.1
+    Tcp(TcpListener),
+    /// A socket in a directory only the current user can open, laid out like Postgres'
+    /// socket directories
+    #[cfg(unix)]
+    Unix {
+        listener: tokio::net::UnixListener,
+        dir: PathBuf,
+    },
+}
+
+impl Listener {
+    /// A port on 127.0.0.1, returned along with the listener
+    pub(crate) async fn tcp() -> Result<(Self, u16), DbError> {
+        let error = |error: io::Error| DbError::Connection(error.to_string());
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .map_err(error)?;
+        let port = listener.local_addr().map_err(error)?.port();
+        Ok((Self::Tcp(listener), port))
+    }
+
+    /// A Postgres socket directory for a server on `port`
+    #[cfg(unix)]
+    pub(crate) fn postgres_socket(port: u16) -> Result<Self, DbError> {
+        use std::os::unix::fs::DirBuilderExt;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static SOCKETS: AtomicUsize = AtomicUsize::new(0);
+
+        let dir = std::env::temp_dir().join(format!(
+            "peek-{}-{}",
+            std::process::id(),
+            SOCKETS.fetch_add(1, Ordering::Relaxed)
+        ));
+        let error = |error: io::Error| DbError::Connection(format!("{}: {error}", dir.display()));
+        std::fs::DirBuilder::new()
+            .mode(0o700)
+            .create(&dir)
+            .map_err(error)?;
+        let listener = tokio::net::UnixListener::bind(dir.join(format!(".s.PGSQL.{port}")))
+            .map_err(|io_error| {
+                let _ = std::fs::remove_dir_all(&dir);
+                error(io_error)
+            })?;
+        Ok(Self::Unix { listener, dir })
+    }
+
+    #[cfg(not(unix))]
+    pub(crate) fn postgres_socket(_port: u16) -> Result<Self, DbError> {
+        Err(DbError::Configuration(
+            "connecting through SSH or with tls.server_name needs Unix sockets".to_string(),
+        ))
+    }
+
+    /// The directory of the socket, for Unix sockets
+    pub(crate) fn socket_dir(&self) -> Option<&Path> {
+        match self {
+            Self::Tcp(_) => None,
+            #[cfg(unix)]
+            Self::Unix { dir, .. } => Some(dir),
+        }
+    }
+
+    /// The next connection, and where it came from for the server's logs
+    pub(crate) async fn accept(&self) -> io::Result<(Box<dyn Stream>, String, u16)> {
+        match self {
+            Self::Tcp(listener) => {
+                let (socket, peer) = listener.accept().await?;
+                Ok((Box::new(socket), peer.ip().to_string(), peer.port()))
+            }
+            #[cfg(unix)]
+            Self::Unix { listener, .. } => {
+                let (socket, _) = listener.accept().await?;
+                Ok((Box::new(socket), Ipv4Addr::LOCALHOST.to_string(), 0))
+            }
+        }
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Self::Unix { dir, .. } = self {
+            let _ = std::fs::remove_dir_all(dir);
+        }
+    }
+}
+
+/// Forward connections to the database as they are, without an SSH server in between
+pub(crate) fn direct(listener: Listener, host: String, port: u16) -> Tunnel {
+    Tunnel::spawn(listener, async move |listener| {
+        let mut connections = JoinSet::new();
+        loop {
+            let Ok((mut socket, _, _)) = listener.accept().await else {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            };
+            while connections.try_join_next().is_some() {}
+
+            let address = (host.clone(), port);
+            // Connections that fail are closed, the database driver then reports them
+            connections.spawn(async move {
+                if let Ok(mut database) = TcpStream::connect(address).await {
+                    let _ = tokio::io::copy_bidirectional(&mut socket, &mut database).await;
+                }
+            });
+        }
+    })
+}
@@ -0,0 +1,300 @@
+mod diff;
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::Display;
+
+pub use diff::{Change, ColumnDiff, SchemaDiff, TableDiff};
+
+/// The structure of a database, as returned by [`Database::get_schema`](crate::Database::get_schema)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Schema {
+    pub tables: Vec<Table>,
+    /// User-defined enum and domain types, which columns may refer to
+    pub types: Vec<CustomType>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Table {
+    /// The schema (namespace) the table lives in, `None` for backends without schemas
+    pub schema: Option<String>,
+    pub name: String,
+    pub kind: RelationKind,
+    /// Columns in their declared order
+    pub columns: Vec<Column>,
+    /// Primary key columns in key order, empty if the table has no primary key
+    pub primary_key: Vec<String>,
+    /// The name of the primary key constraint, where the database reports it (Postgres)
+    pub primary_key_name: Option<String>,
+    pub unique_constraints: Vec<UniqueConstraint>,
+    /// Indexes that don't back a primary key or unique constraint
+    pub indexes: Vec<Index>,
+    pub foreign_keys: Vec<ForeignKey>,
+    pub comment: Option<String>,
+}
+
+/// What kind of relation a [`Table`] is
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RelationKind {
+    #[default]
+    Table,
+    PartitionedTable,
+    View,
+    MaterializedView,
+    ForeignTable,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Column {
+    pub name: String,
+    /// The type as the database spells it, e.g. `character varying(255)`
+    pub data_type: String,
+    pub nullable: bool,
+    /// The default expression, e.g. `now()`
+    pub default: Option<String>,
+    pub comment: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct UniqueConstraint {
+    pub name: String,
+    pub columns: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Index {
+    pub name: String,
+    /// Indexed columns, or expressions for expression indexes
+    pub columns: Vec<String>,
+    pub unique: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ForeignKey {
+    pub name: Option<String>,
+    /// Columns in this table, matched by position with `referenced_columns`
+    pub columns: Vec<String>,
+    pub referenced_schema: Option<String>,
+    pub referenced_table: String,
+    pub referenced_columns: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CustomType {
+    pub schema: Option<String>,
+    pub name: String,
+    #[serde(flatten)]
+    pub kind: CustomTypeKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum CustomTypeKind {
+    /// Labels in their sort order
+    Enum { labels: Vec<String> },
+    /// A base type with constraints on top, `checks` are the `CHECK (...)` clauses
+    Domain {
+        base_type: String,
+        nullable: bool,
+        default: Option<String>,
+        checks: Vec<String>,
+    },
+}
+
+impl Schema {
+    /// Look a table up by its schema-qualified name, or by its bare name
+    pub fn table(&self, name: &str) -> Option<&Table> {
+        self.tables
+            .iter()
+            .find(|table| table.qualified_name() == name)
+            .or_else(|| self.tables.iter().find(|table| table.name == name))
+    }
+}
+
+impl Table {
+    /// `schema.name`, or just the name if the table has no schema
+    pub fn qualified_name(&self) -> String {
+        qualified_name(self.schema.as_deref(), &self.name)
+    }
+
+    pub fn column(&self, name: &str) -> Option<&Column> {
+        self.columns.iter().find(|column| column.name == name)
+    }
+}
+
+impl ForeignKey {
+    pub fn qualified_referenced_table(&self) -> String {
+        qualified_name(self.referenced_schema.as_deref(), &self.referenced_table)
+    }
+}
+
+impl CustomType {
+    pub fn qualified_name(&self) -> String {
+        qualified_name(self.schema.as_deref(), &self.name)
+    }
+}
+
+fn qualified_name(schema: Option<&str>, name: &str) -> String {
+    match schema {
+        Some(schema) => format!("{schema}.{name}"),
+        None => name.to_string(),
+    }
+}
+
+/// Collects tables by name while the introspection queries are read, keeping first-seen order
+#[derive(Default)]
+pub(crate) struct SchemaBuilder {
+    tables: Vec<Table>,
+    positions: HashMap<(Option<String>, String), usize>,
+    types: Vec<CustomType>,
+}
+
+impl SchemaBuilder {
+    /// The table with this name, added if it hasn't been seen yet
+    pub(crate) fn table(&mut self, schema: Option<String>, name: String) -> &mut Table {
+        let key = (schema, name);
+        let position = match self.positions.get(&key) {
+            Some(&position) => position,
+            None => {
+                let (schema, name) = key.clone();
+                self.tables.push(Table {
+                    schema,
+                    name,
+                    ..Table::default()
+                });
+                self.positions.insert(key, self.tables.len() - 1);
+                self.tables.len() - 1
+            }
+        };
+
+        &mut self.tables[position]
+    }
+
+    /// The table with this name, if it has been seen
+    pub(crate) fn existing_table(
+        &mut self,
+        schema: Option<String>,
+        name: String,
+    ) -> Option<&mut Table> {
+        self.positions
+            .get(&(schema, name))
+            .map(|&position| &mut self.tables[position])
+    }
+
+    pub(crate) fn add_type(&mut self, custom_type: CustomType) {
+        self.types.push(custom_type);
+    }
+
+    pub(crate) fn build(self) -> Schema {
+        Schema {
+            tables: self.tables,
+            types: self.types,
+        }
+    }
+}
+
+/// A compact, DDL-like listing meant for prompts
+impl Display for Schema {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for custom_type in &self.types {
+            writeln!(f, "{custom_type}")?;
+        }
+
+        for table in &self.tables {
+            write!(f, "{}", table.qualified_name())?;
+            if table.kind != RelationKind::Table {
+                write!(f, " ({})", table.kind)?;
+            }
+            if let Some(comment) = &table.comment {
+                write!(f, " -- {comment}")?;
+            }
+            writeln!(f)?;
+
+            for column in &table.columns {
+                write!(f, "  {} {}", column.name, column.data_type)?;
+                if !column.nullable {
+                    write!(f, " NOT NULL")?;
+                }
+                if let Some(default) = &column.default {
+                    write!(f, " DEFAULT {default}")?;
+                }
+                if let Some(comment) = &column.comment {
+                    write!(f, " -- {comment}")?;
+                }
+                writeln!(f)?;
+            }
+
+            if !table.primary_key.is_empty() {
+                writeln!(f, "  PRIMARY KEY ({})", table.primary_key.join(", "))?;
+            }
+            for unique in &table.unique_constraints {
+                writeln!(f, "  UNIQUE ({})", unique.columns.join(", "))?;
+            }
+            for foreign_key in &table.foreign_keys {
+                writeln!(
+                    f,
+                    "  FOREIGN KEY ({}) REFERENCES {} ({})",
+                    foreign_key.columns.join(", "),
+                    foreign_key.qualified_referenced_table(),
+                    foreign_key.referenced_columns.join(", ")
+                )?;
+            }
+            for index in &table.indexes {
+                let unique = if index.unique { "UNIQUE " } else { "" };
+                writeln!(
+                    f,
+                    "  {unique}INDEX {} ({})",
+                    index.name,
+                    index.columns.join(", ")
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Display for RelationKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Table => write!(f, "table"),
+            Self::PartitionedTable => write!(f, "partitioned table"),
+            Self::View => write!(f, "view"),
+            Self::MaterializedView => write!(f, "materialized view"),
+            Self::ForeignTable => write!(f, "foreign table"),
+        }
+    }
+}
+
+impl Display for CustomType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            CustomTypeKind::Enum { labels } => {
+                let labels = labels
+                    .iter()
+                    .map(|label| format!("'{}'", label.replace('\'', "''")))
+                    .collect::<Vec<_>>();
+                write!(f, "ENUM {} ({})", self.qualified_name(), labels.join(", "))
+            }
+            CustomTypeKind::Domain {
+                base_type,
+                nullable,
+                default,
+                checks,
+            } => {
+                write!(f, "DOMAIN {} {base_type}", self.qualified_name())?;
+                if !nullable {
+                    write!(f, " NOT NULL")?;
+                }
+                if let Some(default) = default {
+                    write!(f, " DEFAULT {default}")?;
+                }
+                for check in checks {
+                    write!(f, " {check}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
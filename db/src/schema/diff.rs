@@ -0,0 +1,902 @@
+use super::{
+    Column, CustomType, CustomTypeKind, ForeignKey, Index, RelationKind, Schema, Table,
+    UniqueConstraint, qualified_name,
+};
+
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Write};
+
+/// A value that differs between the two schemas
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Change<T> {
+    pub from: T,
+    pub to: T,
+}
+
+impl<T: PartialEq + Clone> Change<T> {
+    fn between(from: &T, to: &T) -> Option<Self> {
+        (from != to).then(|| Self {
+            from: from.clone(),
+            to: to.clone(),
+        })
+    }
+}
+
+/// What turns one schema into another, as returned by [`Schema::diff`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SchemaDiff {
+    pub added_types: Vec<CustomType>,
+    pub removed_types: Vec<CustomType>,
+    pub changed_types: Vec<Change<CustomType>>,
+    pub added_tables: Vec<Table>,
+    pub removed_tables: Vec<Table>,
+    pub changed_tables: Vec<TableDiff>,
+}
+
+/// How a table that is in both schemas differs.
+///
+/// Constraints, indexes and foreign keys have no identity beyond their definition: one that
+/// changed is removed, and added again as it is now.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TableDiff {
+    pub schema: Option<String>,
+    pub name: String,
+    pub kind: Option<Change<RelationKind>>,
+    pub added_columns: Vec<Column>,
+    pub removed_columns: Vec<Column>,
+    pub changed_columns: Vec<ColumnDiff>,
+    pub primary_key: Option<Change<Vec<String>>>,
+    /// The name of the primary key constraint to drop, when the primary key changed
+    pub primary_key_name: Option<String>,
+    pub added_unique_constraints: Vec<UniqueConstraint>,
+    pub removed_unique_constraints: Vec<UniqueConstraint>,
+    pub added_indexes: Vec<Index>,
+    pub removed_indexes: Vec<Index>,
+    pub added_foreign_keys: Vec<ForeignKey>,
+    pub removed_foreign_keys: Vec<ForeignKey>,
+    pub comment: Option<Change<Option<String>>>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ColumnDiff {
+    pub name: String,
+    pub data_type: Option<Change<String>>,
+    pub nullable: Option<Change<bool>>,
+    pub default: Option<Change<Option<String>>>,
+    pub comment: Option<Change<Option<String>>>,
+}
+
+impl Schema {
+    /// What changes turn this schema into `to`. Tables and types are matched by their
+    /// schema-qualified name, columns by their name.
+    pub fn diff(&self, to: &Schema) -> SchemaDiff {
+        let mut diff = SchemaDiff {
+            added_types: to
+                .types
+                .iter()
+                .filter(|custom_type| find_type(self, custom_type).is_none())
+                .cloned()
+                .collect(),
+            added_tables: to
+                .tables
+                .iter()
+                .filter(|table| find_table(self, table).is_none())
+                .cloned()
+                .collect(),
+            ..SchemaDiff::default()
+        };
+
+        for custom_type in &self.types {
+            match find_type(to, custom_type) {
+                Some(other) => diff
+                    .changed_types
+                    .extend(Change::between(custom_type, other)),
+                None => diff.removed_types.push(custom_type.clone()),
+            }
+        }
+
+        for table in &self.tables {
+            match find_table(to, table) {
+                Some(other) => {
+                    let table_diff = table.diff(other);
+                    if !table_diff.is_empty() {
+                        diff.changed_tables.push(table_diff);
+                    }
+                }
+                None => diff.removed_tables.push(table.clone()),
+            }
+        }
+
+        diff
+    }
+}
+
+fn find_type<'s>(schema: &'s Schema, custom_type: &CustomType) -> Option<&'s CustomType> {
+    schema
+        .types
+        .iter()
+        .find(|other| other.schema == custom_type.schema && other.name == custom_type.name)
+}
+
+fn find_table<'s>(schema: &'s Schema, table: &Table) -> Option<&'s Table> {
+    schema
+        .tables
+        .iter()
+        .find(|other| other.schema == table.schema && other.name == table.name)
+}
+
+impl Table {
+    fn diff(&self, to: &Table) -> TableDiff {
+        let changed_columns = self
+            .columns
+            .iter()
+            .filter_map(|column| {
+                let other = to.column(&column.name)?;
+                let column_diff = ColumnDiff {
+                    name: column.name.clone(),
+                    data_type: Change::between(&column.data_type, &other.data_type),
+                    nullable: Change::between(&column.nullable, &other.nullable),
+                    default: Change::between(&column.default, &other.default),
+                    comment: Change::between(&column.comment, &other.comment),
+                };
+                let unchanged = ColumnDiff {
+                    name: column.name.clone(),
+                    ..ColumnDiff::default()
+                };
+                (column_diff != unchanged).then_some(column_diff)
+            })
+            .collect();
+
+        let primary_key = Change::between(&self.primary_key, &to.primary_key);
+        TableDiff {
+            schema: self.schema.clone(),
+            name: self.name.clone(),
+            kind: Change::between(&self.kind, &to.kind),
+            added_columns: to
+                .columns
+                .iter()
+                .filter(|column| self.column(&column.name).is_none())
+                .cloned()
+                .collect(),
+            removed_columns: self
+                .columns
+                .iter()
+                .filter(|column| to.column(&column.name).is_none())
+                .cloned()
+                .collect(),
+            changed_columns,
+            primary_key_name: primary_key
+                .as_ref()
+                .and_then(|_| self.primary_key_name.clone()),
+            primary_key,
+            added_unique_constraints: missing(&to.unique_constraints, &self.unique_constraints),
+            removed_unique_constraints: missing(&self.unique_constraints, &to.unique_constraints),
+            added_indexes: missing(&to.indexes, &self.indexes),
+            removed_indexes: missing(&self.indexes, &to.indexes),
+            added_foreign_keys: missing(&to.foreign_keys, &self.foreign_keys),
+            removed_foreign_keys: missing(&self.foreign_keys, &to.foreign_keys),
+            comment: Change::between(&self.comment, &to.comment),
+        }
+    }
+}
+
+/// Items of `items` that aren't in `other`
+fn missing<T: PartialEq + Clone>(items: &[T], other: &[T]) -> Vec<T> {
+    items
+        .iter()
+        .filter(|item| !other.contains(item))
+        .cloned()
+        .collect()
+}
+
+impl SchemaDiff {
+    /// Whether the schemas are the same
+    pub fn is_empty(&self) -> bool {
+        self == &SchemaDiff::default()
+    }
+
+    /// A Postgres script that turns the `from` schema into the `to` schema.
+    ///
+    /// What can't be derived from an introspected schema is left as a comment to be done by
+    /// hand: view definitions, partition keys, enum labels that were removed or reordered, and
+    /// changed domain checks. Indexes are created as plain B-tree indexes without a predicate.
+    pub fn postgres_migration(&self) -> String {
+        let mut script = Script::default();
+
+        for custom_type in &self.added_types {
+            script.create_type(custom_type);
+        }
+        for change in &self.changed_types {
+            script.alter_type(&change.from, &change.to);
+        }
+
+        // Constraints go first, the columns they cover may be dropped or changed below
+        for table in &self.changed_tables {
+            let name = qualified(table.schema.as_deref(), &table.name);
+            for foreign_key in &table.removed_foreign_keys {
+                script.drop_constraint(&name, foreign_key.name.as_deref(), "foreign key");
+            }
+            for index in &table.removed_indexes {
+                script.statement(format!(
+                    "DROP INDEX {}",
+                    qualified(table.schema.as_deref(), &index.name)
+                ));
+            }
+            for unique in &table.removed_unique_constraints {
+                script.drop_constraint(&name, Some(&unique.name), "unique constraint");
+            }
+            if table
+                .primary_key
+                .as_ref()
+                .is_some_and(|change| !change.from.is_empty())
+            {
+                let primary_key = table.primary_key_name.as_deref();
+                script.drop_constraint(&name, primary_key, "primary key");
+            }
+        }
+
+        for table in &self.added_tables {
+            script.create_table(table);
+        }
+        for table in &self.changed_tables {
+            script.alter_table(table);
+        }
+
+        // Foreign keys last, once every table and key they refer to is there
+        for table in &self.added_tables {
+            script.add_indexes(table.schema.as_deref(), &table.name, &table.indexes);
+        }
+        for table in &self.changed_tables {
+            script.add_indexes(table.schema.as_deref(), &table.name, &table.added_indexes);
+        }
+        for table in &self.added_tables {
+            script.add_foreign_keys(table.schema.as_deref(), &table.name, &table.foreign_keys);
+        }
+        for table in &self.changed_tables {
+            let foreign_keys = &table.added_foreign_keys;
+            script.add_foreign_keys(table.schema.as_deref(), &table.name, foreign_keys);
+        }
+
+        // Views may depend on tables, so they go first. Dropping the tables of each kind at
+        // once takes care of foreign keys between them.
+        for kind in [
+            RelationKind::View,
+            RelationKind::MaterializedView,
+            RelationKind::ForeignTable,
+            RelationKind::Table,
+        ] {
+            let names = self
+                .removed_tables
+                .iter()
+                .filter(|table| match kind {
+                    RelationKind::Table => {
+                        matches!(
+                            table.kind,
+                            RelationKind::Table | RelationKind::PartitionedTable
+                        )
+                    }
+                    kind => table.kind == kind,
+                })
+                .map(|table| qualified(table.schema.as_deref(), &table.name))
+                .collect::<Vec<_>>();
+            if !names.is_empty() {
+                script.statement(format!("DROP {} {}", keyword(kind), names.join(", ")));
+            }
+        }
+
+        for custom_type in &self.removed_types {
+            let keyword = match custom_type.kind {
+                CustomTypeKind::Enum { .. } => "TYPE",
+                CustomTypeKind::Domain { .. } => "DOMAIN",
+            };
+            let name = qualified(custom_type.schema.as_deref(), &custom_type.name);
+            script.statement(format!("DROP {keyword} {name}"));
+        }
+
+        script.sql
+    }
+}
+
+impl TableDiff {
+    pub fn qualified_name(&self) -> String {
+        qualified_name(self.schema.as_deref(), &self.name)
+    }
+
+    fn is_empty(&self) -> bool {
+        self == &TableDiff {
+            schema: self.schema.clone(),
+            name: self.name.clone(),
+            ..TableDiff::default()
+        }
+    }
+}
+
+/// The SQL keyword for a kind of relation
+fn keyword(kind: RelationKind) -> &'static str {
+    match kind {
+        RelationKind::Table | RelationKind::PartitionedTable => "TABLE",
+        RelationKind::View => "VIEW",
+        RelationKind::MaterializedView => "MATERIALIZED VIEW",
+        RelationKind::ForeignTable => "FOREIGN TABLE",
+    }
+}
+
+/// A migration script as it is written
+#[derive(Default)]
+struct Script {
+    sql: String,
+    /// Sequences that column defaults use, created before the first of them
+    sequences: Vec<String>,
+}
+
+impl Script {
+    fn statement(&mut self, statement: impl Display) {
+        let _ = writeln!(self.sql, "{statement};");
+    }
+
+    /// Something that has to be done by hand
+    fn manual(&mut self, note: impl Display) {
+        let _ = writeln!(self.sql, "-- TODO: {note}");
+    }
+
+    fn create_type(&mut self, custom_type: &CustomType) {
+        let name = qualified(custom_type.schema.as_deref(), &custom_type.name);
+        match &custom_type.kind {
+            CustomTypeKind::Enum { labels } => {
+                let labels = labels
+                    .iter()
+                    .map(|label| literal(label))
+                    .collect::<Vec<_>>();
+                self.statement(format!(
+                    "CREATE TYPE {name} AS ENUM ({})",
+                    labels.join(", ")
+                ));
+            }
+            CustomTypeKind::Domain {
+                base_type,
+                nullable,
+                default,
+                checks,
+            } => {
+                let mut statement = format!("CREATE DOMAIN {name} AS {base_type}");
+                if !nullable {
+                    statement.push_str(" NOT NULL");
+                }
+                if let Some(default) = default {
+                    let _ = write!(statement, " DEFAULT {default}");
+                }
+                for check in checks {
+                    let _ = write!(statement, " {check}");
+                }
+                self.statement(statement);
+            }
+        }
+    }
+
+    fn alter_type(&mut self, from: &CustomType, to: &CustomType) {
+        let name = qualified(to.schema.as_deref(), &to.name);
+        match (&from.kind, &to.kind) {
+            (CustomTypeKind::Enum { labels: from }, CustomTypeKind::Enum { labels: to_labels }) => {
+                // Labels can only be added, in between the existing ones
+                let kept = to_labels
+                    .iter()
+                    .filter(|label| from.contains(label))
+                    .collect::<Vec<_>>();
+                if kept.len() != from.len() || kept.iter().zip(from).any(|(a, b)| *a != b) {
+                    self.manual(format!(
+                        "labels of {name} were removed or reordered, which needs a new type: {to}"
+                    ));
+                    return;
+                }
+                for (position, label) in to_labels.iter().enumerate() {
+                    if from.contains(label) {
+                        continue;
+                    }
+                    let place = match position.checked_sub(1) {
+                        Some(previous) => format!("AFTER {}", literal(&to_labels[previous])),
+                        None if to_labels.len() > 1 => format!("BEFORE {}", literal(&to_labels[1])),
+                        None => String::new(),
+                    };
+                    let statement =
+                        format!("ALTER TYPE {name} ADD VALUE {} {place}", literal(label));
+                    self.statement(statement.trim_end());
+                }
+            }
+            (
+                CustomTypeKind::Domain {
+                    base_type: from_base,
+                    nullable: from_nullable,
+                    default: from_default,
+                    checks: from_checks,
+                },
+                CustomTypeKind::Domain {
+                    base_type,
+                    nullable,
+                    default,
+                    checks,
+                },
+            ) if from_base == base_type => {
+                if from_nullable != nullable {
+                    let change = if *nullable { "DROP" } else { "SET" };
+                    self.statement(format!("ALTER DOMAIN {name} {change} NOT NULL"));
+                }
+                if from_default != default {
+                    match default {
+                        Some(default) => {
+                            self.statement(format!("ALTER DOMAIN {name} SET DEFAULT {default}"));
+                        }
+                        None => self.statement(format!("ALTER DOMAIN {name} DROP DEFAULT")),
+                    }
+                }
+                for check in missing(checks, from_checks) {
+                    self.statement(format!("ALTER DOMAIN {name} ADD {check}"));
+                }
+                for check in missing(from_checks, checks) {
+                    self.manual(format!("drop `{check}` from {name}"));
+                }
+            }
+            _ => self.manual(format!("{name} changed from {from} to {to}")),
+        }
+    }
+
+    fn drop_constraint(&mut self, table: &str, name: Option<&str>, what: &str) {
+        match name {
+            Some(name) => self.statement(format!(
+                "ALTER TABLE {table} DROP CONSTRAINT {}",
+                ident(name)
+            )),
+            None => self.manual(format!("drop the unnamed {what} of {table}")),
+        }
+    }
+
+    fn create_table(&mut self, table: &Table) {
+        let name = qualified(table.schema.as_deref(), &table.name);
+        match table.kind {
+            RelationKind::Table => {}
+            RelationKind::PartitionedTable => {
+                self.manual(format!(
+                    "create the partitioned table {name} with its partition key"
+                ));
+                return;
+            }
+            kind => {
+                self.manual(format!("create the {kind} {name}"));
+                return;
+            }
+        }
+
+        for column in &table.columns {
+            self.sequence(column.default.as_deref());
+        }
+        let mut definitions = table
+            .columns
+            .iter()
+            .map(column_definition)
+            .collect::<Vec<_>>();
+        if !table.primary_key.is_empty() {
+            definitions.push(format!("PRIMARY KEY ({})", idents(&table.primary_key)));
+        }
+        for unique in &table.unique_constraints {
+            definitions.push(format!(
+                "CONSTRAINT {} UNIQUE ({})",
+                ident(&unique.name),
+                idents(&unique.columns)
+            ));
+        }
+        self.statement(format!(
+            "CREATE TABLE {name} (\n    {}\n)",
+            definitions.join(",\n    ")
+        ));
+
+        if let Some(comment) = &table.comment {
+            self.comment("TABLE", &name, Some(comment));
+        }
+        for column in &table.columns {
+            self.column_comment(&name, column);
+        }
+    }
+
+    fn alter_table(&mut self, table: &TableDiff) {
+        let name = qualified(table.schema.as_deref(), &table.name);
+        if let Some(change) = &table.kind {
+            self.manual(format!(
+                "{name} changed from a {} to a {}",
+                change.from, change.to
+            ));
+        }
+
+        for column in &table.added_columns {
+            self.sequence(column.default.as_deref());
+            self.statement(format!(
+                "ALTER TABLE {name} ADD COLUMN {}",
+                column_definition(column)
+            ));
+            self.column_comment(&name, column);
+        }
+
+        for column in &table.changed_columns {
+            let alter = format!("ALTER TABLE {name} ALTER COLUMN {}", ident(&column.name));
+            if let Some(change) = &column.data_type {
+                self.statement(format!(
+                    "{alter} TYPE {} USING {}::{}",
+                    change.to,
+                    ident(&column.name),
+                    change.to
+                ));
+            }
+            if let Some(change) = &column.nullable {
+                let change = if change.to { "DROP" } else { "SET" };
+                self.statement(format!("{alter} {change} NOT NULL"));
+            }
+            if let Some(change) = &column.default {
+                match &change.to {
+                    Some(default) => {
+                        self.sequence(Some(default));
+                        self.statement(format!("{alter} SET DEFAULT {default}"));
+                    }
+                    None => self.statement(format!("{alter} DROP DEFAULT")),
+                }
+            }
+            if let Some(change) = &column.comment {
+                let column_name = format!("{name}.{}", ident(&column.name));
+                self.comment("COLUMN", &column_name, change.to.as_deref());
+            }
+        }
+
+        for column in &table.removed_columns {
+            self.statement(format!(
+                "ALTER TABLE {name} DROP COLUMN {}",
+                ident(&column.name)
+            ));
+        }
+
+        if let Some(change) = &table.primary_key
+            && !change.to.is_empty()
+        {
+            self.statement(format!(
+                "ALTER TABLE {name} ADD PRIMARY KEY ({})",
+                idents(&change.to)
+            ));
+        }
+        for unique in &table.added_unique_constraints {
+            self.statement(format!(
+                "ALTER TABLE {name} ADD CONSTRAINT {} UNIQUE ({})",
+                ident(&unique.name),
+                idents(&unique.columns)
+            ));
+        }
+        if let Some(change) = &table.comment {
+            self.comment("TABLE", &name, change.to.as_deref());
+        }
+    }
+
+    fn add_indexes(&mut self, schema: Option<&str>, table: &str, indexes: &[Index]) {
+        for index in indexes {
+            let unique = if index.unique { "UNIQUE " } else { "" };
+            // The columns are as Postgres prints them, quoted where needed
+            self.statement(format!(
+                "CREATE {unique}INDEX {} ON {} ({})",
+                ident(&index.name),
+                qualified(schema, table),
+                index.columns.join(", ")
+            ));
+        }
+    }
+
+    fn add_foreign_keys(&mut self, schema: Option<&str>, table: &str, foreign_keys: &[ForeignKey]) {
+        for foreign_key in foreign_keys {
+            let constraint = match &foreign_key.name {
+                Some(name) => format!("CONSTRAINT {} ", ident(name)),
+                None => String::new(),
+            };
+            self.statement(format!(
+                "ALTER TABLE {} ADD {constraint}FOREIGN KEY ({}) REFERENCES {} ({})",
+                qualified(schema, table),
+                idents(&foreign_key.columns),
+                qualified(
+                    foreign_key.referenced_schema.as_deref(),
+                    &foreign_key.referenced_table
+                ),
+                idents(&foreign_key.referenced_columns)
+            ));
+        }
+    }
+
+    /// Set the comment, `None` removes it
+    fn comment(&mut self, on: &str, name: &str, comment: Option<&str>) {
+        let comment = comment.map_or_else(|| "NULL".to_string(), literal);
+        self.statement(format!("COMMENT ON {on} {name} IS {comment}"));
+    }
+
+    /// The comment of a new column, if it has one
+    fn column_comment(&mut self, table: &str, column: &Column) {
+        if let Some(comment) = &column.comment {
+            let column_name = format!("{table}.{}", ident(&column.name));
+            self.comment("COLUMN", &column_name, Some(comment));
+        }
+    }
+
+    /// `serial` columns default to a sequence, which has to be there before the column
+    fn sequence(&mut self, default: Option<&str>) {
+        let Some(sequence) = default
+            .and_then(|default| default.strip_prefix("nextval('"))
+            .and_then(|rest| rest.split_once("'::regclass)"))
+            .map(|(sequence, _)| sequence.replace("''", "'"))
+        else {
+            return;
+        };
+        if !self.sequences.contains(&sequence) {
+            self.statement(format!("CREATE SEQUENCE IF NOT EXISTS {sequence}"));
+            self.sequences.push(sequence);
+        }
+    }
+}
+
+fn column_definition(column: &Column) -> String {
+    let mut definition = format!("{} {}", ident(&column.name), column.data_type);
+    if !column.nullable {
+        definition.push_str(" NOT NULL");
+    }
+    if let Some(default) = &column.default {
+        let _ = write!(definition, " DEFAULT {default}");
+    }
+    definition
+}
+
+/// Postgres' reserved key words, which can't be used as identifiers without quotes
+const RESERVED: &[&str] = &[
+    "all",
+    "analyse",
+    "analyze",
+    "and",
+    "any",
+    "array",
+    "as",
+    "asc",
+    "asymmetric",
+    "both",
+    "case",
+    "cast",
+    "check",
+    "collate",
+    "column",
+    "constraint",
+    "create",
+    "current_catalog",
+    "current_date",
+    "current_role",
+    "current_time",
+    "current_timestamp",
+    "current_user",
+    "default",
+    "deferrable",
+    "desc",
+    "distinct",
+    "do",
+    "else",
+    "end",
+    "except",
+    "false",
+    "fetch",
+    "for",
+    "foreign",
+    "from",
+    "grant",
+    "group",
+    "having",
+    "in",
+    "initially",
+    "intersect",
+    "into",
+    "lateral",
+    "leading",
+    "limit",
+    "localtime",
+    "localtimestamp",
+    "not",
+    "null",
+    "offset",
+    "on",
+    "only",
+    "or",
+    "order",
+    "placing",
+    "primary",
+    "references",
+    "returning",
+    "select",
+    "session_user",
+    "some",
+    "symmetric",
+    "system_user",
+    "table",
+    "then",
+    "to",
+    "trailing",
+    "true",
+    "union",
+    "unique",
+    "user",
+    "using",
+    "variadic",
+    "when",
+    "where",
+    "window",
+    "with",
+];
+
+/// An identifier, quoted unless it can be written as it is
+fn ident(name: &str) -> String {
+    let plain = name
+        .bytes()
+        .next()
+        .is_some_and(|byte| byte.is_ascii_lowercase() || byte == b'_')
+        && name
+            .bytes()
+            .all(|byte| byte.is_ascii_lowercase() || byte.is_ascii_digit() || byte == b'_')
+        && !RESERVED.contains(&name);
+    if plain {
+        name.to_string()
+    } else {
+        format!("\"{}\"", name.replace('"', "\"\""))
+    }
+}
+
+fn idents(names: &[String]) -> String {
+    names
+        .iter()
+        .map(|name| ident(name))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn qualified(schema: Option<&str>, name: &str) -> String {
+    match schema {
+        Some(schema) => format!("{}.{}", ident(schema), ident(name)),
+        None => ident(name),
+    }
+}
+
+fn literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// A listing of the differences, `+` for what was added, `-` for what was removed and `~` for
+/// what changed
+impl Display for SchemaDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for custom_type in &self.added_types {
+            writeln!(f, "+ {custom_type}")?;
+        }
+        for custom_type in &self.removed_types {
+            writeln!(f, "- {custom_type}")?;
+        }
+        for change in &self.changed_types {
+            writeln!(f, "~ {}", change.to.qualified_name())?;
+            writeln!(f, "    from {}", change.from)?;
+            writeln!(f, "    to   {}", change.to)?;
+        }
+
+        for table in &self.added_tables {
+            writeln!(f, "+ {} {}", table.kind, table.qualified_name())?;
+        }
+        for table in &self.removed_tables {
+            writeln!(f, "- {} {}", table.kind, table.qualified_name())?;
+        }
+        for table in &self.changed_tables {
+            write!(f, "{table}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Display for TableDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "~ {}", self.qualified_name())?;
+        if let Some(change) = &self.kind {
+            writeln!(f, "    kind: {} -> {}", change.from, change.to)?;
+        }
+
+        for column in &self.added_columns {
+            writeln!(f, "    + column {}", column_definition(column))?;
+        }
+        for column in &self.removed_columns {
+            writeln!(f, "    - column {}", column_definition(column))?;
+        }
+        for column in &self.changed_columns {
+            writeln!(f, "    ~ column {column}")?;
+        }
+
+        if let Some(change) = &self.primary_key {
+            writeln!(
+                f,
+                "    primary key: ({}) -> ({})",
+                change.from.join(", "),
+                change.to.join(", ")
+            )?;
+        }
+        for (sign, uniques) in [
+            ('+', &self.added_unique_constraints),
+            ('-', &self.removed_unique_constraints),
+        ] {
+            for unique in uniques {
+                let columns = unique.columns.join(", ");
+                writeln!(f, "    {sign} UNIQUE {} ({columns})", unique.name)?;
+            }
+        }
+        for (sign, indexes) in [('+', &self.added_indexes), ('-', &self.removed_indexes)] {
+            for index in indexes {
+                let unique = if index.unique { "UNIQUE " } else { "" };
+                let columns = index.columns.join(", ");
+                writeln!(f, "    {sign} {unique}INDEX {} ({columns})", index.name)?;
+            }
+        }
+        for (sign, foreign_keys) in [
+            ('+', &self.added_foreign_keys),
+            ('-', &self.removed_foreign_keys),
+        ] {
+            for foreign_key in foreign_keys {
+                let name = foreign_key
+                    .name
+                    .as_ref()
+                    .map(|name| format!("{name} "))
+                    .unwrap_or_default();
+                writeln!(
+                    f,
+                    "    {sign} FOREIGN KEY {name}({}) REFERENCES {} ({})",
+                    foreign_key.columns.join(", "),
+                    foreign_key.qualified_referenced_table(),
+                    foreign_key.referenced_columns.join(", ")
+                )?;
+            }
+        }
+
+        if let Some(change) = &self.comment {
+            writeln!(
+                f,
+                "    comment: {} -> {}",
+                optional(&change.from),
+                optional(&change.to)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The changes to a column, e.g. `age: type integer -> bigint, NOT NULL -> NULL`
+impl Display for ColumnDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut changes = vec![];
+        if let Some(change) = &self.data_type {
+            changes.push(format!("type {} -> {}", change.from, change.to));
+        }
+        if let Some(change) = &self.nullable {
+            let nullability = |nullable| if nullable { "NULL" } else { "NOT NULL" };
+            changes.push(format!(
+                "{} -> {}",
+                nullability(change.from),
+                nullability(change.to)
+            ));
+        }
+        if let Some(change) = &self.default {
+            changes.push(format!(
+                "default {} -> {}",
+                optional(&change.from),
+                optional(&change.to)
+            ));
+        }
+        if let Some(change) = &self.comment {
+            changes.push(format!(
+                "comment {} -> {}",
+                optional(&change.from),
+                optional(&change.to)
+            ));
+        }
+        write!(f, "{}: {}", self.name, changes.join(", "))
+    }
+}
+
+fn optional(value: &Option<String>) -> &str {
+    value.as_deref().unwrap_or("none")
+}
@@ -0,0 +1,365 @@
+use crate::statement::{dollar_tag, find, first_keyword, is_word_byte, tokens};
+use crate::{Backend, Database, DbError, ExecuteOutcome, Session, Transaction};
+
+use std::time::{Duration, Instant};
+
+/// A statement of a script, without its terminating `;`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptStatement {
+    /// Line of the script the statement starts on, from 1
+    pub line: usize,
+    pub sql: String,
+}
+
+/// How [`Database::run_script`] runs the statements of a script
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ScriptOptions {
+    /// Run the whole script in a transaction that is only committed if every statement
+    /// succeeds. The script then always stops at the first error.
+    ///
+    /// A plain `BEGIN` at the start of the script and `COMMIT` at its end are left out, since
+    /// they do what the transaction does. A script that controls transactions in any other
+    /// way, e.g. with `BEGIN ISOLATION LEVEL SERIALIZABLE`, is run as it is written instead,
+    /// still stopping at the first error.
+    pub transaction: bool,
+    /// Stop at the first failing statement instead of running the rest
+    pub stop_on_error: bool,
+}
+
+/// What a script did, statement by statement
+#[derive(Debug, Default)]
+pub struct ScriptOutcome {
+    /// The statements that were run, in order
+    pub statements: Vec<StatementOutcome>,
+    /// Statements left out after the script stopped at an error
+    pub skipped: usize,
+    /// Whether the wrapping transaction was rolled back because of an error
+    pub rolled_back: bool,
+    pub elapsed: Duration,
+}
+
+impl ScriptOutcome {
+    pub fn failed(&self) -> usize {
+        self.statements
+            .iter()
+            .filter(|statement| statement.result.is_err())
+            .count()
+    }
+}
+
+#[derive(Debug)]
+pub struct StatementOutcome {
+    pub statement: ScriptStatement,
+    pub result: Result<ExecuteOutcome, DbError>,
+    pub elapsed: Duration,
+}
+
+/// Split a script into its statements at the `;`s between them.
+///
+/// Semicolons in string literals, quoted identifiers, comments, parentheses, Postgres
+/// dollar-quoted bodies and `BEGIN ATOMIC ... END` bodies, as well as SQLite trigger bodies,
+/// don't end a statement. Pieces that hold nothing but comments are left out. MySQL's
+/// `DELIMITER` is a client command and isn't understood.
+pub fn split_statements(script: &str, backend: Backend) -> Vec<ScriptStatement> {
+    let bytes = script.as_bytes();
+    let mut statements = vec![];
+
+    let mut start = 0;
+    let mut has_code = false;
+    let mut parens = 0usize;
+    // `BEGIN ... END` bodies, with the `CASE ... END` expressions inside them
+    let mut blocks = 0usize;
+    let mut first_word = String::new();
+    let mut last_word = String::new();
+    let mut trigger = false;
+
+    let mut line = 1;
+    let mut counted = 0;
+
+    let mut i = 0;
+    while i < bytes.len() {
+        let byte = bytes[i];
+        let next = bytes.get(i + 1).copied();
+
+        // Comments
+        match byte {
+            b'-' if next == Some(b'-')
+                && (backend != Backend::MySql
+                    || bytes.get(i + 2).is_none_or(u8::is_ascii_whitespace)) =>
+            {
+                i = line_end(bytes, i);
+                continue;
+            }
+            b'#' if backend == Backend::MySql => {
+                i = line_end(bytes, i);
+                continue;
+            }
+            b'/' if next == Some(b'*') => {
+                i = block_comment_end(bytes, i, backend == Backend::Postgres);
+                continue;
+            }
+            _ => {}
+        }
+
+        if byte == b';' && parens == 0 && blocks == 0 {
+            if has_code {
+                let sql = script[start..i].trim();
+                let offset = i - script[start..i].trim_start().len();
+                line += script[counted..offset].matches('\n').count();
+                counted = offset;
+                statements.push(ScriptStatement {
+                    line,
+                    sql: sql.to_string(),
+                });
+            }
+
+            start = i + 1;
+            has_code = false;
+            first_word.clear();
+            last_word.clear();
+            trigger = false;
+            i += 1;
+            continue;
+        }
+
+        if !byte.is_ascii_whitespace() {
+            has_code = true;
+        }
+
+        i = match byte {
+            b'\'' => {
+                // Backslashes escape in MySQL strings, and in Postgres only in `E'...'`
+                let backslash = match backend {
+                    Backend::MySql => true,
+                    Backend::Postgres => {
+                        i > 0
+                            && bytes[i - 1].eq_ignore_ascii_case(&b'e')
+                            && (i < 2 || !is_word_byte(bytes[i - 2]))
+                    }
+                    Backend::Sqlite => false,
+                };
+                quoted_end(bytes, i, b'\'', backslash)
+            }
+            b'"' => quoted_end(bytes, i, b'"', backend == Backend::MySql),
+            b'`' if backend != Backend::Postgres => quoted_end(bytes, i, b'`', false),
+            b'[' if backend == Backend::Sqlite => quoted_end(bytes, i, b']', false),
+            b'$' if backend == Backend::Postgres && (i == 0 || !is_word_byte(bytes[i - 1])) => {
+                match dollar_tag(bytes, i) {
+                    Some(tag) => {
+                        find(bytes, i + tag.len(), tag).map_or(bytes.len(), |end| end + tag.len())
+                    }
+                    None => i + 1,
+                }
+            }
+            b'(' => {
+                parens += 1;
+                i + 1
+            }
+            b')' => {
+                parens = parens.saturating_sub(1);
+                i + 1
+            }
+            byte if is_word_byte(byte) && !byte.is_ascii_digit() => {
+                let end = (i..bytes.len())
+                    .find(|&j| !is_word_byte(bytes[j]))
+                    .unwrap_or(bytes.len());
+                let word = script[i..end].to_uppercase();
+
+                match word.as_str() {
+                    "CASE" if blocks > 0 => blocks += 1,
+                    "END" if blocks > 0 => blocks -= 1,
+                    "ATOMIC" if backend == Backend::Postgres && last_word == "BEGIN" => {
+                        blocks += 1;
+                    }
+                    "BEGIN" if backend == Backend::Sqlite && first_word == "CREATE" && trigger => {
+                        blocks += 1;
+                    }
+                    "TRIGGER" => trigger = true,
+                    _ => {}
+                }
+
+                if first_word.is_empty() {
+                    first_word.clone_from(&word);
+                }
+                last_word = word;
+                end
+            }
+            _ => i + 1,
+        };
+    }
+
+    if has_code {
+        let sql = script[start..].trim();
+        let offset = script.len() - script[start..].trim_start().len();
+        line += script[counted..offset].matches('\n').count();
+        statements.push(ScriptStatement {
+            line,
+            sql: sql.to_string(),
+        });
+    }
+
+    statements
+}
+
+fn line_end(bytes: &[u8], i: usize) -> usize {
+    find(bytes, i, b"\n").unwrap_or(bytes.len())
+}
+
+/// The end of the comment that starts at `i`. Postgres comments nest, MySQL's and SQLite's
+/// end at the first `*/`.
+fn block_comment_end(bytes: &[u8], i: usize, nested: bool) -> usize {
+    let mut depth = 0;
+    let mut j = i;
+    while j < bytes.len() {
+        match &bytes[j..] {
+            [b'/', b'*', ..] if nested || depth == 0 => {
+                depth += 1;
+                j += 2;
+            }
+            [b'*', b'/', ..] => {
+                depth -= 1;
+                j += 2;
+                if depth == 0 {
+                    return j;
+                }
+            }
+            _ => j += 1,
+        }
+    }
+    bytes.len()
+}
+
+/// The end of the quoted string or identifier that starts at `i`, a doubled quote is part of it
+fn quoted_end(bytes: &[u8], i: usize, quote: u8, backslash: bool) -> usize {
+    let mut j = i + 1;
+    while j < bytes.len() {
+        match bytes[j] {
+            b'\\' if backslash => j += 2,
+            byte if byte == quote => {
+                if bytes.get(j + 1) == Some(&quote) && quote != b']' {
+                    j += 2;
+                } else {
+                    return j + 1;
+                }
+            }
+            _ => j += 1,
+        }
+    }
+    bytes.len()
+}
+
+/// Whether the statement is a `BEGIN` or `COMMIT` without options, which does no more than
+/// the wrapping transaction
+fn plain(statement: &ScriptStatement, keywords: &[&str]) -> bool {
+    let tokens = tokens(&statement.sql);
+    let words = tokens
+        .iter()
+        .map(|token| token.text.as_str())
+        .collect::<Vec<_>>();
+    match words.as_slice() {
+        ["START", "TRANSACTION"] => keywords.contains(&"START"),
+        ["START", ..] => false,
+        [word] | [word, "TRANSACTION" | "WORK"] => keywords.contains(word),
+        _ => false,
+    }
+}
+
+/// Whether the statement starts or ends a transaction
+fn controls_transaction(statement: &ScriptStatement) -> bool {
+    matches!(
+        first_keyword(&statement.sql).as_str(),
+        "BEGIN" | "START" | "COMMIT" | "END" | "ROLLBACK" | "ABORT"
+    )
+}
+
+/// Where the statements of a script run
+enum Runner {
+    Session(Box<dyn Session>),
+    Transaction(Box<dyn Transaction>),
+}
+
+impl Runner {
+    async fn execute(&mut self, query: &str) -> Result<ExecuteOutcome, DbError> {
+        match self {
+            Self::Session(session) => session.execute(query).await,
+            Self::Transaction(transaction) => transaction.execute(query).await,
+        }
+    }
+}
+
+/// See [`Database::run_script`]
+pub(crate) async fn run_script<D: Database + ?Sized>(
+    database: &D,
+    script: &str,
+    options: ScriptOptions,
+) -> Result<ScriptOutcome, DbError> {
+    let started = Instant::now();
+    let mut statements = split_statements(script, database.backend());
+
+    // A script's own transaction around all of it is the same as the wrapping one, any other
+    // can only be run as written
+    let mut transaction = options.transaction;
+    if transaction {
+        let inner = match &statements[..] {
+            [first, inner @ .., last]
+                if plain(first, &["BEGIN", "START"]) && plain(last, &["COMMIT", "END"]) =>
+            {
+                inner
+            }
+            all => all,
+        };
+        transaction = !inner.iter().any(controls_transaction);
+        if transaction && inner.len() < statements.len() {
+            statements.pop();
+            statements.remove(0);
+        }
+    }
+
+    // A session of its own keeps `SET`s and temporary tables around for later statements
+    let mut runner = if transaction {
+        Runner::Transaction(database.begin().await?)
+    } else {
+        Runner::Session(database.session().await?)
+    };
+    let stop_on_error = options.stop_on_error || options.transaction;
+    let controls_transactions = statements.iter().any(controls_transaction);
+
+    let mut outcome = ScriptOutcome::default();
+    let total = statements.len();
+    for statement in statements {
+        let statement_started = Instant::now();
+        let result = runner.execute(&statement.sql).await;
+        let failed = result.is_err();
+        outcome.statements.push(StatementOutcome {
+            statement,
+            result,
+            elapsed: statement_started.elapsed(),
+        });
+
+        if failed && stop_on_error {
+            outcome.skipped = total - outcome.statements.len();
+            break;
+        }
+    }
+
+    match runner {
+        Runner::Transaction(transaction) => {
+            if outcome.failed() > 0 {
+                transaction.rollback().await?;
+                outcome.rolled_back = true;
+            } else {
+                transaction.commit().await?;
+            }
+        }
+        // A script that stopped in the middle of its own transaction, or never ended it, would
+        // leave it open on a pooled connection. Without one, rolling back does nothing or fails
+        // harmlessly.
+        Runner::Session(mut session) if controls_transactions => {
+            let _ = session.execute("ROLLBACK").await;
+        }
+        Runner::Session(_) => {}
+    }
+
+    outcome.elapsed = started.elapsed();
+    Ok(outcome)
+}
@@ -0,0 +1,477 @@
+use crate::param::{BindParam, bind_params, check_params};
+use crate::schema::{
+    Column, ForeignKey, Index, RelationKind, Schema, SchemaBuilder, UniqueConstraint,
+};
+use crate::statement::command_tag;
+use crate::stream::{CancelHandle, stream_results};
+use crate::transaction::{Changes, check_statement, modifies_rows, preview};
+use crate::{
+    Backend, DatabaseResult, DbError, ExecuteOutcome, Param, QueryDescription, RowStream,
+    StreamLimits, connect_pool, hex, pool_options,
+};
+
+use super::{Database, Session, Transaction};
+use config::{Access, DatabaseConnection, TlsConfig};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use sqlx::pool::PoolConnection;
+use sqlx::query::Query;
+use sqlx::sqlite::{
+    SqliteArguments, SqliteConnectOptions, SqliteQueryResult, SqliteRow, SqliteTypeInfo,
+};
+use sqlx::{Column as _, Executor, Row, Sqlite, SqliteConnection, SqlitePool, TypeInfo, ValueRef};
+use std::time::{Duration, Instant};
+
+pub struct SqliteDatabase {
+    pool: SqlitePool,
+}
+
+/// A single connection taken from the pool for as long as the session lives
+pub struct SqliteSession {
+    connection: PoolConnection<Sqlite>,
+}
+
+/// A transaction on a connection of its own, rolled back when dropped
+pub struct SqliteTransaction {
+    transaction: sqlx::Transaction<'static, Sqlite>,
+}
+
+impl SqliteDatabase {
+    pub async fn new(connection: &DatabaseConnection) -> Result<Self, DbError> {
+        if connection.ssh.is_some() {
+            return Err(DbError::Configuration(
+                "SQLite databases are files, they can't be reached through SSH".to_string(),
+            ));
+        }
+        if connection.tls != TlsConfig::default() {
+            return Err(DbError::Configuration(
+                "SQLite databases are files, they don't use TLS".to_string(),
+            ));
+        }
+
+        let mut options: SqliteConnectOptions = connection.url.parse()?;
+        // SQLite can't time out statements, but it can stop waiting for a locked database
+        if let Some(secs) = connection.lock_timeout_secs {
+            options = options.busy_timeout(Duration::from_secs(secs));
+        }
+        // The database file is opened read-only, so there is no need for transactions
+        if connection.access == Access::ReadOnly {
+            options = options.read_only(true);
+        }
+        let pool = connect_pool(pool_options(&connection.pool), options).await?;
+        Ok(Self { pool })
+    }
+}
+
+#[async_trait::async_trait]
+impl Database for SqliteDatabase {
+    fn backend(&self) -> Backend {
+        Backend::Sqlite
+    }
+
+    async fn get_results_with(
+        &self,
+        query: &str,
+        params: &[Param],
+    ) -> Result<DatabaseResult, DbError> {
+        let mut connection = self.pool.acquire().await?;
+        get_results(&mut connection, query, params).await
+    }
+
+    async fn stream_results(
+        &self,
+        query: &str,
+        params: &[Param],
+        limits: StreamLimits,
+    ) -> Result<RowStream, DbError> {
+        let connection = self.pool.acquire().await?;
+        let cancel = CancelHandle::unsupported();
+        stream_results(
+            connection,
+            query,
+            params.to_vec(),
+            limits,
+            decode_row,
+            cancel,
+            None,
+        )
+        .await
+    }
+
+    async fn describe(&self, query: &str) -> Result<QueryDescription, DbError> {
+        describe(&self.pool, query).await
+    }
+
+    async fn execute_with(&self, query: &str, params: &[Param]) -> Result<ExecuteOutcome, DbError> {
+        let mut connection = self.pool.acquire().await?;
+        execute(&mut connection, query, params).await
+    }
+
+    async fn session(&self) -> Result<Box<dyn Session>, DbError> {
+        let connection = self.pool.acquire().await?;
+        Ok(Box::new(SqliteSession { connection }))
+    }
+
+    async fn begin(&self) -> Result<Box<dyn Transaction>, DbError> {
+        let transaction = self.pool.begin().await?;
+        Ok(Box::new(SqliteTransaction { transaction }))
+    }
+
+    async fn get_schema(&self) -> Result<Schema, DbError> {
+        let mut schema = SchemaBuilder::default();
+
+        let columns = sqlx::query(
+            r#"SELECT
+                m.name AS table_name,
+                m.type AS table_type,
+                p.name AS column_name,
+                p.type AS data_type,
+                NOT p."notnull" AS nullable,
+                p.dflt_value AS column_default,
+                p.pk AS pk
+            FROM sqlite_master m
+            JOIN pragma_table_info(m.name) p
+            WHERE m.type IN ('table', 'view')
+              AND m.name NOT LIKE 'sqlite_%'
+            ORDER BY m.name, p.cid;"#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut primary_keys: Vec<(String, i64, String)> = vec![];
+
+        for row in columns {
+            let table_name: String = row.try_get("table_name")?;
+            let column_name: String = row.try_get("column_name")?;
+
+            let pk: i64 = row.try_get("pk")?;
+            if pk > 0 {
+                primary_keys.push((table_name.clone(), pk, column_name.clone()));
+            }
+
+            let table = schema.table(None, table_name);
+            if row.try_get::<String, _>("table_type")? == "view" {
+                table.kind = RelationKind::View;
+            }
+            table.columns.push(Column {
+                name: column_name,
+                data_type: row.try_get("data_type")?,
+                nullable: row.try_get("nullable")?,
+                default: row.try_get("column_default")?,
+                comment: None,
+            });
+        }
+
+        // pk is the position of the column within the primary key
+        primary_keys.sort();
+        for (table_name, _, column_name) in primary_keys {
+            if let Some(table) = schema.existing_table(None, table_name) {
+                table.primary_key.push(column_name);
+            }
+        }
+
+        // A foreign key without target columns references the primary key of the other table
+        let foreign_keys = sqlx::query(
+            r#"SELECT
+                m.name AS table_name,
+                f.id AS id,
+                f."from" AS column_name,
+                f."table" AS referenced_table,
+                COALESCE(
+                    f."to",
+                    (SELECT p.name FROM pragma_table_info(f."table") p WHERE p.pk = f.seq + 1)
+                ) AS referenced_column
+            FROM sqlite_master m
+            JOIN pragma_foreign_key_list(m.name) f
+            WHERE m.type = 'table'
+            ORDER BY m.name, f.id, f.seq;"#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut last_foreign_key: Option<(String, i64)> = None;
+
+        for row in foreign_keys {
+            let table_name: String = row.try_get("table_name")?;
+            let id: i64 = row.try_get("id")?;
+            let Some(table) = schema.existing_table(None, table_name.clone()) else {
+                continue;
+            };
+
+            // Rows of a composite foreign key share the id and follow each other
+            let key = Some((table_name, id));
+            if last_foreign_key != key {
+                table.foreign_keys.push(ForeignKey {
+                    name: None,
+                    columns: vec![],
+                    referenced_schema: None,
+                    referenced_table: row.try_get("referenced_table")?,
+                    referenced_columns: vec![],
+                });
+                last_foreign_key = key;
+            }
+
+            if let Some(foreign_key) = table.foreign_keys.last_mut() {
+                foreign_key.columns.push(row.try_get("column_name")?);
+                foreign_key.referenced_columns.push(
+                    row.try_get::<Option<String>, _>("referenced_column")?
+                        .unwrap_or_default(),
+                );
+            }
+        }
+
+        // origin is "pk" for primary keys, "u" for unique constraints and "c" for CREATE INDEX
+        let indexes = sqlx::query(
+            r#"SELECT
+                m.name AS table_name,
+                l.name AS index_name,
+                l."unique" AS is_unique,
+                l.origin AS origin,
+                i.name AS column_name
+            FROM sqlite_master m
+            JOIN pragma_index_list(m.name) l
+            JOIN pragma_index_info(l.name) i
+            WHERE m.type = 'table'
+              AND l.origin != 'pk'
+            ORDER BY m.name, l.name, i.seqno;"#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        for row in indexes {
+            let Some(table) = schema.existing_table(None, row.try_get("table_name")?) else {
+                continue;
+            };
+            let name: String = row.try_get("index_name")?;
+            let column: String = row
+                .try_get::<Option<String>, _>("column_name")?
+                .unwrap_or_else(|| "<expression>".to_string());
+
+            if row.try_get::<String, _>("origin")? == "u" {
+                match table.unique_constraints.last_mut() {
+                    Some(unique) if unique.name == name => unique.columns.push(column),
+                    _ => table.unique_constraints.push(UniqueConstraint {
+                        name,
+                        columns: vec![column],
+                    }),
+                }
+            } else {
+                match table.indexes.last_mut() {
+                    Some(index) if index.name == name => index.columns.push(column),
+                    _ => table.indexes.push(Index {
+                        name,
+                        columns: vec![column],
+                        unique: row.try_get("is_unique")?,
+                    }),
+                }
+            }
+        }
+
+        Ok(schema.build())
+    }
+
+    /// Everything introspection reads is derived from the statements in `sqlite_master`, so a
+    /// hash of them changes exactly when the schema does
+    async fn schema_fingerprint(&self) -> Result<Option<String>, DbError> {
+        let definitions: Vec<(String, String, Option<String>)> =
+            sqlx::query_as("SELECT type, name, sql FROM sqlite_master ORDER BY type, name")
+                .fetch_all(&self.pool)
+                .await?;
+
+        let mut hash = Sha256::new();
+        for (kind, name, sql) in definitions {
+            hash.update(format!("{kind}\0{name}\0{}\0", sql.unwrap_or_default()));
+        }
+        Ok(Some(format!("{:x}", hash.finalize())))
+    }
+}
+
+#[async_trait::async_trait]
+impl Session for SqliteSession {
+    async fn get_results_with(
+        &mut self,
+        query: &str,
+        params: &[Param],
+    ) -> Result<DatabaseResult, DbError> {
+        get_results(&mut self.connection, query, params).await
+    }
+
+    async fn describe(&mut self, query: &str) -> Result<QueryDescription, DbError> {
+        describe(&mut *self.connection, query).await
+    }
+
+    async fn execute_with(
+        &mut self,
+        query: &str,
+        params: &[Param],
+    ) -> Result<ExecuteOutcome, DbError> {
+        execute(&mut self.connection, query, params).await
+    }
+}
+
+/// A read-only database file stays read-only whatever the statements do, so they are only
+/// checked for ending the transaction
+#[async_trait::async_trait]
+impl Transaction for SqliteTransaction {
+    async fn get_results_with(
+        &mut self,
+        query: &str,
+        params: &[Param],
+    ) -> Result<DatabaseResult, DbError> {
+        check_statement(query, false)?;
+        get_results(&mut self.transaction, query, params).await
+    }
+
+    async fn execute_with(
+        &mut self,
+        query: &str,
+        params: &[Param],
+    ) -> Result<ExecuteOutcome, DbError> {
+        check_statement(query, false)?;
+        execute(&mut self.transaction, query, params).await
+    }
+
+    async fn preview(&mut self, query: &str, params: &[Param]) -> Result<Changes, DbError> {
+        check_statement(query, false)?;
+        preview::<Sqlite>(
+            &mut self.transaction,
+            query,
+            params,
+            true,
+            decode_row,
+            SqliteQueryResult::rows_affected,
+        )
+        .await
+    }
+
+    async fn commit(self: Box<Self>) -> Result<(), DbError> {
+        Ok(self.transaction.commit().await?)
+    }
+
+    async fn rollback(self: Box<Self>) -> Result<(), DbError> {
+        Ok(self.transaction.rollback().await?)
+    }
+}
+
+async fn get_results(
+    connection: &mut SqliteConnection,
+    query: &str,
+    params: &[Param],
+) -> Result<DatabaseResult, DbError> {
+    // Preparing first gives us the columns even when no rows come back
+    let statement = connection.prepare(query).await?;
+    check_params(&statement, params)?;
+    let mut headers = QueryDescription::new(&statement).columns;
+    let rows = bind_params(&statement, params)
+        .fetch_all(&mut *connection)
+        .await?;
+
+    // Expressions have no declared type, the values in the first row tell us what they hold
+    if let Some(first) = rows.first() {
+        for (i, (_, type_name)) in headers.iter_mut().enumerate() {
+            if type_name == "NULL" {
+                *type_name = first.try_get_raw(i)?.type_info().name().to_string();
+            }
+        }
+    }
+
+    let mut results = DatabaseResult {
+        headers,
+        rows: vec![],
+    };
+
+    for row in rows {
+        results.rows.push(decode_row(&row)?);
+    }
+
+    Ok(results)
+}
+
+/// SQLite columns take any type of value, JSON is stored as text
+impl BindParam for Sqlite {
+    fn bind_param<'q>(
+        query: Query<'q, Sqlite, SqliteArguments<'q>>,
+        param: Param,
+        _type_info: Option<&SqliteTypeInfo>,
+    ) -> Query<'q, Sqlite, SqliteArguments<'q>> {
+        match param {
+            Param::Null => query.bind(None::<String>),
+            Param::Bool(value) => query.bind(value),
+            Param::Int(value) => query.bind(value),
+            Param::Float(value) => query.bind(value),
+            Param::Text(value) => query.bind(value),
+            Param::Bytes(value) => query.bind(value),
+            Param::Json(value) => query.bind(value.to_string()),
+        }
+    }
+}
+
+/// Decode every column of a row into JSON
+fn decode_row(row: &SqliteRow) -> Result<Vec<Value>, DbError> {
+    let mut row_data: Vec<Value> = Vec::new();
+
+    for (i, col) in row.columns().iter().enumerate() {
+        let raw = row.try_get_raw(i)?;
+        if raw.is_null() {
+            row_data.push(Value::Null);
+            continue;
+        }
+
+        // SQLite is dynamically typed, so the declared column type is only a hint and
+        // the storage class of the value itself decides how it is decoded
+        let declared_type = col.type_info().name();
+        let storage_class = raw.type_info().name().to_string();
+        let value = match (declared_type, storage_class.as_str()) {
+            ("BOOLEAN", "INTEGER") => row.try_get::<bool, _>(i).map(|v| json!(v)),
+            (_, "INTEGER") => row.try_get::<i64, _>(i).map(|v| json!(v)),
+            (_, "REAL") => row.try_get::<f64, _>(i).map(|v| json!(v)),
+            (_, "BLOB") => row.try_get::<Vec<u8>, _>(i).map(|bytes| json!(hex(&bytes))),
+            _ => row.try_get::<String, _>(i).map(|v| json!(v)),
+        }
+        .map_err(|e| {
+            DbError::Decode(format!(
+                "column {} ({declared_type}, stored as {storage_class}): {e}",
+                col.name()
+            ))
+        })?;
+
+        row_data.push(value);
+    }
+
+    Ok(row_data)
+}
+
+async fn describe<'e, E>(executor: E, query: &'e str) -> Result<QueryDescription, DbError>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let statement = executor.prepare(query).await?;
+    Ok(QueryDescription::new(&statement))
+}
+
+async fn execute(
+    connection: &mut SqliteConnection,
+    query: &str,
+    params: &[Param],
+) -> Result<ExecuteOutcome, DbError> {
+    let started = Instant::now();
+    let statement = connection.prepare(query).await?;
+    check_params(&statement, params)?;
+    let result = bind_params(&statement, params)
+        .execute(&mut *connection)
+        .await?;
+    // SQLite's count of changes is only updated by statements that change rows, others would
+    // report the count of the last one that did
+    let rows_affected = if modifies_rows(query) {
+        result.rows_affected()
+    } else {
+        0
+    };
+
+    Ok(ExecuteOutcome {
+        command_tag: command_tag(query, rows_affected),
+        rows_affected,
+        notices: vec![],
+        elapsed: started.elapsed(),
+    })
+}
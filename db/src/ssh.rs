@@ -0,0 +1,216 @@
+use crate::relay::{Listener, Tunnel};
+use crate::{DbError, expand_home};
+
+use config::{DatabaseConnection, SSHConfig};
+use russh::client::{self, Handle};
+use russh::keys::{
+    PrivateKeyWithHashAlg, PublicKey, check_known_hosts_path, load_secret_key, ssh_key,
+};
+use std::net::Ipv4Addr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinSet;
+use url::{Host, Url};
+
+/// How long to wait for the SSH server before giving up
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Keeps idle sessions from being dropped by the server or firewalls in between
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Open a tunnel if the connection goes through an SSH server. Returns it along with the URL to
+/// connect to, which points at the local end of the tunnel if there is one.
+pub(crate) async fn tunnel(
+    connection: &DatabaseConnection,
+    default_port: u16,
+) -> Result<(Option<Tunnel>, String), DbError> {
+    let Some(ssh) = &connection.ssh else {
+        return Ok((None, connection.url.clone()));
+    };
+
+    let mut url =
+        Url::parse(&connection.url).map_err(|error| DbError::Configuration(error.to_string()))?;
+    let host = match url.host() {
+        Some(Host::Domain(domain)) if !domain.is_empty() => domain.to_string(),
+        Some(Host::Ipv4(address)) => address.to_string(),
+        Some(Host::Ipv6(address)) => address.to_string(),
+        _ => {
+            return Err(DbError::Configuration(
+                "connecting through SSH needs the database's host in the URL".to_string(),
+            ));
+        }
+    };
+    let port = url.port().unwrap_or(default_port);
+
+    let (listener, local_port) = Listener::tcp().await?;
+    url.set_host(Some(&Ipv4Addr::LOCALHOST.to_string()))
+        .and_then(|()| {
+            url.set_port(Some(local_port))
+                .map_err(|()| url::ParseError::InvalidPort)
+        })
+        .map_err(|error| DbError::Configuration(error.to_string()))?;
+
+    let tunnel = forward(ssh, host, port, listener).await?;
+    Ok((Some(tunnel), url.to_string()))
+}
+
+/// Forward the listener's connections to `host` and `port`, as seen from the SSH server
+pub(crate) async fn forward(
+    ssh: &SSHConfig,
+    host: String,
+    port: u16,
+    listener: Listener,
+) -> Result<Tunnel, DbError> {
+    let forwarder = Forwarder {
+        ssh: ssh.clone(),
+        host,
+        port,
+    };
+    let session = forwarder.connect().await?;
+    Ok(Tunnel::spawn(listener, |listener| {
+        forwarder.run(listener, session)
+    }))
+}
+
+/// Opens SSH sessions, and forwards connections to the local port through them to the database
+struct Forwarder {
+    ssh: SSHConfig,
+    /// The database, as seen from the SSH server
+    host: String,
+    port: u16,
+}
+
+impl Forwarder {
+    async fn connect(&self) -> Result<Handle<KnownHosts>, DbError> {
+        let ssh = &self.ssh;
+        let config = Arc::new(client::Config {
+            keepalive_interval: Some(KEEPALIVE_INTERVAL),
+            ..client::Config::default()
+        });
+        let known_hosts = KnownHosts {
+            host: ssh.host.clone(),
+            port: ssh.port,
+            path: ssh
+                .known_hosts
+                .as_deref()
+                .map_or_else(|| expand_home("~/.ssh/known_hosts"), expand_home),
+        };
+
+        let connecting = client::connect(config, (ssh.host.as_str(), ssh.port), known_hosts);
+        let mut session = tokio::time::timeout(CONNECT_TIMEOUT, connecting)
+            .await
+            .map_err(|_| {
+                DbError::Ssh(format!("timed out connecting to {}:{}", ssh.host, ssh.port))
+            })??;
+
+        if !self.authenticate(&mut session).await? {
+            return Err(DbError::Ssh(format!(
+                "{}@{} didn't accept the key or password",
+                ssh.username, ssh.host
+            )));
+        }
+        Ok(session)
+    }
+
+    /// Try the key, then the password
+    async fn authenticate(&self, session: &mut Handle<KnownHosts>) -> Result<bool, DbError> {
+        let ssh = &self.ssh;
+
+        if let Some(path) = &ssh.ssh_key {
+            let key = load_secret_key(expand_home(path), ssh.ssh_key_passphrase.as_deref())
+                .map_err(|error| match error {
+                    russh::keys::Error::KeyIsEncrypted => {
+                        DbError::Ssh(format!("{path} is encrypted, set ssh_key_passphrase"))
+                    }
+                    russh::keys::Error::SshKey(ssh_key::Error::Crypto) => {
+                        DbError::Ssh(format!("couldn't decrypt {path}, check ssh_key_passphrase"))
+                    }
+                    error => DbError::Ssh(format!("{path}: {error}")),
+                })?;
+            let hash = session.best_supported_rsa_hash().await?.flatten();
+            let key = PrivateKeyWithHashAlg::new(Arc::new(key), hash);
+            if session
+                .authenticate_publickey(&ssh.username, key)
+                .await?
+                .success()
+            {
+                return Ok(true);
+            }
+        }
+
+        if let Some(password) = &ssh.password {
+            return Ok(session
+                .authenticate_password(&ssh.username, password)
+                .await?
+                .success());
+        }
+        Ok(false)
+    }
+
+    /// Connections that can't be forwarded are closed, the database driver then reports them
+    async fn run(self, listener: Listener, mut session: Handle<KnownHosts>) {
+        let mut connections = JoinSet::new();
+
+        loop {
+            let Ok((mut socket, peer_address, peer_port)) = listener.accept().await else {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            };
+            while connections.try_join_next().is_some() {}
+
+            // The server may have ended the session since the last connection
+            if session.is_closed() {
+                match self.connect().await {
+                    Ok(reconnected) => session = reconnected,
+                    Err(_) => continue,
+                }
+            }
+
+            let Ok(channel) = session
+                .channel_open_direct_tcpip(
+                    self.host.clone(),
+                    self.port.into(),
+                    peer_address,
+                    peer_port.into(),
+                )
+                .await
+            else {
+                continue;
+            };
+
+            connections.spawn(async move {
+                let mut stream = channel.into_stream();
+                let _ = tokio::io::copy_bidirectional(&mut socket, &mut stream).await;
+            });
+        }
+    }
+}
+
+/// Accepts the SSH server only if its key is in the known hosts file
+struct KnownHosts {
+    host: String,
+    port: u16,
+    path: PathBuf,
+}
+
+impl client::Handler for KnownHosts {
+    type Error = DbError;
+
+    async fn check_server_key(&mut self, key: &PublicKey) -> Result<bool, Self::Error> {
+        let path = self.path.display();
+        match check_known_hosts_path(&self.host, self.port, key, &self.path) {
+            Ok(true) => Ok(true),
+            Ok(false) => Err(DbError::Ssh(format!(
+                "the host key of {} isn't in {path}, check it and add it with \
+                 `ssh-keyscan -p {} {} >> {path}`",
+                self.host, self.port, self.host
+            ))),
+            Err(russh::keys::Error::KeyChanged { line }) => Err(DbError::Ssh(format!(
+                "the host key of {} doesn't match the one on line {line} of {path}",
+                self.host
+            ))),
+            Err(error) => Err(DbError::Ssh(format!("{path}: {error}"))),
+        }
+    }
+}
@@ -0,0 +1,197 @@
+/// Keywords that may come between `CREATE`, `ALTER` or `DROP` and the kind of object
+const MODIFIERS: &[&str] = &[
+    "OR",
+    "REPLACE",
+    "UNIQUE",
+    "TEMP",
+    "TEMPORARY",
+    "UNLOGGED",
+    "GLOBAL",
+    "LOCAL",
+    "RECURSIVE",
+];
+
+/// The command tag of a statement in the form Postgres reports it, e.g. `UPDATE 3412`,
+/// `INSERT 0 1` or `CREATE TABLE`. The driver only hands over the count of the server's tag,
+/// so the command is derived from the statement. Postgres reports the command as it was
+/// written even when rules, `INSTEAD OF` triggers or data-modifying CTEs run others, so those
+/// tags match the server's. They differ where the server reports another command:
+/// `CREATE TABLE ... AS` and `CREATE MATERIALIZED VIEW` are tagged with the statement rather
+/// than as `SELECT 3`.
+pub(crate) fn command_tag(query: &str, rows_affected: u64) -> String {
+    let keywords = leading_keywords(query);
+    let Some(command) = keywords.first() else {
+        return String::new();
+    };
+
+    // A WITH query is tagged with the statement that follows its common table expressions
+    let tokens = tokens(query);
+    let command = match command.as_str() {
+        "WITH" => main_statement(&tokens).unwrap_or(command),
+        _ => command,
+    };
+
+    match command {
+        // The 0 is where Postgres used to report the oid of a single inserted row
+        "INSERT" => format!("INSERT 0 {rows_affected}"),
+        "VALUES" | "TABLE" => format!("SELECT {rows_affected}"),
+        "UPDATE" | "DELETE" | "MERGE" | "REPLACE" | "SELECT" | "COPY" | "FETCH" | "MOVE" => {
+            format!("{command} {rows_affected}")
+        }
+        "CREATE" | "ALTER" | "DROP" => {
+            let object = keywords[1..]
+                .iter()
+                .filter(|keyword| !MODIFIERS.contains(&keyword.as_str()))
+                .take_while(|keyword| *keyword != "IF")
+                .take(2)
+                .collect::<Vec<_>>();
+            // Two-word kinds such as MATERIALIZED VIEW or FOREIGN TABLE
+            match object.as_slice() {
+                [first, second]
+                    if ["MATERIALIZED", "FOREIGN", "EVENT"].contains(&first.as_str()) =>
+                {
+                    format!("{command} {first} {second}")
+                }
+                [first, ..] => format!("{command} {first}"),
+                [] => command.to_string(),
+            }
+        }
+        _ => command.to_string(),
+    }
+}
+
+/// The statement a `WITH` query runs, the first one outside of the parentheses of its common
+/// table expressions
+fn main_statement(tokens: &[Token]) -> Option<&str> {
+    let mut depth = 0_usize;
+    tokens.iter().find_map(|token| {
+        match token.text.as_str() {
+            "(" => depth += 1,
+            ")" => depth = depth.saturating_sub(1),
+            command
+            @ ("SELECT" | "INSERT" | "UPDATE" | "DELETE" | "MERGE" | "VALUES" | "TABLE")
+                if depth == 0 =>
+            {
+                return Some(command);
+            }
+            _ => {}
+        }
+        None
+    })
+}
+
+/// The first keyword of a statement, in upper case, after any leading comments
+pub(crate) fn first_keyword(query: &str) -> String {
+    leading_keywords(query)
+        .into_iter()
+        .next()
+        .unwrap_or_default()
+}
+
+/// A word or symbol of a statement, and the byte offset it ends at
+pub(crate) struct Token {
+    /// Words in upper case, other characters as they are, and `'` for any string literal,
+    /// quoted identifier or dollar-quoted body
+    pub text: String,
+    pub end: usize,
+}
+
+/// The words and symbols of a statement, leaving out whitespace and comments. What is inside
+/// literals and quoted identifiers isn't looked at.
+pub(crate) fn tokens(query: &str) -> Vec<Token> {
+    let bytes = query.as_bytes();
+    let mut tokens = vec![];
+
+    let mut i = 0;
+    while i < bytes.len() {
+        let rest = &bytes[i..];
+        let end = match rest {
+            [byte, ..] if byte.is_ascii_whitespace() => {
+                i += 1;
+                continue;
+            }
+            [b'-', b'-', ..] => {
+                i = find(bytes, i, b"\n").map_or(bytes.len(), |end| end + 1);
+                continue;
+            }
+            [b'/', b'*', ..] => {
+                i = find(bytes, i + 2, b"*/").map_or(bytes.len(), |end| end + 2);
+                continue;
+            }
+            [quote @ (b'\'' | b'"' | b'`'), ..] => {
+                let mut j = i + 1;
+                loop {
+                    match find(bytes, j, &[*quote]) {
+                        Some(end) if bytes.get(end + 1) == Some(quote) => j = end + 2,
+                        Some(end) => break end + 1,
+                        None => break bytes.len(),
+                    }
+                }
+            }
+            [b'$', ..] if i == 0 || !is_word_byte(bytes[i - 1]) => match dollar_tag(bytes, i) {
+                Some(tag) => {
+                    find(bytes, i + tag.len(), tag).map_or(bytes.len(), |end| end + tag.len())
+                }
+                None => i + 1,
+            },
+            [byte, ..] if is_word_byte(*byte) => (i..bytes.len())
+                .find(|&j| !is_word_byte(bytes[j]))
+                .unwrap_or(bytes.len()),
+            _ => i + query[i..].chars().next().map_or(1, char::len_utf8),
+        };
+
+        let text = match bytes[i] {
+            b'\'' | b'"' | b'`' => "'".to_string(),
+            b'$' if end > i + 1 => "'".to_string(),
+            _ => query[i..end].to_ascii_uppercase(),
+        };
+        tokens.push(Token { text, end });
+        i = end;
+    }
+
+    tokens
+}
+
+/// Letters, digits, `_` and `$` continue an identifier or keyword, as does anything non-ASCII
+pub(crate) fn is_word_byte(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'$' || !byte.is_ascii()
+}
+
+/// The `$tag$` that starts at `i`, if it is one. Tags are like identifiers but without `$`, and
+/// can be empty; `$1` is a parameter.
+pub(crate) fn dollar_tag(bytes: &[u8], i: usize) -> Option<&[u8]> {
+    let end = (i + 1..bytes.len()).find(|&j| !is_word_byte(bytes[j]) || bytes[j] == b'$')?;
+    let tag = &bytes[i + 1..end];
+    let valid = bytes[end] == b'$' && tag.first().is_none_or(|byte| !byte.is_ascii_digit());
+    valid.then_some(&bytes[i..=end])
+}
+
+pub(crate) fn find(bytes: &[u8], from: usize, needle: &[u8]) -> Option<usize> {
+    bytes
+        .get(from..)?
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|position| from + position)
+}
+
+/// The words a statement starts with, in upper case, up to the first one that isn't a keyword
+fn leading_keywords(query: &str) -> Vec<String> {
+    let mut rest = query.trim_start();
+    loop {
+        if let Some(comment) = rest.strip_prefix("--") {
+            rest = comment.split_once('\n').map_or("", |(_, rest)| rest);
+        } else if let Some(comment) = rest.strip_prefix("/*") {
+            rest = comment.split_once("*/").map_or("", |(_, rest)| rest);
+        } else {
+            break;
+        }
+        rest = rest.trim_start();
+    }
+
+    rest.split(|c: char| c.is_whitespace() || c == '(' || c == ';')
+        .filter(|word| !word.is_empty())
+        .take_while(|word| word.chars().all(|c| c.is_ascii_alphabetic() || c == '_'))
+        .take(6)
+        .map(str::to_ascii_uppercase)
+        .collect()
+}
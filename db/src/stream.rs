@@ -0,0 +1,290 @@
+use crate::param::{BindParam, bind_params, check_params};
+use crate::transaction::check_access_mode;
+use crate::{DbError, Param, QueryDescription};
+
+use futures::StreamExt;
+use serde_json::Value;
+use sqlx::pool::PoolConnection;
+use sqlx::{
+    Connection, Executor, IntoArguments, MySqlConnection, MySqlPool, PgConnection, PgPool,
+    Statement,
+};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::sync::{Mutex, mpsc};
+
+/// Rows that are fetched ahead of the consumer
+const BUFFERED_ROWS: usize = 64;
+
+/// Caps on how much of a result is fetched, `None` means no cap
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StreamLimits {
+    pub max_rows: Option<usize>,
+    /// Estimated from the JSON size of the decoded rows
+    pub max_bytes: Option<usize>,
+}
+
+/// Why a stream stopped before the end of the result
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Rows,
+    Bytes,
+}
+
+#[derive(Debug)]
+enum Message {
+    Row(Vec<Value>),
+    Error(DbError),
+    Truncated(Limit),
+}
+
+/// Rows of a query, decoded as they arrive from the database.
+///
+/// Dropping the stream stops fetching; the connection is closed instead of going back to the
+/// pool, so the server stops sending the rest of the result.
+#[derive(Debug)]
+pub struct RowStream {
+    /// Column names and types, available before the first row
+    pub headers: Vec<(String, String)>,
+    receiver: mpsc::Receiver<Message>,
+    fetched: Arc<AtomicUsize>,
+    truncated: Option<Limit>,
+    cancel: CancelHandle,
+}
+
+/// Stops a running query from another task, e.g. when the user presses Ctrl-C.
+///
+/// The query is cancelled on the server; reading the stream then returns the server's error.
+#[derive(Debug, Clone)]
+pub struct CancelHandle {
+    target: CancelTarget,
+    /// Set once the query is done, so that a later query on the same connection isn't hit. It
+    /// is locked while a cancel request is sent, which keeps the connection from going back to
+    /// the pool and starting another query before the request has arrived.
+    finished: Arc<Mutex<bool>>,
+}
+
+#[derive(Debug, Clone)]
+enum CancelTarget {
+    /// `pg_cancel_backend` needs the process id of the connection's backend
+    Postgres { pool: PgPool, pid: i32 },
+    /// `KILL QUERY` needs the connection id
+    MySql { pool: MySqlPool, connection_id: u64 },
+    /// SQLite runs in-process and stops as soon as the stream is dropped
+    Unsupported,
+}
+
+impl CancelHandle {
+    pub(crate) fn postgres(pool: PgPool, pid: i32) -> Self {
+        Self::new(CancelTarget::Postgres { pool, pid })
+    }
+
+    pub(crate) fn mysql(pool: MySqlPool, connection_id: u64) -> Self {
+        Self::new(CancelTarget::MySql {
+            pool,
+            connection_id,
+        })
+    }
+
+    pub(crate) fn unsupported() -> Self {
+        Self::new(CancelTarget::Unsupported)
+    }
+
+    fn new(target: CancelTarget) -> Self {
+        Self {
+            target,
+            finished: Arc::new(Mutex::new(false)),
+        }
+    }
+
+    /// Ask the server to cancel the query, over a connection of its own rather than one from
+    /// the pool, which the query may hold the last connection of. Returns whether a cancel
+    /// request was sent, which it isn't once the query is done or for SQLite.
+    pub async fn cancel(&self) -> Result<bool, DbError> {
+        if *self.finished.lock().await {
+            return Ok(false);
+        }
+
+        match &self.target {
+            CancelTarget::Postgres { pool, pid } => {
+                let mut connection = PgConnection::connect_with(&pool.connect_options()).await?;
+                let finished = self.finished.lock().await;
+                if *finished {
+                    return Ok(false);
+                }
+                let sent = sqlx::query_scalar("SELECT pg_cancel_backend($1)")
+                    .bind(pid)
+                    .fetch_one(&mut connection)
+                    .await?;
+                drop(finished);
+                let _ = connection.close().await;
+                Ok(sent)
+            }
+            CancelTarget::MySql {
+                pool,
+                connection_id,
+            } => {
+                let mut connection = MySqlConnection::connect_with(&pool.connect_options()).await?;
+                let finished = self.finished.lock().await;
+                if *finished {
+                    return Ok(false);
+                }
+                let kill = format!("KILL QUERY {connection_id}");
+                connection.execute(kill.as_str()).await?;
+                drop(finished);
+                let _ = connection.close().await;
+                Ok(true)
+            }
+            CancelTarget::Unsupported => Ok(false),
+        }
+    }
+
+    /// Waits for a cancel request that is being sent
+    async fn finish(&self) {
+        *self.finished.lock().await = true;
+    }
+}
+
+impl RowStream {
+    /// The next row, `None` once the result is exhausted or a limit was reached
+    pub async fn next(&mut self) -> Option<Result<Vec<Value>, DbError>> {
+        match self.receiver.recv().await? {
+            Message::Row(row) => Some(Ok(row)),
+            Message::Error(error) => Some(Err(error)),
+            Message::Truncated(limit) => {
+                self.truncated = Some(limit);
+                None
+            }
+        }
+    }
+
+    /// Number of rows fetched from the database so far, including rows not yet taken
+    pub fn rows_fetched(&self) -> usize {
+        self.fetched.load(Ordering::Relaxed)
+    }
+
+    /// The limit that cut the result short, once the stream has ended because of it
+    pub fn truncated(&self) -> Option<Limit> {
+        self.truncated
+    }
+
+    pub fn cancel_handle(&self) -> CancelHandle {
+        self.cancel.clone()
+    }
+}
+
+/// Prepare the query on a connection of its own, then fetch rows in the background until the
+/// result ends, a limit is reached or the [`RowStream`] is dropped.
+///
+/// With `begin_read_only` the query runs in a read-only transaction, like [`crate::Guarded`].
+pub(crate) async fn stream_results<DB>(
+    mut connection: PoolConnection<DB>,
+    query: &str,
+    params: Vec<Param>,
+    limits: StreamLimits,
+    decode_row: fn(&DB::Row) -> Result<Vec<Value>, DbError>,
+    cancel: CancelHandle,
+    begin_read_only: Option<&'static str>,
+) -> Result<RowStream, DbError>
+where
+    DB: BindParam,
+    for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+    for<'q> DB::Arguments<'q>: IntoArguments<'q, DB>,
+{
+    // The transaction outlives this function, so it's managed by hand instead of with a guard
+    if let Some(begin) = begin_read_only {
+        check_access_mode(query)?;
+        connection.execute(begin).await?;
+    }
+
+    let statement = match connection.prepare(query).await {
+        Ok(statement) => match check_params(&statement, &params) {
+            Ok(()) => statement,
+            Err(error) => return Err(rollback(connection, begin_read_only, error).await),
+        },
+        Err(error) => return Err(rollback(connection, begin_read_only, error.into()).await),
+    };
+    let headers = QueryDescription::new(&statement).columns;
+    let statement = statement.to_owned();
+
+    let (sender, receiver) = mpsc::channel(BUFFERED_ROWS);
+    let fetched = Arc::new(AtomicUsize::new(0));
+    let progress = fetched.clone();
+    let task_cancel = cancel.clone();
+
+    tokio::spawn(async move {
+        let finished = {
+            let mut rows = bind_params(&statement, &params).fetch(&mut *connection);
+            let mut bytes = 0;
+
+            loop {
+                let row = tokio::select! {
+                    row = rows.next() => row,
+                    () = sender.closed() => break false,
+                };
+
+                let message = match row.map(|row| decode_row(&row?)) {
+                    None => break true,
+                    Some(Err(error)) => Message::Error(error),
+                    Some(Ok(row)) => {
+                        let rows_so_far = progress.load(Ordering::Relaxed);
+                        bytes += row
+                            .iter()
+                            .map(|value| value.to_string().len())
+                            .sum::<usize>();
+
+                        if limits.max_rows.is_some_and(|max| rows_so_far >= max) {
+                            let _ = sender.send(Message::Truncated(Limit::Rows)).await;
+                            break false;
+                        }
+                        if limits.max_bytes.is_some_and(|max| bytes > max) {
+                            let _ = sender.send(Message::Truncated(Limit::Bytes)).await;
+                            break false;
+                        }
+
+                        progress.fetch_add(1, Ordering::Relaxed);
+                        Message::Row(row)
+                    }
+                };
+
+                if sender.send(message).await.is_err() {
+                    break false;
+                }
+            }
+        };
+
+        task_cancel.finish().await;
+
+        // The rest of the result would still be read when the connection is next used, and a
+        // transaction that can't be ended mustn't go back to the pool either
+        let reusable =
+            finished && (begin_read_only.is_none() || connection.execute("COMMIT").await.is_ok());
+        if !reusable {
+            let _ = connection.close().await;
+        }
+    });
+
+    Ok(RowStream {
+        headers,
+        receiver,
+        fetched,
+        truncated: None,
+        cancel,
+    })
+}
+
+/// End the read-only transaction after the query couldn't be started
+async fn rollback<DB>(
+    mut connection: PoolConnection<DB>,
+    begin_read_only: Option<&'static str>,
+    error: DbError,
+) -> DbError
+where
+    DB: sqlx::Database,
+    for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+{
+    if begin_read_only.is_some() && connection.execute("ROLLBACK").await.is_err() {
+        let _ = connection.close().await;
+    }
+    error
+}
@@ -0,0 +1,102 @@
+use crate::{DbError, expand_home};
+
+use config::{TlsConfig, TlsMode};
+use sqlx::mysql::{MySqlConnectOptions, MySqlSslMode};
+use sqlx::postgres::{PgConnectOptions, PgSslMode};
+use std::path::PathBuf;
+
+/// The files of a [`TlsConfig`], checked to be there. The driver only reads them once it
+/// connects, and would report a missing file as a connection error.
+struct Files {
+    root_cert: Option<PathBuf>,
+    client: Option<(PathBuf, PathBuf)>,
+}
+
+impl Files {
+    fn new(tls: &TlsConfig) -> Result<Self, DbError> {
+        let client = match (&tls.client_cert, &tls.client_key) {
+            (Some(cert), Some(key)) => Some((file("client_cert", cert)?, file("client_key", key)?)),
+            (None, None) => None,
+            _ => {
+                return Err(DbError::Configuration(
+                    "tls.client_cert and tls.client_key have to be set together".to_string(),
+                ));
+            }
+        };
+        Ok(Self {
+            root_cert: tls
+                .root_cert
+                .as_deref()
+                .map(|path| file("root_cert", path))
+                .transpose()?,
+            client,
+        })
+    }
+}
+
+fn file(setting: &str, path: &str) -> Result<PathBuf, DbError> {
+    let expanded = expand_home(path);
+    match std::fs::metadata(&expanded) {
+        Ok(metadata) if metadata.is_file() => Ok(expanded),
+        Ok(_) => Err(DbError::Configuration(format!(
+            "tls.{setting} {path} isn't a file"
+        ))),
+        Err(error) => Err(DbError::Configuration(format!(
+            "tls.{setting} {path}: {error}"
+        ))),
+    }
+}
+
+pub(crate) fn postgres(
+    mut options: PgConnectOptions,
+    tls: &TlsConfig,
+) -> Result<PgConnectOptions, DbError> {
+    let files = Files::new(tls)?;
+
+    if let Some(mode) = tls.mode {
+        options = options.ssl_mode(match mode {
+            TlsMode::Disable => PgSslMode::Disable,
+            TlsMode::Allow => PgSslMode::Allow,
+            TlsMode::Prefer => PgSslMode::Prefer,
+            TlsMode::Require => PgSslMode::Require,
+            TlsMode::VerifyCa => PgSslMode::VerifyCa,
+            TlsMode::VerifyFull => PgSslMode::VerifyFull,
+        });
+    }
+    if let Some(path) = files.root_cert {
+        options = options.ssl_root_cert(path);
+    }
+    if let Some((cert, key)) = files.client {
+        options = options.ssl_client_cert(cert).ssl_client_key(key);
+    }
+    Ok(options)
+}
+
+pub(crate) fn mysql(
+    mut options: MySqlConnectOptions,
+    tls: &TlsConfig,
+) -> Result<MySqlConnectOptions, DbError> {
+    if tls.server_name.is_some() {
+        return Err(DbError::Configuration(
+            "tls.server_name is only supported for Postgres".to_string(),
+        ));
+    }
+    let files = Files::new(tls)?;
+
+    if let Some(mode) = tls.mode {
+        options = options.ssl_mode(match mode {
+            TlsMode::Disable | TlsMode::Allow => MySqlSslMode::Disabled,
+            TlsMode::Prefer => MySqlSslMode::Preferred,
+            TlsMode::Require => MySqlSslMode::Required,
+            TlsMode::VerifyCa => MySqlSslMode::VerifyCa,
+            TlsMode::VerifyFull => MySqlSslMode::VerifyIdentity,
+        });
+    }
+    if let Some(path) = files.root_cert {
+        options = options.ssl_ca(path);
+    }
+    if let Some((cert, key)) = files.client {
+        options = options.ssl_client_cert(cert).ssl_client_key(key);
+    }
+    Ok(options)
+}
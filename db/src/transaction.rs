@@ -0,0 +1,173 @@
+use crate::param::{BindParam, bind_params, check_params};
+use crate::statement::{Token, first_keyword, tokens};
+use crate::{DbError, Param, QueryDescription};
+
+use futures::TryStreamExt;
+use serde_json::Value;
+use sqlx::{Either, Executor, IntoArguments};
+
+/// Changed rows that are kept for a preview, the rest are only counted
+const SAMPLE_ROWS: usize = 20;
+
+/// What a data-changing statement did, or would do in a dry run
+#[derive(Debug)]
+pub struct Changes {
+    pub rows_affected: u64,
+    /// Columns of the changed rows, empty where the backend can't return them (MySQL, and MERGE
+    /// before Postgres 17)
+    pub headers: Vec<(String, String)>,
+    /// The first changed rows, as they are after the statement
+    pub rows: Vec<Vec<Value>>,
+}
+
+/// Whether the statement changes rows anywhere: an INSERT, UPDATE, DELETE or MERGE, including
+/// one in a `WITH` query or after `EXPLAIN ANALYZE`
+pub fn modifies_rows(query: &str) -> bool {
+    changes_rows(&tokens(query))
+}
+
+fn changes_rows(tokens: &[Token]) -> bool {
+    let keyword = |i: usize| tokens.get(i).map_or("", |token| token.text.as_str());
+    // REPLACE is MySQL's and SQLite's statement, elsewhere it is a function
+    keyword(0) == "REPLACE"
+        || (0..tokens.len()).any(|i| {
+            matches!(keyword(i), "INSERT" | "UPDATE" | "DELETE" | "MERGE")
+                // MySQL's INSERT() function
+                && keyword(i + 1) != "("
+                // ON DELETE CASCADE, FOR UPDATE and FOR NO KEY UPDATE don't change rows
+                && (i == 0 || !matches!(keyword(i - 1), "ON" | "FOR" | "KEY"))
+        })
+}
+
+/// Whether the statement only reads: a query, `SHOW` or `EXPLAIN` that doesn't change rows
+/// and doesn't write them elsewhere with `SELECT ... INTO`. Functions it calls can still
+/// change things.
+pub fn is_read_only(query: &str) -> bool {
+    let tokens = tokens(query);
+    let first = tokens.iter().find(|token| token.text != "(");
+    first.is_some_and(|first| {
+        matches!(
+            first.text.as_str(),
+            "SELECT" | "WITH" | "VALUES" | "TABLE" | "SHOW" | "EXPLAIN" | "DESCRIBE" | "DESC"
+        )
+    }) && !changes_rows(&tokens)
+        && !tokens.iter().any(|token| token.text == "INTO")
+}
+
+/// Whether the statement can be previewed and rolled back: an INSERT, UPDATE, DELETE, MERGE or
+/// REPLACE, or a `WITH` query that runs one. Other statements, such as DDL, commit on their own
+/// on MySQL.
+pub fn can_preview(query: &str) -> bool {
+    let tokens = tokens(query);
+    match tokens.first().map(|first| first.text.as_str()) {
+        Some("INSERT" | "UPDATE" | "DELETE" | "MERGE" | "REPLACE") => true,
+        Some("WITH") => changes_rows(&tokens),
+        _ => false,
+    }
+}
+
+/// Statements inside a transaction handle mustn't end the transaction behind its back; later
+/// statements would run outside of it. A `read_only` transaction mustn't be made read-write
+/// either, which Postgres allows as long as nothing has been queried in it yet.
+pub(crate) fn check_statement(query: &str, read_only: bool) -> Result<(), DbError> {
+    match first_keyword(query).as_str() {
+        "BEGIN" | "START" | "COMMIT" | "END" | "ROLLBACK" | "ABORT" | "PREPARE" => {
+            Err(DbError::Other(
+                "Transactions are ended with commit() or rollback(), not with statements"
+                    .to_string(),
+            ))
+        }
+        _ if read_only => check_access_mode(query),
+        _ => Ok(()),
+    }
+}
+
+/// Statements on a read-only connection mustn't change its access mode. Most would only last
+/// as long as the statement's own read-only transaction, but on MySQL a session setting stays
+/// with the pooled connection, and DDL commits implicitly and runs outside of the transaction.
+pub(crate) fn check_access_mode(query: &str) -> Result<(), DbError> {
+    if first_keyword(query) == "SET" && sets_access_mode(&tokens(query)) {
+        return Err(DbError::Other(
+            "The access mode of a read-only connection can't be changed".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Whether a `SET` statement may change the access mode of the transaction or the session:
+/// `SET TRANSACTION`, `SET SESSION CHARACTERISTICS AS TRANSACTION`, MySQL's
+/// `SET SESSION TRANSACTION`, or a setting such as `transaction_read_only`. Settings with a
+/// quoted name are refused as well, since their name isn't looked at.
+fn sets_access_mode(tokens: &[Token]) -> bool {
+    let name = tokens.iter().skip(1).find(|token| {
+        !matches!(
+            token.text.as_str(),
+            "SESSION" | "LOCAL" | "GLOBAL" | "PERSIST" | "@" | "."
+        )
+    });
+    name.is_some_and(|name| name.text == "'")
+        || tokens.iter().any(|token| {
+            matches!(token.text.as_str(), "TRANSACTION" | "CHARACTERISTICS")
+                || token.text.contains("READ_ONLY")
+        })
+}
+
+/// Run a data-changing statement and collect the rows it changed. With `returning`, `RETURNING *`
+/// is added to an INSERT, UPDATE, DELETE or MERGE unless it already returns something; a
+/// trailing `;` or comment is left out so that it doesn't end the statement first. Other
+/// statements are refused, see [`can_preview`].
+pub(crate) async fn preview<DB>(
+    connection: &mut DB::Connection,
+    query: &str,
+    params: &[Param],
+    returning: bool,
+    decode_row: fn(&DB::Row) -> Result<Vec<Value>, DbError>,
+    rows_affected: fn(&DB::QueryResult) -> u64,
+) -> Result<Changes, DbError>
+where
+    DB: BindParam,
+    for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+    for<'q> DB::Arguments<'q>: IntoArguments<'q, DB>,
+{
+    if !can_preview(query) {
+        return Err(DbError::Other(
+            "Only INSERT, UPDATE, DELETE and MERGE can be previewed, other statements may commit on their own"
+                .to_string(),
+        ));
+    }
+
+    let tokens = tokens(query);
+    let takes_returning = tokens.first().is_some_and(|first| {
+        matches!(
+            first.text.as_str(),
+            "INSERT" | "UPDATE" | "DELETE" | "MERGE"
+        )
+    }) && !tokens.iter().any(|token| token.text == "RETURNING");
+    let query = match tokens.iter().rev().find(|token| token.text != ";") {
+        Some(last) if returning && takes_returning => {
+            format!("{} RETURNING *", &query[..last.end])
+        }
+        _ => query.trim().trim_end_matches(';').to_string(),
+    };
+
+    let statement = connection.prepare(&query).await?;
+    check_params(&statement, params)?;
+    let mut changes = Changes {
+        rows_affected: 0,
+        headers: QueryDescription::new(&statement).columns,
+        rows: vec![],
+    };
+
+    let mut results = connection.fetch_many(bind_params(&statement, params));
+    while let Some(result) = results.try_next().await? {
+        match result {
+            Either::Left(result) => changes.rows_affected += rows_affected(&result),
+            Either::Right(row) if changes.rows.len() < SAMPLE_ROWS => {
+                changes.rows.push(decode_row(&row)?);
+            }
+            Either::Right(_) => {}
+        }
+    }
+
+    Ok(changes)
+}
@@ -0,0 +1,120 @@
+//! Schemas cached on disk, and the fingerprints that tell when they are stale.
+//!
+//! The Postgres test needs a database and is ignored by default. Run it with
+//! `PEEK_TEST_POSTGRES_URL` set and `cargo test -p db -- --ignored`.
+
+mod common;
+
+use common::{connect, postgres_url};
+use config::{Access, DatabaseConnection};
+use db::SchemaCache;
+use std::path::PathBuf;
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("peek_test_{name}_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn column_names(schema: &db::Schema, table: &str) -> Vec<String> {
+    let table = schema.tables.iter().find(|t| t.name == table).unwrap();
+    table.columns.iter().map(|c| c.name.clone()).collect()
+}
+
+#[tokio::test]
+async fn sqlite_cache() {
+    let dir = temp_dir("schema_cache");
+    let connection = DatabaseConnection {
+        url: format!("sqlite://{}?mode=rwc", dir.join("test.db").display()),
+        ..DatabaseConnection::default()
+    };
+    let database = db::connect(&connection).await.unwrap();
+    database
+        .execute("CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT)")
+        .await
+        .unwrap();
+
+    let cache = SchemaCache::new(dir.join("cache"));
+    let schema = cache
+        .get_schema(database.as_ref(), &connection)
+        .await
+        .unwrap();
+    assert_eq!(column_names(&schema, "users"), ["id", "name"]);
+
+    // While the fingerprint is the same, whatever was saved is used
+    let [entry] = &std::fs::read_dir(dir.join("cache"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect::<Vec<_>>()[..]
+    else {
+        panic!("expected a single cached schema");
+    };
+    let json = std::fs::read_to_string(entry).unwrap();
+    std::fs::write(
+        entry,
+        json.replace(r#""name":"name""#, r#""name":"cached""#),
+    )
+    .unwrap();
+    let schema = cache
+        .get_schema(database.as_ref(), &connection)
+        .await
+        .unwrap();
+    assert_eq!(column_names(&schema, "users"), ["id", "cached"]);
+
+    // Refreshing doesn't look at the cache
+    let schema = cache.refresh(database.as_ref(), &connection).await.unwrap();
+    assert_eq!(column_names(&schema, "users"), ["id", "name"]);
+
+    database
+        .execute("ALTER TABLE users ADD COLUMN email TEXT")
+        .await
+        .unwrap();
+    let schema = cache
+        .get_schema(database.as_ref(), &connection)
+        .await
+        .unwrap();
+    assert_eq!(column_names(&schema, "users"), ["id", "name", "email"]);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL"]
+async fn postgres_fingerprint() {
+    let database = connect(&postgres_url(), Access::ReadWrite).await;
+    database
+        .execute("DROP TABLE IF EXISTS peek_test_fingerprint")
+        .await
+        .unwrap();
+
+    let before = database.schema_fingerprint().await.unwrap().unwrap();
+    database.execute("SELECT 1").await.unwrap();
+    assert_eq!(
+        database.schema_fingerprint().await.unwrap().unwrap(),
+        before
+    );
+
+    database
+        .execute("CREATE TABLE peek_test_fingerprint (id int)")
+        .await
+        .unwrap();
+    let created = database.schema_fingerprint().await.unwrap().unwrap();
+    assert_ne!(created, before);
+
+    database
+        .execute("COMMENT ON TABLE peek_test_fingerprint IS 'changed'")
+        .await
+        .unwrap();
+    let commented = database.schema_fingerprint().await.unwrap().unwrap();
+    assert_ne!(commented, created);
+
+    database
+        .execute("DROP TABLE peek_test_fingerprint")
+        .await
+        .unwrap();
+    assert_ne!(
+        database.schema_fingerprint().await.unwrap().unwrap(),
+        commented
+    );
+}
@@ -0,0 +1,70 @@
+//! Connections and databases shared by the tests.
+
+// Each test file is a crate of its own and uses only some of these
+#![allow(dead_code)]
+
+use config::{Access, DatabaseConnection, PoolConfig};
+use std::path::PathBuf;
+
+/// The database of `PEEK_TEST_POSTGRES_URL`, which the ignored Postgres tests run against
+pub fn postgres_url() -> String {
+    std::env::var("PEEK_TEST_POSTGRES_URL").expect("PEEK_TEST_POSTGRES_URL is not set")
+}
+
+/// The database of `PEEK_TEST_MYSQL_URL`, which the ignored MySQL tests run against
+pub fn mysql_url() -> String {
+    std::env::var("PEEK_TEST_MYSQL_URL").expect("PEEK_TEST_MYSQL_URL is not set")
+}
+
+pub async fn connect(url: &str, access: Access) -> Box<dyn db::Database> {
+    connect_with(DatabaseConnection {
+        url: url.to_string(),
+        access,
+        ..DatabaseConnection::default()
+    })
+    .await
+}
+
+pub async fn connect_with(connection: DatabaseConnection) -> Box<dyn db::Database> {
+    db::connect(&connection).await.unwrap()
+}
+
+/// A database on a single connection, so that every query runs on the connection the one
+/// before it ran on
+pub async fn single_connection(url: &str) -> Box<dyn db::Database> {
+    connect_with(DatabaseConnection {
+        url: url.to_string(),
+        pool: PoolConfig {
+            max_connections: 1,
+            ..PoolConfig::default()
+        },
+        ..DatabaseConnection::default()
+    })
+    .await
+}
+
+/// A SQLite database in a new file, which is removed when it is dropped
+pub struct TempDatabase {
+    pub database: Box<dyn db::Database>,
+    pub url: String,
+    path: PathBuf,
+}
+
+impl Drop for TempDatabase {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// A new database file for the test `name`, which is unique among all tests
+pub async fn temp_database(name: &str) -> TempDatabase {
+    let path = std::env::temp_dir().join(format!("peek_test_{name}_{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let url = format!("sqlite://{}?mode=rwc", path.display());
+    let database = connect(&url, Access::ReadWrite).await;
+    TempDatabase {
+        database,
+        url,
+        path,
+    }
+}
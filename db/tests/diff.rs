@@ -0,0 +1,362 @@
+//! Comparing schemas, and the migrations between them.
+//!
+//! Running a migration needs a database, that test is ignored by default. Run it with
+//! `PEEK_TEST_POSTGRES_URL` set and `cargo test -p db -- --ignored`.
+
+mod common;
+
+use common::{connect, postgres_url};
+use config::Access;
+use db::{
+    Column, CustomType, CustomTypeKind, ForeignKey, Index, RelationKind, Schema, ScriptOptions,
+    Table, UniqueConstraint,
+};
+
+fn column(name: &str, data_type: &str, nullable: bool) -> Column {
+    Column {
+        name: name.to_string(),
+        data_type: data_type.to_string(),
+        nullable,
+        ..Column::default()
+    }
+}
+
+fn table(name: &str, columns: Vec<Column>) -> Table {
+    Table {
+        schema: Some("public".to_string()),
+        name: name.to_string(),
+        primary_key: vec!["id".to_string()],
+        primary_key_name: Some(format!("{name}_pkey")),
+        columns,
+        ..Table::default()
+    }
+}
+
+fn mood(labels: &[&str]) -> CustomType {
+    CustomType {
+        schema: Some("public".to_string()),
+        name: "mood".to_string(),
+        kind: CustomTypeKind::Enum {
+            labels: labels.iter().map(|label| label.to_string()).collect(),
+        },
+    }
+}
+
+fn users() -> Table {
+    table(
+        "users",
+        vec![
+            column("id", "integer", false),
+            column("age", "integer", true),
+            column("nickname", "text", true),
+        ],
+    )
+}
+
+#[test]
+fn same_schema() {
+    let schema = Schema {
+        tables: vec![users()],
+        types: vec![mood(&["sad", "happy"])],
+    };
+    let diff = schema.diff(&schema.clone());
+    assert!(diff.is_empty());
+    assert_eq!(diff.postgres_migration(), "");
+}
+
+#[test]
+fn changed_columns() {
+    let from = Schema {
+        tables: vec![users()],
+        ..Schema::default()
+    };
+    let mut users = users();
+    users.columns[1] = Column {
+        default: Some("18".to_string()),
+        ..column("age", "bigint", false)
+    };
+    users.columns.remove(2);
+    users.columns.push(column("email", "text", false));
+    let to = Schema {
+        tables: vec![users],
+        ..Schema::default()
+    };
+
+    let diff = from.diff(&to);
+    assert!(diff.added_tables.is_empty() && diff.removed_tables.is_empty());
+    let table = &diff.changed_tables[0];
+    assert_eq!(table.added_columns[0].name, "email");
+    assert_eq!(table.removed_columns[0].name, "nickname");
+    assert_eq!(
+        table.changed_columns[0].to_string(),
+        "age: type integer -> bigint, NULL -> NOT NULL, default none -> 18"
+    );
+
+    assert_eq!(
+        diff.postgres_migration(),
+        "ALTER TABLE public.users ADD COLUMN email text NOT NULL;
+ALTER TABLE public.users ALTER COLUMN age TYPE bigint USING age::bigint;
+ALTER TABLE public.users ALTER COLUMN age SET NOT NULL;
+ALTER TABLE public.users ALTER COLUMN age SET DEFAULT 18;
+ALTER TABLE public.users DROP COLUMN nickname;
+"
+    );
+}
+
+#[test]
+fn added_and_removed_tables() {
+    let from = Schema {
+        tables: vec![
+            users(),
+            table("legacy", vec![column("id", "integer", false)]),
+            Table {
+                kind: RelationKind::View,
+                ..table("report", vec![])
+            },
+        ],
+        ..Schema::default()
+    };
+
+    let mut posts = table(
+        "posts",
+        vec![
+            Column {
+                default: Some("nextval('posts_id_seq'::regclass)".to_string()),
+                ..column("id", "integer", false)
+            },
+            column("user_id", "integer", false),
+            column("Order", "text", true),
+        ],
+    );
+    posts.unique_constraints.push(UniqueConstraint {
+        name: "posts_order_key".to_string(),
+        columns: vec!["Order".to_string()],
+    });
+    posts.indexes.push(Index {
+        name: "posts_user_idx".to_string(),
+        columns: vec!["user_id".to_string()],
+        unique: false,
+    });
+    posts.foreign_keys.push(ForeignKey {
+        name: Some("posts_user_id_fkey".to_string()),
+        columns: vec!["user_id".to_string()],
+        referenced_schema: Some("public".to_string()),
+        referenced_table: "users".to_string(),
+        referenced_columns: vec!["id".to_string()],
+    });
+    let to = Schema {
+        tables: vec![users(), posts],
+        ..Schema::default()
+    };
+
+    let diff = from.diff(&to);
+    assert_eq!(
+        diff.to_string(),
+        "+ table public.posts\n- table public.legacy\n- view public.report\n"
+    );
+    assert_eq!(
+        diff.postgres_migration(),
+        r#"CREATE SEQUENCE IF NOT EXISTS posts_id_seq;
+CREATE TABLE public.posts (
+    id integer NOT NULL DEFAULT nextval('posts_id_seq'::regclass),
+    user_id integer NOT NULL,
+    "Order" text,
+    PRIMARY KEY (id),
+    CONSTRAINT posts_order_key UNIQUE ("Order")
+);
+CREATE INDEX posts_user_idx ON public.posts (user_id);
+ALTER TABLE public.posts ADD CONSTRAINT posts_user_id_fkey FOREIGN KEY (user_id) REFERENCES public.users (id);
+DROP VIEW public.report;
+DROP TABLE public.legacy;
+"#
+    );
+}
+
+#[test]
+fn changed_indexes_and_keys() {
+    let mut from_users = users();
+    from_users.indexes.push(Index {
+        name: "users_age_idx".to_string(),
+        columns: vec!["age".to_string()],
+        unique: false,
+    });
+    let mut to_users = users();
+    to_users.primary_key = vec!["id".to_string(), "age".to_string()];
+    to_users.indexes.push(Index {
+        name: "users_age_idx".to_string(),
+        columns: vec!["age".to_string(), "lower(nickname)".to_string()],
+        unique: true,
+    });
+
+    let from = Schema {
+        tables: vec![from_users],
+        ..Schema::default()
+    };
+    let to = Schema {
+        tables: vec![to_users],
+        ..Schema::default()
+    };
+
+    let diff = from.diff(&to);
+    let table = &diff.changed_tables[0];
+    assert_eq!(table.added_indexes.len(), 1);
+    assert_eq!(table.removed_indexes.len(), 1);
+    assert_eq!(
+        diff.postgres_migration(),
+        "DROP INDEX public.users_age_idx;
+ALTER TABLE public.users DROP CONSTRAINT users_pkey;
+ALTER TABLE public.users ADD PRIMARY KEY (id, age);
+CREATE UNIQUE INDEX users_age_idx ON public.users (age, lower(nickname));
+"
+    );
+}
+
+#[test]
+fn primary_key_names() {
+    // Named explicitly, or kept from before the table was renamed
+    let mut from_users = users();
+    from_users.primary_key_name = Some("accounts pk".to_string());
+    let mut to_users = users();
+    to_users.primary_key = vec!["age".to_string()];
+
+    let from = Schema {
+        tables: vec![from_users.clone()],
+        ..Schema::default()
+    };
+    let to = Schema {
+        tables: vec![to_users],
+        ..Schema::default()
+    };
+    assert_eq!(
+        from.diff(&to).postgres_migration(),
+        r#"ALTER TABLE public.users DROP CONSTRAINT "accounts pk";
+ALTER TABLE public.users ADD PRIMARY KEY (age);
+"#
+    );
+
+    // Schemas from backends that don't name it leave the drop to be done by hand
+    from_users.primary_key_name = None;
+    let from = Schema {
+        tables: vec![from_users],
+        ..Schema::default()
+    };
+    assert!(
+        from.diff(&to)
+            .postgres_migration()
+            .starts_with("-- TODO: drop the unnamed primary key of public.users\n")
+    );
+
+    // Only the name isn't a change
+    let mut renamed = users();
+    renamed.primary_key_name = Some("users_key".to_string());
+    let renamed = Schema {
+        tables: vec![renamed],
+        ..Schema::default()
+    };
+    let users = Schema {
+        tables: vec![users()],
+        ..Schema::default()
+    };
+    assert!(users.diff(&renamed).is_empty());
+}
+
+#[test]
+fn comments() {
+    let mut from_users = users();
+    from_users.comment = Some("People".to_string());
+    from_users.columns[1].comment = Some("In years".to_string());
+    let mut to_users = users();
+    to_users.columns[2].comment = Some("What they're called".to_string());
+    to_users.columns.push(Column {
+        comment: Some("Where to reach them".to_string()),
+        ..column("email", "text", true)
+    });
+
+    let from = Schema {
+        tables: vec![from_users],
+        ..Schema::default()
+    };
+    let to = Schema {
+        tables: vec![to_users],
+        ..Schema::default()
+    };
+    assert_eq!(
+        from.diff(&to).postgres_migration(),
+        "ALTER TABLE public.users ADD COLUMN email text;
+COMMENT ON COLUMN public.users.email IS 'Where to reach them';
+COMMENT ON COLUMN public.users.age IS NULL;
+COMMENT ON COLUMN public.users.nickname IS 'What they''re called';
+COMMENT ON TABLE public.users IS NULL;
+"
+    );
+}
+
+#[test]
+fn enum_labels() {
+    let from = Schema {
+        types: vec![mood(&["sad", "happy"])],
+        ..Schema::default()
+    };
+
+    let added = Schema {
+        types: vec![mood(&["meh", "sad", "ok", "happy"])],
+        ..Schema::default()
+    };
+    assert_eq!(
+        from.diff(&added).postgres_migration(),
+        "ALTER TYPE public.mood ADD VALUE 'meh' BEFORE 'sad';
+ALTER TYPE public.mood ADD VALUE 'ok' AFTER 'sad';
+"
+    );
+
+    // Postgres can't remove or reorder labels
+    let reordered = Schema {
+        types: vec![mood(&["happy", "sad"])],
+        ..Schema::default()
+    };
+    assert!(
+        from.diff(&reordered)
+            .postgres_migration()
+            .starts_with("-- TODO: labels of public.mood were removed or reordered")
+    );
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL"]
+async fn postgres_migration() {
+    let database = connect(&postgres_url(), Access::ReadWrite).await;
+    for statement in [
+        "DROP TABLE IF EXISTS diff_accounts, diff_renamed",
+        "CREATE TABLE diff_accounts (id int CONSTRAINT diff_accounts_key PRIMARY KEY, email text)",
+        "COMMENT ON TABLE diff_accounts IS 'Accounts'",
+        // The primary key keeps its name
+        "ALTER TABLE diff_accounts RENAME TO diff_renamed",
+    ] {
+        database.execute(statement).await.unwrap();
+    }
+
+    let from = database.get_schema().await.unwrap();
+    let mut to = from.clone();
+    let table = to
+        .tables
+        .iter_mut()
+        .find(|table| table.name == "diff_renamed")
+        .unwrap();
+    assert_eq!(table.primary_key_name.as_deref(), Some("diff_accounts_key"));
+    table.primary_key = vec!["email".to_string()];
+    table.comment = None;
+
+    let migration = from.diff(&to).postgres_migration();
+    let options = ScriptOptions {
+        transaction: true,
+        stop_on_error: true,
+    };
+    let outcome = database.run_script(&migration, options).await.unwrap();
+    assert_eq!(outcome.failed(), 0, "{migration}");
+
+    let schema = database.get_schema().await.unwrap();
+    let table = schema.table("diff_renamed").unwrap();
+    assert_eq!(table.primary_key, ["email"]);
+    assert_eq!(table.comment, None);
+    database.execute("DROP TABLE diff_renamed").await.unwrap();
+}
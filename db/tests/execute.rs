@@ -0,0 +1,209 @@
+//! What running a statement reports: its command tag, the rows it affected and the server's
+//! notices.
+//!
+//! SQLite runs against a database in a temporary file. Postgres needs a database and is
+//! ignored by default; run it with `PEEK_TEST_POSTGRES_URL` set and
+//! `cargo test -p db -- --ignored`.
+
+mod common;
+
+use common::{connect, postgres_url, single_connection, temp_database};
+use config::Access;
+use std::sync::{Arc, Mutex};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata};
+
+/// Run each statement and check the tag and row count it reports
+async fn assert_outcomes(database: &dyn db::Database, expected: &[(&str, &str, u64)]) {
+    for (query, tag, rows_affected) in expected {
+        let outcome = database.execute(query).await.unwrap();
+        assert_eq!(outcome.command_tag, *tag, "{query}");
+        assert_eq!(outcome.rows_affected, *rows_affected, "{query}");
+    }
+}
+
+#[tokio::test]
+async fn sqlite() {
+    let temp = temp_database("execute").await;
+    let database = &temp.database;
+
+    assert_outcomes(
+        database.as_ref(),
+        &[
+            ("CREATE TABLE IF NOT EXISTS t (v int)", "CREATE TABLE", 0),
+            ("INSERT INTO t VALUES (1), (2), (3)", "INSERT 0 3", 3),
+            (
+                "/* all */ WITH new (v) AS (VALUES (4), (5)) INSERT INTO t SELECT v FROM new",
+                "INSERT 0 2",
+                2,
+            ),
+            ("UPDATE t SET v = v + 1 WHERE v > 3", "UPDATE 2", 2),
+            (
+                "WITH old AS (SELECT 1 AS v) DELETE FROM t WHERE v IN (SELECT v FROM old)",
+                "DELETE 1",
+                1,
+            ),
+            ("CREATE UNIQUE INDEX t_v ON t (v)", "CREATE INDEX", 0),
+            ("DROP TABLE t", "DROP TABLE", 0),
+        ],
+    )
+    .await;
+
+    let outcome = database.execute("SELECT 1").await.unwrap();
+    assert!(outcome.notices.is_empty());
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL"]
+async fn postgres() {
+    let database = connect(&postgres_url(), Access::ReadWrite).await;
+    database
+        .execute("DROP TABLE IF EXISTS execute_outcome")
+        .await
+        .unwrap();
+
+    assert_outcomes(
+        database.as_ref(),
+        &[
+            ("CREATE TABLE execute_outcome (v int)", "CREATE TABLE", 0),
+            (
+                "INSERT INTO execute_outcome SELECT generate_series(1, 5)",
+                "INSERT 0 5",
+                5,
+            ),
+            (
+                "WITH d AS (DELETE FROM execute_outcome WHERE v > 3 RETURNING *) SELECT * FROM d",
+                "SELECT 2",
+                2,
+            ),
+            (
+                "WITH n AS (SELECT 1) UPDATE execute_outcome SET v = v * 10",
+                "UPDATE 3",
+                3,
+            ),
+            ("VALUES (1), (2)", "SELECT 2", 2),
+            (
+                "CREATE MATERIALIZED VIEW IF NOT EXISTS execute_view AS SELECT 1",
+                "CREATE MATERIALIZED VIEW",
+                1,
+            ),
+            (
+                "DROP MATERIALIZED VIEW execute_view",
+                "DROP MATERIALIZED VIEW",
+                0,
+            ),
+        ],
+    )
+    .await;
+
+    let outcome = database
+        .execute("DO $$ BEGIN RAISE NOTICE 'one'; RAISE WARNING 'two'; END $$")
+        .await
+        .unwrap();
+    assert_eq!(outcome.command_tag, "DO");
+    assert_eq!(outcome.rows_affected, 0);
+    assert_eq!(outcome.notices, ["one", "two"]);
+
+    // Notices of earlier statements don't show up on later ones
+    let outcome = database
+        .execute("DROP TABLE execute_outcome")
+        .await
+        .unwrap();
+    assert!(outcome.notices.is_empty(), "{:?}", outcome.notices);
+    let outcome = database
+        .execute("DROP TABLE IF EXISTS execute_outcome")
+        .await
+        .unwrap();
+    assert_eq!(outcome.notices.len(), 1);
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL"]
+async fn postgres_tags_of_rewritten_statements() {
+    // Temporary objects live on a single connection
+    let database = single_connection(&postgres_url()).await;
+    for setup in [
+        "CREATE TEMP TABLE tag_source (v int)",
+        "CREATE TEMP TABLE tag_target (v int)",
+        "CREATE RULE tag_redirect AS ON INSERT TO tag_source \
+         DO INSTEAD INSERT INTO tag_target VALUES (NEW.v), (NEW.v)",
+        "CREATE TEMP VIEW tag_view AS SELECT v FROM tag_target",
+        "CREATE FUNCTION pg_temp.tag_insert() RETURNS trigger LANGUAGE plpgsql \
+         AS $$ BEGIN INSERT INTO tag_target VALUES (NEW.v); RETURN NEW; END $$",
+        "CREATE TRIGGER tag_insert INSTEAD OF INSERT ON tag_view \
+         FOR EACH ROW EXECUTE FUNCTION pg_temp.tag_insert()",
+    ] {
+        database.execute(setup).await.unwrap();
+    }
+
+    // Postgres reports the command that was written, with the count of what the rewritten one did
+    assert_outcomes(
+        database.as_ref(),
+        &[
+            ("INSERT INTO tag_source VALUES (1)", "INSERT 0 2", 2),
+            ("INSERT INTO tag_view VALUES (2), (3)", "INSERT 0 2", 2),
+            (
+                "WITH a AS (INSERT INTO tag_target VALUES (4) RETURNING *), \
+                 d AS (DELETE FROM tag_target WHERE v = 1 RETURNING *) UPDATE tag_target SET v = 0",
+                "UPDATE 4",
+                4,
+            ),
+            // The server reports `SELECT 5`, the derived tag names the statement instead
+            (
+                "CREATE TEMP TABLE tag_copy AS SELECT * FROM tag_target",
+                "CREATE TABLE",
+                5,
+            ),
+            // The rows are deleted, but the server's tag has no count
+            ("EXPLAIN ANALYZE DELETE FROM tag_target", "EXPLAIN", 0),
+        ],
+    )
+    .await;
+}
+
+/// The targets of the events it receives, standing in for an app's own tracing
+#[derive(Clone, Default)]
+struct Targets(Arc<Mutex<Vec<String>>>);
+
+impl tracing::Subscriber for Targets {
+    fn enabled(&self, _: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, _: &Attributes<'_>) -> Id {
+        Id::from_u64(1)
+    }
+
+    fn record(&self, _: &Id, _: &Record<'_>) {}
+
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let target = event.metadata().target().to_string();
+        self.0.lock().unwrap().push(target);
+    }
+
+    fn enter(&self, _: &Id) {}
+
+    fn exit(&self, _: &Id) {}
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL"]
+async fn postgres_notices_keep_tracing() {
+    let database = connect(&postgres_url(), Access::ReadWrite).await;
+    let targets = Targets::default();
+    let _default = tracing::subscriber::set_default(targets.clone());
+
+    let outcome = database
+        .execute("DO $$ BEGIN RAISE NOTICE 'one'; END $$")
+        .await
+        .unwrap();
+    assert_eq!(outcome.notices, ["one"]);
+
+    // Events while notices are collected still reach the subscriber that was in place
+    let targets = targets.0.lock().unwrap();
+    for target in ["sqlx::postgres::notice", "sqlx::query"] {
+        assert!(targets.iter().any(|t| t == target), "{target}: {targets:?}");
+    }
+}
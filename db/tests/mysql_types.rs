@@ -0,0 +1,119 @@
+//! Decoding of MySQL values into JSON.
+//!
+//! These need a database and are ignored by default. Run them with `PEEK_TEST_MYSQL_URL` set:
+//! `PEEK_TEST_MYSQL_URL=mysql://root@localhost/test cargo test -p db -- --ignored`
+
+mod common;
+
+use config::Access;
+use db::DbError;
+use serde_json::{Value, json};
+
+async fn connect() -> Box<dyn db::Database> {
+    common::connect(&common::mysql_url(), Access::ReadWrite).await
+}
+
+/// Store `value` in a temporary column of `column_type` and read it back
+async fn column_value(column_type: &str, value: &str) -> Result<Value, DbError> {
+    let database = connect().await;
+    let mut session = database.session().await.unwrap();
+    // Without the strict modes, out-of-range values such as zero dates can be stored
+    session.execute("SET SESSION sql_mode = ''").await.unwrap();
+    session
+        .execute(&format!(
+            "CREATE TEMPORARY TABLE decode (value {column_type})"
+        ))
+        .await
+        .unwrap();
+    session
+        .execute(&format!("INSERT INTO decode VALUES ({value})"))
+        .await
+        .unwrap();
+
+    let results = session.get_results("SELECT value FROM decode").await?;
+    Ok(results.rows[0][0].clone())
+}
+
+async fn assert_decodes(column_type: &str, value: &str, expected: Value) {
+    let decoded = column_value(column_type, value).await.unwrap();
+    assert_eq!(decoded, expected, "{column_type} {value}");
+}
+
+/// Check that the value fails to decode, instead of turning into null
+async fn assert_fails(column_type: &str, value: &str) {
+    let error = column_value(column_type, value).await.unwrap_err();
+    assert!(
+        matches!(error, DbError::Decode(_)),
+        "{column_type}: {error}"
+    );
+    assert!(error.to_string().contains("column value"), "{error}");
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_MYSQL_URL"]
+async fn null() {
+    assert_decodes("INT", "NULL", Value::Null).await;
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_MYSQL_URL"]
+async fn unsigned_integers() {
+    assert_decodes("TINYINT UNSIGNED", "255", json!(255)).await;
+    assert_decodes("INT UNSIGNED", "4294967295", json!(4_294_967_295_u64)).await;
+    assert_decodes(
+        "BIGINT UNSIGNED",
+        "18446744073709551615",
+        json!(18_446_744_073_709_551_615_u64),
+    )
+    .await;
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_MYSQL_URL"]
+async fn decimal() {
+    assert_decodes("DECIMAL(10, 2)", "12.5", json!("12.50")).await;
+    assert_decodes("DECIMAL(10, 4)", "-0.0001", json!("-0.0001")).await;
+    // More digits than rust_decimal's 28
+    assert_decodes(
+        "DECIMAL(65, 30)",
+        "12345678901234567890123456789012345.123456789012345678901234567890",
+        json!("12345678901234567890123456789012345.123456789012345678901234567890"),
+    )
+    .await;
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_MYSQL_URL"]
+async fn datetime() {
+    assert_decodes(
+        "DATETIME(2)",
+        "'2024-02-29 13:45:07.25'",
+        json!("2024-02-29T13:45:07.250"),
+    )
+    .await;
+    assert_fails("DATETIME", "'0000-00-00 00:00:00'").await;
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_MYSQL_URL"]
+async fn json() {
+    assert_decodes(
+        "JSON",
+        r#"'{"a": [1, true, null]}'"#,
+        json!({"a": [1, true, null]}),
+    )
+    .await;
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_MYSQL_URL"]
+async fn enums() {
+    assert_decodes("ENUM('sad', 'happy')", "'happy'", json!("happy")).await;
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_MYSQL_URL"]
+async fn sets() {
+    assert_decodes("SET('a', 'b', 'c')", "'c,a'", json!(["a", "c"])).await;
+    assert_decodes("SET('a', 'b', 'c')", "''", json!([])).await;
+}
@@ -0,0 +1,181 @@
+//! Binding params to the placeholders of a query.
+//!
+//! SQLite runs against an in-memory database. Postgres needs a database and is ignored by
+//! default; run it with `PEEK_TEST_POSTGRES_URL` set and `cargo test -p db -- --ignored`.
+
+mod common;
+
+use common::{connect, postgres_url};
+use config::Access;
+use db::Param;
+use serde_json::{Value, json};
+
+async fn postgres() -> Box<dyn db::Database> {
+    connect(&postgres_url(), Access::ReadWrite).await
+}
+
+/// The single value `query` returns with `params`
+async fn select(database: &dyn db::Database, query: &str, params: &[Param]) -> Value {
+    let results = database.get_results_with(query, params).await.unwrap();
+    results.rows[0][0].clone()
+}
+
+#[tokio::test]
+async fn sqlite() {
+    let database = connect("sqlite::memory:", Access::ReadWrite).await;
+    let params = [
+        Param::from(1),
+        Param::from("two"),
+        Param::from(3.5),
+        Param::Null,
+        Param::from(vec![0x0a, 0xff]),
+    ];
+    let results = database
+        .get_results_with("SELECT ?, ?, ?, ?, ?", &params)
+        .await
+        .unwrap();
+    assert_eq!(
+        results.rows,
+        [[
+            json!(1),
+            json!("two"),
+            json!(3.5),
+            Value::Null,
+            json!("\\x0aff")
+        ]]
+    );
+
+    let error = database
+        .get_results_with("SELECT ?, ?", &[Param::from(1)])
+        .await
+        .unwrap_err();
+    assert!(
+        error.to_string().contains("2 placeholders, but 1 params"),
+        "{error}"
+    );
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL"]
+async fn postgres_scalars() {
+    let database = postgres().await;
+    let database = database.as_ref();
+
+    // Params are converted to the type of their placeholder
+    assert_eq!(
+        select(database, "SELECT $1::date + 1", &["2024-02-28".into()]).await,
+        json!("2024-02-29")
+    );
+    assert_eq!(
+        select(database, "SELECT $1::numeric * 2", &["1.25".into()]).await,
+        json!("2.50")
+    );
+    assert_eq!(
+        select(
+            database,
+            "SELECT $1::jsonb -> 'a'",
+            &[json!({"a": [1]}).into()]
+        )
+        .await,
+        json!([1])
+    );
+    assert_eq!(
+        select(database, "SELECT $1::int4 IS NULL", &[Param::Null]).await,
+        json!(true)
+    );
+
+    let error = database
+        .get_results_with("SELECT $1::int2", &[Param::from(100_000)])
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("out of range"), "{error}");
+
+    // Integers too large for an int8 keep every digit, or fail where they don't fit
+    let large = Param::from(json!(u64::MAX));
+    assert_eq!(large, Param::from("18446744073709551615"));
+    assert_eq!(
+        select(database, "SELECT $1::numeric", std::slice::from_ref(&large)).await,
+        json!("18446744073709551615")
+    );
+    for param in [large, Param::from(1e20)] {
+        let error = database
+            .get_results_with("SELECT $1::int8", &[param])
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("too large"), "{error}");
+    }
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL"]
+async fn postgres_arrays() {
+    let database = postgres().await;
+    let database = database.as_ref();
+
+    let ids = Param::from(json!([1, 3]));
+    assert_eq!(
+        select(
+            database,
+            "SELECT array_agg(i ORDER BY i) FROM generate_series(1, 5) i WHERE i = ANY($1)",
+            &[ids],
+        )
+        .await,
+        json!([1, 3])
+    );
+
+    // Elements are converted like other params, and can be null
+    assert_eq!(
+        select(
+            database,
+            "SELECT $1::date[]",
+            &[json!(["2024-01-31", null]).into()]
+        )
+        .await,
+        json!(["2024-01-31", null])
+    );
+    assert_eq!(
+        select(
+            database,
+            "SELECT cardinality($1::text[])",
+            &[json!([]).into()]
+        )
+        .await,
+        json!(0)
+    );
+    // Text holding a JSON array, the way it may come from a form
+    assert_eq!(
+        select(database, "SELECT $1::int8[]", &["[5, 6]".into()]).await,
+        json!([5, 6])
+    );
+
+    let error = database
+        .get_results_with("SELECT $1::int4[]", &["{1,2}".into()])
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("JSON arrays"), "{error}");
+    let error = database
+        .get_results_with("SELECT $1::int4[]", &[json!(["one"]).into()])
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("invalid digit"), "{error}");
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL"]
+async fn postgres_enum_arrays() {
+    let database = postgres().await;
+    let mut session = database.session().await.unwrap();
+    session
+        .execute("CREATE TYPE pg_temp.param_mood AS ENUM ('sad', 'happy')")
+        .await
+        .unwrap();
+
+    let results = session
+        .get_results_with(
+            "SELECT $1::pg_temp.param_mood[]",
+            &[json!(["happy", "sad"]).into()],
+        )
+        .await
+        .unwrap();
+    assert_eq!(results.rows[0][0], json!(["happy", "sad"]));
+}
@@ -0,0 +1,319 @@
+//! Decoding of Postgres values into JSON.
+//!
+//! These need a database and are ignored by default. Run them with `PEEK_TEST_POSTGRES_URL` set:
+//! `PEEK_TEST_POSTGRES_URL=postgres://postgres@localhost/postgres cargo test -p db -- --ignored`
+
+mod common;
+
+use config::Access;
+use serde_json::{Value, json};
+
+async fn connect() -> Box<dyn db::Database> {
+    common::connect(&common::postgres_url(), Access::ReadWrite).await
+}
+
+/// Select `expression` after running `setup` on the same connection, and compare the result
+async fn assert_decodes_with(setup: &[&str], expression: &str, expected: Value) {
+    let database = connect().await;
+    let mut session = database.session().await.unwrap();
+    for statement in setup {
+        session.execute(statement).await.unwrap();
+    }
+
+    let results = session
+        .get_results(&format!("SELECT {expression} AS value"))
+        .await
+        .unwrap();
+    assert_eq!(results.rows[0][0], expected, "{expression}");
+}
+
+async fn assert_decodes(expression: &str, expected: Value) {
+    assert_decodes_with(&[], expression, expected).await;
+}
+
+/// Select `expression` and check that it fails to decode with an error containing `message`
+async fn assert_fails(expression: &str, message: &str) {
+    let database = connect().await;
+    let error = database
+        .get_results(&format!("SELECT {expression} AS value"))
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains(message), "{expression}: {error}");
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL"]
+async fn null() {
+    assert_decodes("NULL::int4", Value::Null).await;
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL"]
+async fn bool() {
+    assert_decodes("true", json!(true)).await;
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL"]
+async fn integers() {
+    assert_decodes("(-12)::int2", json!(-12)).await;
+    assert_decodes("2147483647::int4", json!(2_147_483_647)).await;
+    assert_decodes(
+        "9223372036854775807::int8",
+        json!(9_223_372_036_854_775_807_i64),
+    )
+    .await;
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL"]
+async fn oid() {
+    assert_decodes("'pg_class'::regclass::oid", json!(1259)).await;
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL"]
+async fn floats() {
+    assert_decodes("1.5::float4", json!(1.5)).await;
+    assert_decodes("1.1::float4", json!(1.1)).await;
+    assert_decodes("-0.125::float8", json!(-0.125)).await;
+    assert_decodes("'NaN'::float8", json!("NaN")).await;
+    assert_decodes("'-Infinity'::float4", json!("-Infinity")).await;
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL"]
+async fn numeric() {
+    assert_decodes("12.50::numeric(10, 2)", json!("12.50")).await;
+    assert_decodes("(-0.0001)::numeric", json!("-0.0001")).await;
+    assert_decodes("0::numeric", json!("0")).await;
+    assert_decodes("10000::numeric", json!("10000")).await;
+    assert_decodes(
+        "123456789012345678901234567890.123456789::numeric",
+        json!("123456789012345678901234567890.123456789"),
+    )
+    .await;
+    assert_decodes("'NaN'::numeric", json!("NaN")).await;
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL"]
+async fn money() {
+    assert_decodes("12.34::money", json!("12.34")).await;
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL"]
+async fn uuid() {
+    assert_decodes(
+        "'a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11'::uuid",
+        json!("a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11"),
+    )
+    .await;
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL"]
+async fn text() {
+    assert_decodes("'héllo'::text", json!("héllo")).await;
+    assert_decodes("'abc'::varchar(5)", json!("abc")).await;
+    assert_decodes("'ab'::char(3)", json!("ab ")).await;
+    assert_decodes("'pg_class'::name", json!("pg_class")).await;
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL"]
+async fn date() {
+    assert_decodes("'2024-02-29'::date", json!("2024-02-29")).await;
+    assert_decodes("'infinity'::date", json!("infinity")).await;
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL"]
+async fn time() {
+    assert_decodes("'13:45:07.25'::time", json!("13:45:07.250")).await;
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL"]
+async fn timetz() {
+    assert_decodes("'13:45:07+02'::timetz", json!("13:45:07+02:00")).await;
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL"]
+async fn timestamp() {
+    assert_decodes(
+        "'2024-02-29 13:45:07.5'::timestamp",
+        json!("2024-02-29T13:45:07.500"),
+    )
+    .await;
+    assert_decodes("'-infinity'::timestamp", json!("-infinity")).await;
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL"]
+async fn timestamptz() {
+    assert_decodes(
+        "'2024-02-29 13:45:07+02'::timestamptz",
+        json!("2024-02-29T11:45:07+00:00"),
+    )
+    .await;
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL"]
+async fn interval() {
+    assert_decodes(
+        "'1 year 2 months 3 days 04:05:06.5'::interval",
+        json!("1 year 2 mons 3 days 04:05:06.5"),
+    )
+    .await;
+    assert_decodes("'-90 minutes'::interval", json!("-01:30:00")).await;
+    assert_decodes("'0'::interval", json!("00:00:00")).await;
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL"]
+async fn bytea() {
+    assert_decodes("'\\x00ff10'::bytea", json!("\\x00ff10")).await;
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL"]
+async fn network_addresses() {
+    assert_decodes("'192.168.1.5'::inet", json!("192.168.1.5")).await;
+    assert_decodes("'192.168.1.5/24'::inet", json!("192.168.1.5/24")).await;
+    assert_decodes("'10.0.0.0/8'::cidr", json!("10.0.0.0/8")).await;
+    assert_decodes("'::1'::inet", json!("::1")).await;
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL"]
+async fn mac_addresses() {
+    assert_decodes("'08:00:2B:01:02:03'::macaddr", json!("08:00:2b:01:02:03")).await;
+    assert_decodes(
+        "'08:00:2b:01:02:03:04:05'::macaddr8",
+        json!("08:00:2b:01:02:03:04:05"),
+    )
+    .await;
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL"]
+async fn bit_strings() {
+    assert_decodes("B'101'::bit(3)", json!("101")).await;
+    assert_decodes("B'1000000001'::varbit", json!("1000000001")).await;
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL"]
+async fn json() {
+    assert_decodes(r#"'{"a": [1, null]}'::json"#, json!({"a": [1, null]})).await;
+    assert_decodes(r#"'{"a": true}'::jsonb"#, json!({"a": true})).await;
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL"]
+async fn arrays() {
+    assert_decodes("ARRAY[1, NULL, 3]::int4[]", json!([1, null, 3])).await;
+    assert_decodes("ARRAY['a', 'b']::text[]", json!(["a", "b"])).await;
+    assert_decodes("'{}'::int8[]", json!([])).await;
+    assert_decodes(
+        "ARRAY['2024-01-01'::date, 'infinity']",
+        json!(["2024-01-01", "infinity"]),
+    )
+    .await;
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL"]
+async fn multidimensional_arrays() {
+    assert_fails("ARRAY[[1, 2], [3, 4]]", "cast them to text").await;
+    assert_decodes("ARRAY[[1, 2], [3, 4]]::text", json!("{{1,2},{3,4}}")).await;
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL"]
+async fn geometry() {
+    assert_decodes("point(1, 2.5)", json!("(1,2.5)")).await;
+    assert_decodes("lseg(point(0, 0), point(1, 1))", json!("[(0,0),(1,1)]")).await;
+    assert_decodes("box(point(1, 1), point(0, 0))", json!("(1,1),(0,0)")).await;
+    assert_decodes("'{1,-1,0}'::line", json!("{1,-1,0}")).await;
+    assert_decodes("circle(point(1, 2), 3)", json!("<(1,2),3>")).await;
+    assert_decodes("'[(0,0),(1,1)]'::path", json!("[(0,0),(1,1)]")).await;
+    assert_decodes("'((0,0),(1,1))'::path", json!("((0,0),(1,1))")).await;
+    assert_decodes(
+        "'((0,0),(1,0),(1,1))'::polygon",
+        json!("((0,0),(1,0),(1,1))"),
+    )
+    .await;
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL"]
+async fn text_search() {
+    assert_decodes(
+        "'fat:2A cat:3 it''s'::tsvector",
+        json!("'cat':3 'fat':2A 'it''s'"),
+    )
+    .await;
+    assert_decodes("''::tsvector", json!("")).await;
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL"]
+async fn binary_types() {
+    assert_fails("'fat & cat'::tsquery", "cast them to text").await;
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL"]
+async fn enums() {
+    let setup = ["CREATE TYPE pg_temp.mood AS ENUM ('sad', 'happy')"];
+    assert_decodes_with(&setup, "'happy'::pg_temp.mood", json!("happy")).await;
+    assert_decodes_with(
+        &setup,
+        "ARRAY['sad', 'happy']::pg_temp.mood[]",
+        json!(["sad", "happy"]),
+    )
+    .await;
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL"]
+async fn domains() {
+    let setup = ["CREATE DOMAIN pg_temp.positive AS int4 CHECK (VALUE > 0)"];
+    assert_decodes_with(&setup, "5::pg_temp.positive", json!(5)).await;
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL"]
+async fn composites() {
+    let setup = ["CREATE TYPE pg_temp.item AS (name text, price numeric, tags text[])"];
+    assert_decodes_with(
+        &setup,
+        "ROW('pen', 1.50, ARRAY['office'])::pg_temp.item",
+        json!({"name": "pen", "price": "1.50", "tags": ["office"]}),
+    )
+    .await;
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL"]
+async fn records() {
+    assert_decodes("ROW(1, 'a', NULL::int4)", json!([1, "a", null])).await;
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL"]
+async fn ranges() {
+    assert_decodes("int4range(1, 10)", json!("[1,10)")).await;
+    assert_decodes("numrange(1.5, NULL, '(]')", json!("(1.5,)")).await;
+    assert_decodes("'empty'::int4range", json!("empty")).await;
+    assert_decodes(
+        "daterange('2024-01-01', '2024-02-01')",
+        json!("[2024-01-01,2024-02-01)"),
+    )
+    .await;
+}
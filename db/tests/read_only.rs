@@ -0,0 +1,173 @@
+//! Read-only connections refuse writes, whichever way a statement is run.
+//!
+//! SQLite runs against a database in a temporary file. Postgres and MySQL need a database and
+//! are ignored by default; run them with `PEEK_TEST_POSTGRES_URL` or `PEEK_TEST_MYSQL_URL` set
+//! and `cargo test -p db -- --ignored`.
+
+mod common;
+
+use common::{connect, mysql_url, postgres_url, temp_database};
+use config::Access;
+use db::{DbError, StreamLimits};
+
+/// Run `query` with each of the ways to run a statement, and check that every one fails with
+/// an error that `refused` accepts
+async fn assert_refused(database: &dyn db::Database, query: &str, refused: fn(&DbError) -> bool) {
+    let check = |way: &str, result: Result<(), DbError>| match result {
+        Ok(()) => panic!("{way} ran {query}"),
+        Err(error) => assert!(refused(&error), "{way} {query}: {error}"),
+    };
+
+    check("get_results", database.get_results(query).await.map(drop));
+    check("execute", database.execute(query).await.map(drop));
+
+    // The statement may only fail once its rows are fetched
+    let streamed = match database
+        .stream_results(query, &[], StreamLimits::default())
+        .await
+    {
+        Ok(mut stream) => match stream.next().await {
+            Some(Err(error)) => Err(error),
+            _ => Ok(()),
+        },
+        Err(error) => Err(error),
+    };
+    check("stream_results", streamed);
+
+    let mut session = database.session().await.unwrap();
+    check("session", session.execute(query).await.map(drop));
+    check("session", session.get_results(query).await.map(drop));
+
+    let mut transaction = database.begin().await.unwrap();
+    check("begin", transaction.execute(query).await.map(drop));
+}
+
+fn refused_by_server(error: &DbError, code: &str) -> bool {
+    error.to_string().contains("access mode")
+        || error.server_error().and_then(|error| error.code.as_deref()) == Some(code)
+}
+
+#[tokio::test]
+async fn sqlite() {
+    let temp = temp_database("read_only").await;
+    let writer = &temp.database;
+    writer
+        .execute("CREATE TABLE read_only (v int)")
+        .await
+        .unwrap();
+    writer
+        .execute("INSERT INTO read_only VALUES (1)")
+        .await
+        .unwrap();
+
+    let database = connect(&temp.url, Access::ReadOnly).await;
+    for query in [
+        "INSERT INTO read_only VALUES (2)",
+        "INSERT INTO read_only VALUES (2) RETURNING v",
+        "UPDATE read_only SET v = 3",
+        "CREATE TABLE created (v int)",
+        "DROP TABLE read_only",
+        "WITH new (v) AS (SELECT 2) INSERT INTO read_only SELECT v FROM new",
+    ] {
+        assert_refused(database.as_ref(), query, |error| {
+            error.to_string().contains("readonly")
+        })
+        .await;
+    }
+
+    let results = writer
+        .get_results("SELECT count(*), max(v) FROM read_only")
+        .await
+        .unwrap();
+    assert_eq!(results.rows, [[1, 1]]);
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL"]
+async fn postgres() {
+    let url = postgres_url();
+    let writer = connect(&url, Access::ReadWrite).await;
+    writer
+        .execute("DROP TABLE IF EXISTS read_only_writes")
+        .await
+        .unwrap();
+    writer
+        .execute("CREATE TABLE read_only_writes (v int)")
+        .await
+        .unwrap();
+
+    let database = connect(&url, Access::ReadOnly).await;
+    let refused = |error: &DbError| refused_by_server(error, "25006");
+    for query in [
+        "INSERT INTO read_only_writes VALUES (1)",
+        "CREATE TABLE read_only_created (v int)",
+        "WITH d AS (INSERT INTO read_only_writes VALUES (1) RETURNING *) SELECT * FROM d",
+        "SET TRANSACTION READ WRITE",
+        "SET default_transaction_read_only = off",
+        "SET SESSION CHARACTERISTICS AS TRANSACTION READ WRITE",
+    ] {
+        assert_refused(database.as_ref(), query, refused).await;
+    }
+
+    // A write after an attempt to turn read-only off, on the same connection
+    let mut session = database.session().await.unwrap();
+    for query in [
+        "SET default_transaction_read_only = off",
+        "INSERT INTO read_only_writes VALUES (1)",
+    ] {
+        let error = session.execute(query).await.unwrap_err();
+        assert!(refused(&error), "{query}: {error}");
+    }
+
+    let results = writer
+        .get_results("SELECT count(*) FROM read_only_writes")
+        .await
+        .unwrap();
+    assert_eq!(results.rows[0][0], 0);
+    writer.execute("DROP TABLE read_only_writes").await.unwrap();
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_MYSQL_URL"]
+async fn mysql() {
+    let url = mysql_url();
+    let writer = connect(&url, Access::ReadWrite).await;
+    writer
+        .execute("DROP TABLE IF EXISTS read_only_writes")
+        .await
+        .unwrap();
+    writer
+        .execute("CREATE TABLE read_only_writes (v int)")
+        .await
+        .unwrap();
+
+    let database = connect(&url, Access::ReadOnly).await;
+    let refused = |error: &DbError| refused_by_server(error, "25006");
+    for query in [
+        "INSERT INTO read_only_writes VALUES (1)",
+        // DDL commits implicitly, the session's access mode still applies after that
+        "CREATE TABLE read_only_created (v int)",
+        "WITH new (v) AS (SELECT 1) UPDATE read_only_writes, new SET read_only_writes.v = new.v",
+        "SET SESSION TRANSACTION READ WRITE",
+        "SET SESSION transaction_read_only = OFF",
+        "SET @@session.transaction_read_only = 0",
+    ] {
+        assert_refused(database.as_ref(), query, refused).await;
+    }
+
+    let mut session = database.session().await.unwrap();
+    for query in [
+        "SET SESSION transaction_read_only = OFF",
+        "CREATE TABLE read_only_created (v int)",
+    ] {
+        let error = session.execute(query).await.unwrap_err();
+        assert!(refused(&error), "{query}: {error}");
+    }
+
+    let results = writer
+        .get_results("SELECT count(*) FROM read_only_writes")
+        .await
+        .unwrap();
+    assert_eq!(results.rows[0][0], 0);
+    writer.execute("DROP TABLE read_only_writes").await.unwrap();
+}
@@ -0,0 +1,312 @@
+//! Splitting scripts into statements, and running them.
+//!
+//! Running scripts on SQLite uses a database in a temporary file. Postgres needs a database,
+//! those tests are ignored by default. Run them with `PEEK_TEST_POSTGRES_URL` set and
+//! `cargo test -p db -- --ignored`.
+
+mod common;
+
+use common::{connect, postgres_url, single_connection, temp_database};
+use config::Access;
+use db::{Backend, ScriptOptions, split_statements};
+
+fn split(script: &str, backend: Backend) -> Vec<String> {
+    split_statements(script, backend)
+        .into_iter()
+        .map(|statement| statement.sql)
+        .collect()
+}
+
+#[test]
+fn semicolons() {
+    assert_eq!(
+        split("SELECT 1;\nSELECT 2;;\n\nSELECT 3", Backend::Postgres),
+        ["SELECT 1", "SELECT 2", "SELECT 3"]
+    );
+}
+
+#[test]
+fn line_numbers() {
+    let statements = split_statements(
+        "SELECT 1;\n\n-- two\nSELECT 2; SELECT 3;\n",
+        Backend::Sqlite,
+    );
+    let lines: Vec<usize> = statements.iter().map(|statement| statement.line).collect();
+    assert_eq!(lines, [1, 3, 4]);
+}
+
+#[test]
+fn comments() {
+    assert_eq!(
+        split(
+            "-- a; comment\nSELECT 1 /* b; /* nested; */ c; */;\n-- only a comment;\n",
+            Backend::Postgres
+        ),
+        ["-- a; comment\nSELECT 1 /* b; /* nested; */ c; */"]
+    );
+    assert_eq!(
+        split("SELECT 1 # a; comment\n;SELECT 2--1;", Backend::MySql),
+        ["SELECT 1 # a; comment", "SELECT 2--1"]
+    );
+}
+
+#[test]
+fn quotes() {
+    assert_eq!(
+        split(
+            r#"SELECT 'a;''b', "c;""d"; SELECT E'\';', '\'; SELECT 2"#,
+            Backend::Postgres
+        ),
+        [
+            r#"SELECT 'a;''b', "c;""d""#,
+            r"SELECT E'\';', '\'",
+            "SELECT 2"
+        ]
+    );
+    assert_eq!(
+        split(r#"SELECT 'a\';', "b;", `c;`; SELECT 2"#, Backend::MySql),
+        [r#"SELECT 'a\';', "b;", `c;`"#, "SELECT 2"]
+    );
+    assert_eq!(
+        split("SELECT [a;b] FROM t; SELECT 2", Backend::Sqlite),
+        ["SELECT [a;b] FROM t", "SELECT 2"]
+    );
+}
+
+#[test]
+fn dollar_quotes() {
+    let function = "CREATE FUNCTION f() RETURNS int AS $body$\nBEGIN\n  PERFORM $$;$$;\n  RETURN 1;\nEND;\n$body$ LANGUAGE plpgsql";
+    assert_eq!(
+        split(
+            &format!("{function};\nSELECT $1, a$b FROM t; SELECT 2"),
+            Backend::Postgres
+        ),
+        [function, "SELECT $1, a$b FROM t", "SELECT 2"]
+    );
+}
+
+#[test]
+fn bodies() {
+    let atomic = "CREATE FUNCTION f() RETURNS int LANGUAGE sql BEGIN ATOMIC SELECT CASE WHEN true THEN 1 END; SELECT 2; END";
+    assert_eq!(
+        split(&format!("{atomic}; SELECT 3"), Backend::Postgres),
+        [atomic, "SELECT 3"]
+    );
+
+    let trigger = "CREATE TRIGGER t AFTER INSERT ON a BEGIN INSERT INTO b VALUES (1); UPDATE c SET d = CASE WHEN 1 THEN 2 END; END";
+    assert_eq!(
+        split(&format!("{trigger};\nBEGIN;\nCOMMIT"), Backend::Sqlite),
+        [trigger, "BEGIN", "COMMIT"]
+    );
+
+    assert_eq!(
+        split(
+            "CREATE RULE r AS ON INSERT TO a DO ALSO (INSERT INTO b VALUES (1); NOTIFY c); SELECT 1",
+            Backend::Postgres
+        ),
+        [
+            "CREATE RULE r AS ON INSERT TO a DO ALSO (INSERT INTO b VALUES (1); NOTIFY c)",
+            "SELECT 1"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn transactions_are_not_left_open() {
+    // A single connection, so that every statement runs on the connection the script ran on
+    let temp = temp_database("script").await;
+    let database = single_connection(&temp.url).await;
+    database.execute("CREATE TABLE t (v int)").await.unwrap();
+
+    // BEGIN with options isn't the same as the wrapping transaction, the script runs as written
+    let options = ScriptOptions {
+        transaction: true,
+        stop_on_error: false,
+    };
+    let outcome = database
+        .run_script(
+            "BEGIN IMMEDIATE;\nINSERT INTO t VALUES (1);\nCOMMIT;",
+            options,
+        )
+        .await
+        .unwrap();
+    assert_eq!(outcome.statements.len(), 3);
+    assert_eq!(outcome.failed(), 0);
+
+    // Stopped at an error inside the script's own transaction, or never committed
+    let options = ScriptOptions {
+        transaction: false,
+        stop_on_error: true,
+    };
+    for script in [
+        "BEGIN;\nINSERT INTO t VALUES (2);\nSELECT * FROM missing;\nCOMMIT;",
+        "BEGIN;\nINSERT INTO t VALUES (3);",
+    ] {
+        database.run_script(script, options).await.unwrap();
+        let results = database.get_results("SELECT v FROM t").await.unwrap();
+        assert_eq!(results.rows, [[1]], "{script}");
+    }
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL"]
+async fn run_in_transaction() {
+    let database = connect(&postgres_url(), Access::ReadWrite).await;
+    database
+        .execute("DROP TABLE IF EXISTS script_transaction")
+        .await
+        .unwrap();
+
+    let options = ScriptOptions {
+        transaction: true,
+        stop_on_error: false,
+    };
+    let outcome = database
+        .run_script(
+            "CREATE TABLE script_transaction (id int);\nINSERT INTO script_transaction VALUES (1), (2);\nSELECT 1/0;\nSELECT 3;",
+            options,
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(outcome.statements.len(), 3);
+    assert_eq!(
+        outcome.statements[1].result.as_ref().unwrap().command_tag,
+        "INSERT 0 2"
+    );
+    assert_eq!(outcome.statements[2].statement.line, 3);
+    assert!(outcome.statements[2].result.is_err());
+    assert_eq!(outcome.skipped, 1);
+    assert!(outcome.rolled_back);
+
+    let results = database
+        .get_results("SELECT to_regclass('script_transaction') IS NULL")
+        .await
+        .unwrap();
+    assert_eq!(results.rows[0][0], true);
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL"]
+async fn run_without_transaction() {
+    let database = connect(&postgres_url(), Access::ReadWrite).await;
+
+    // Later statements see the session state of earlier ones, and run after an error
+    let outcome = database
+        .run_script(
+            "CREATE TEMPORARY TABLE script_session (id int);\nSELECT 1/0;\nINSERT INTO script_session VALUES (1);\nDO $$ BEGIN RAISE NOTICE 'done;'; END $$;",
+            ScriptOptions::default(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(outcome.statements.len(), 4);
+    assert_eq!(outcome.failed(), 1);
+    assert!(outcome.statements[2].result.is_ok());
+    assert_eq!(
+        outcome.statements[3].result.as_ref().unwrap().notices,
+        ["done;"]
+    );
+    assert!(!outcome.rolled_back);
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL"]
+async fn run_with_begin_and_commit() {
+    let database = connect(&postgres_url(), Access::ReadWrite).await;
+    database
+        .execute("DROP TABLE IF EXISTS script_explicit")
+        .await
+        .unwrap();
+    let options = ScriptOptions {
+        transaction: true,
+        stop_on_error: false,
+    };
+
+    // The script's own transaction is the wrapping one, so it is still rolled back as a whole
+    let outcome = database
+        .run_script(
+            "BEGIN;\nCREATE TABLE script_explicit (id int);\nSELECT 1/0;\nCOMMIT;",
+            options,
+        )
+        .await
+        .unwrap();
+    assert_eq!(outcome.statements.len(), 2);
+    assert_eq!(outcome.statements[0].statement.line, 2);
+    assert!(outcome.rolled_back);
+
+    let outcome = database
+        .run_script(
+            "BEGIN;\nCREATE TABLE script_explicit (id int);\nINSERT INTO script_explicit VALUES (1);\nCOMMIT;",
+            options,
+        )
+        .await
+        .unwrap();
+    assert_eq!(outcome.statements.len(), 2);
+    assert_eq!(outcome.failed(), 0);
+
+    // Transactions of its own are run as written
+    let outcome = database
+        .run_script(
+            "BEGIN;\nINSERT INTO script_explicit VALUES (2);\nROLLBACK;\nBEGIN;\nINSERT INTO script_explicit VALUES (3);\nCOMMIT;",
+            options,
+        )
+        .await
+        .unwrap();
+    assert_eq!(outcome.statements.len(), 6);
+    assert_eq!(outcome.failed(), 0);
+
+    // and one left open by an error is rolled back
+    let outcome = database
+        .run_script(
+            "BEGIN;\nINSERT INTO script_explicit VALUES (4);\nSELECT 1/0;\nCOMMIT;\nBEGIN;\nCOMMIT;",
+            options,
+        )
+        .await
+        .unwrap();
+    assert_eq!(outcome.failed(), 1);
+    assert_eq!(outcome.skipped, 3);
+
+    let results = database
+        .get_results("SELECT array_agg(id ORDER BY id)::text FROM script_explicit")
+        .await
+        .unwrap();
+    assert_eq!(results.rows[0][0], "{1,3}");
+    database
+        .execute("DROP TABLE script_explicit")
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL"]
+async fn run_with_transaction_options() {
+    let database = single_connection(&postgres_url()).await;
+    let options = ScriptOptions {
+        transaction: true,
+        stop_on_error: false,
+    };
+
+    let outcome = database
+        .run_script(
+            "BEGIN ISOLATION LEVEL SERIALIZABLE;\nSET LOCAL application_name = 'script';\nCOMMIT;",
+            options,
+        )
+        .await
+        .unwrap();
+    assert_eq!(outcome.statements.len(), 3);
+    assert_eq!(outcome.failed(), 0);
+
+    // An aborted transaction would fail every later statement on the connection
+    let options = ScriptOptions {
+        transaction: false,
+        stop_on_error: true,
+    };
+    let outcome = database
+        .run_script("BEGIN;\nSELECT 1/0;\nCOMMIT;", options)
+        .await
+        .unwrap();
+    assert_eq!(outcome.skipped, 1);
+    let results = database.get_results("SELECT 1").await.unwrap();
+    assert_eq!(results.rows, [[1]]);
+}
@@ -0,0 +1,187 @@
+//! The SQLite backend: decoding by storage class and introspection from `sqlite_master` and the
+//! `pragma_*` functions. These run against a database in a temporary file.
+
+mod common;
+
+use common::temp_database;
+use db::{DbError, ForeignKey, Index, RelationKind, UniqueConstraint};
+use serde_json::{Value, json};
+
+#[tokio::test]
+async fn decodes_by_storage_class() {
+    let temp = temp_database("sqlite_decode").await;
+    let database = temp.database.as_ref();
+    database
+        .execute("CREATE TABLE decode (b BOOLEAN, i INTEGER, r REAL, t TEXT, bl BLOB, n TEXT, v)")
+        .await
+        .unwrap();
+    database
+        .execute("INSERT INTO decode VALUES (1, 42, 1.5, 'text', x'0aff', NULL, 7)")
+        .await
+        .unwrap();
+    database
+        .execute("INSERT INTO decode VALUES (0, -1, 0.25, '', x'', NULL, 'seven')")
+        .await
+        .unwrap();
+
+    let results = database.get_results("SELECT * FROM decode").await.unwrap();
+    assert_eq!(
+        results.rows,
+        [
+            [
+                json!(true),
+                json!(42),
+                json!(1.5),
+                json!("text"),
+                json!("\\x0aff"),
+                Value::Null,
+                json!(7)
+            ],
+            [
+                json!(false),
+                json!(-1),
+                json!(0.25),
+                json!(""),
+                json!("\\x"),
+                Value::Null,
+                json!("seven")
+            ],
+        ]
+    );
+}
+
+#[tokio::test]
+async fn headers_of_expressions() {
+    let temp = temp_database("sqlite_headers").await;
+
+    // Expressions have no declared type, the first row tells what they hold
+    let results = temp
+        .database
+        .get_results("SELECT 1 + 1 AS two, 'a' || 'b' AS ab, 0.5 AS half")
+        .await
+        .unwrap();
+    assert_eq!(
+        results.headers,
+        [
+            ("two".to_string(), "INTEGER".to_string()),
+            ("ab".to_string(), "TEXT".to_string()),
+            ("half".to_string(), "REAL".to_string()),
+        ]
+    );
+    assert_eq!(results.rows, [[json!(2), json!("ab"), json!(0.5)]]);
+}
+
+#[tokio::test]
+async fn decode_errors_are_returned() {
+    let temp = temp_database("sqlite_decode_error").await;
+
+    // Text that isn't UTF-8 can't be decoded into a string, it mustn't look like NULL
+    let error = temp
+        .database
+        .get_results("SELECT CAST(x'ff' AS TEXT) AS bad")
+        .await
+        .unwrap_err();
+    assert!(matches!(error, DbError::Decode(_)), "{error}");
+    assert!(error.to_string().contains("column bad"), "{error}");
+}
+
+#[tokio::test]
+async fn schema() {
+    let temp = temp_database("sqlite_schema").await;
+    let database = temp.database.as_ref();
+    for statement in [
+        "CREATE TABLE users (
+            id INTEGER PRIMARY KEY,
+            email TEXT NOT NULL UNIQUE,
+            name TEXT DEFAULT 'anonymous'
+        )",
+        "CREATE TABLE memberships (
+            user_id INTEGER REFERENCES users,
+            team TEXT,
+            since DATE,
+            PRIMARY KEY (team, user_id)
+        )",
+        "CREATE TABLE invites (
+            team TEXT,
+            user_id INTEGER,
+            FOREIGN KEY (team, user_id) REFERENCES memberships (team, user_id)
+        )",
+        "CREATE INDEX memberships_since ON memberships (since, team)",
+        "CREATE VIEW user_names AS SELECT id, name FROM users",
+    ] {
+        database.execute(statement).await.unwrap();
+    }
+
+    let schema = database.get_schema().await.unwrap();
+    assert_eq!(
+        schema
+            .tables
+            .iter()
+            .map(|table| table.name.as_str())
+            .collect::<Vec<_>>(),
+        ["invites", "memberships", "user_names", "users"]
+    );
+
+    let users = schema.table("users").unwrap();
+    assert_eq!(users.kind, RelationKind::Table);
+    assert_eq!(users.primary_key, ["id"]);
+    let email = users.column("email").unwrap();
+    assert_eq!(email.data_type, "TEXT");
+    assert!(!email.nullable);
+    assert_eq!(
+        users.column("name").unwrap().default.as_deref(),
+        Some("'anonymous'")
+    );
+    assert_eq!(
+        users.unique_constraints,
+        [UniqueConstraint {
+            name: "sqlite_autoindex_users_1".to_string(),
+            columns: vec!["email".to_string()],
+        }]
+    );
+
+    // Primary key columns come in key order, not in column order
+    let memberships = schema.table("memberships").unwrap();
+    assert_eq!(memberships.primary_key, ["team", "user_id"]);
+    assert_eq!(
+        memberships.indexes,
+        [Index {
+            name: "memberships_since".to_string(),
+            columns: vec!["since".to_string(), "team".to_string()],
+            unique: false,
+        }]
+    );
+    // A reference without columns is to the primary key of the other table
+    assert_eq!(
+        memberships.foreign_keys,
+        [ForeignKey {
+            name: None,
+            columns: vec!["user_id".to_string()],
+            referenced_schema: None,
+            referenced_table: "users".to_string(),
+            referenced_columns: vec!["id".to_string()],
+        }]
+    );
+
+    let invites = schema.table("invites").unwrap();
+    assert_eq!(
+        invites.foreign_keys,
+        [ForeignKey {
+            name: None,
+            columns: vec!["team".to_string(), "user_id".to_string()],
+            referenced_schema: None,
+            referenced_table: "memberships".to_string(),
+            referenced_columns: vec!["team".to_string(), "user_id".to_string()],
+        }]
+    );
+
+    let view = schema.table("user_names").unwrap();
+    assert_eq!(view.kind, RelationKind::View);
+    assert_eq!(
+        view.columns
+            .iter()
+            .map(|column| column.name.as_str())
+            .collect::<Vec<_>>(),
+        ["id", "name"]
+    );
+}
@@ -0,0 +1,81 @@
+//! Connecting through an SSH tunnel.
+//!
+//! Needs an SSH server that can reach the database, so these are ignored by default. Run them
+//! with `cargo test -p db -- --ignored` and `PEEK_TEST_SSH` (`user@host:port`) and
+//! `PEEK_TEST_POSTGRES_URL` set. The URL is the database as seen from the SSH server. `PEEK_TEST_SSH_PASSWORD`, `PEEK_TEST_SSH_KEY`,
+//! `PEEK_TEST_SSH_KEY_PASSPHRASE` and `PEEK_TEST_SSH_KNOWN_HOSTS` fill in the rest of the
+//! SSH config.
+
+mod common;
+
+use config::{DatabaseConnection, SSHConfig};
+
+fn connection() -> DatabaseConnection {
+    let ssh = std::env::var("PEEK_TEST_SSH").expect("PEEK_TEST_SSH is not set");
+    let url = common::postgres_url();
+
+    let (username, address) = ssh
+        .split_once('@')
+        .expect("PEEK_TEST_SSH is user@host:port");
+    let (host, port) = address.rsplit_once(':').unwrap_or((address, "22"));
+    let ssh = SSHConfig {
+        host: host.to_string(),
+        port: port.parse().unwrap(),
+        username: username.to_string(),
+        password: std::env::var("PEEK_TEST_SSH_PASSWORD").ok(),
+        ssh_key: std::env::var("PEEK_TEST_SSH_KEY").ok(),
+        ssh_key_passphrase: std::env::var("PEEK_TEST_SSH_KEY_PASSPHRASE").ok(),
+        known_hosts: std::env::var("PEEK_TEST_SSH_KNOWN_HOSTS").ok(),
+    };
+
+    DatabaseConnection {
+        url,
+        ssh: Some(ssh),
+        ..DatabaseConnection::default()
+    }
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_SSH and PEEK_TEST_POSTGRES_URL"]
+async fn query_through_tunnel() {
+    let connection = connection();
+    let database = db::connect(&connection).await.unwrap();
+
+    // Each pooled connection is forwarded on its own
+    let (first, second) = tokio::join!(
+        database.get_results("SELECT 1, pg_sleep(0.2)::text"),
+        database.get_results("SELECT 2, pg_sleep(0.2)::text"),
+    );
+    assert_eq!(first.unwrap().rows[0][0], 1);
+    assert_eq!(second.unwrap().rows[0][0], 2);
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_SSH and PEEK_TEST_POSTGRES_URL"]
+async fn unknown_host_key() {
+    let mut connection = connection();
+    let known_hosts = std::env::temp_dir().join("peek_test_known_hosts");
+    std::fs::write(&known_hosts, "").unwrap();
+    connection.ssh.as_mut().unwrap().known_hosts = Some(known_hosts.display().to_string());
+
+    let Err(error) = db::connect(&connection).await else {
+        panic!("connected");
+    };
+    let error = error.to_string();
+    assert!(error.contains("isn't in"), "{error}");
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_SSH and PEEK_TEST_POSTGRES_URL"]
+async fn wrong_password() {
+    let mut connection = connection();
+    let ssh = connection.ssh.as_mut().unwrap();
+    ssh.ssh_key = None;
+    ssh.password = Some("not the password".to_string());
+
+    let Err(error) = db::connect(&connection).await else {
+        panic!("connected");
+    };
+    let error = error.to_string();
+    assert!(error.contains("didn't accept"), "{error}");
+}
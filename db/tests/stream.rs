@@ -0,0 +1,321 @@
+//! Streaming results: row and byte caps, progress, stopping when the stream is dropped, and
+//! cancelling or timing out queries on the server.
+//!
+//! SQLite runs against a database in a temporary file. Postgres needs a database and is
+//! ignored by default; run it with `PEEK_TEST_POSTGRES_URL` set and
+//! `cargo test -p db -- --ignored`.
+
+mod common;
+
+use common::{connect, connect_with, postgres_url, single_connection, temp_database};
+use config::{Access, DatabaseConnection};
+use db::{Limit, RowStream, StreamLimits};
+use serde_json::{Value, json};
+use std::time::Duration;
+
+/// The numbers 1 to 1000, one per row
+const NUMBERS: &str = "WITH RECURSIVE n (i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 1000) SELECT i FROM n";
+
+async fn collect(stream: &mut RowStream) -> Vec<Vec<Value>> {
+    let mut rows = vec![];
+    while let Some(row) = stream.next().await {
+        rows.push(row.unwrap());
+    }
+    rows
+}
+
+async fn stream(database: &dyn db::Database, query: &str, limits: StreamLimits) -> RowStream {
+    database.stream_results(query, &[], limits).await.unwrap()
+}
+
+async fn stream_all(database: &dyn db::Database, query: &str) -> RowStream {
+    stream(database, query, StreamLimits::default()).await
+}
+
+#[tokio::test]
+async fn whole_result() {
+    let temp = temp_database("stream_whole").await;
+    let mut stream = stream_all(temp.database.as_ref(), NUMBERS).await;
+    assert_eq!(stream.headers[0].0, "i");
+
+    let rows = collect(&mut stream).await;
+    assert_eq!(rows.len(), 1000);
+    assert_eq!(rows[999], [json!(1000)]);
+    assert_eq!(stream.rows_fetched(), 1000);
+    assert_eq!(stream.truncated(), None);
+}
+
+#[tokio::test]
+async fn row_limit() {
+    let temp = temp_database("stream_rows").await;
+    let database = temp.database.as_ref();
+
+    let limits = StreamLimits {
+        max_rows: Some(10),
+        max_bytes: None,
+    };
+    let mut truncated = stream(database, NUMBERS, limits).await;
+    let rows = collect(&mut truncated).await;
+    assert_eq!(rows, (1..=10).map(|i| [json!(i)]).collect::<Vec<_>>());
+    assert_eq!(truncated.rows_fetched(), 10);
+    assert_eq!(truncated.truncated(), Some(Limit::Rows));
+
+    // A result that fits exactly isn't cut short
+    let limits = StreamLimits {
+        max_rows: Some(1000),
+        max_bytes: None,
+    };
+    let mut whole = stream(database, NUMBERS, limits).await;
+    assert_eq!(collect(&mut whole).await.len(), 1000);
+    assert_eq!(whole.truncated(), None);
+}
+
+#[tokio::test]
+async fn byte_limit() {
+    let temp = temp_database("stream_bytes").await;
+
+    // 1 to 9 take a byte each and 10 to 14 two, 15 would go past 20 bytes
+    let limits = StreamLimits {
+        max_rows: None,
+        max_bytes: Some(20),
+    };
+    let mut stream = stream(temp.database.as_ref(), NUMBERS, limits).await;
+    let rows = collect(&mut stream).await;
+    assert_eq!(rows.len(), 14);
+    assert_eq!(stream.truncated(), Some(Limit::Bytes));
+
+    // The first limit that is reached wins
+    let limits = StreamLimits {
+        max_rows: Some(5),
+        max_bytes: Some(20),
+    };
+    let mut stream = self::stream(temp.database.as_ref(), NUMBERS, limits).await;
+    assert_eq!(collect(&mut stream).await.len(), 5);
+    assert_eq!(stream.truncated(), Some(Limit::Rows));
+}
+
+#[tokio::test]
+async fn errors_are_streamed() {
+    let temp = temp_database("stream_errors").await;
+    let database = temp.database.as_ref();
+
+    // Errors in preparing the query are returned right away
+    let error = database
+        .stream_results("SELECT * FROM missing", &[], StreamLimits::default())
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("missing"), "{error}");
+
+    let mut stream = stream_all(database, "SELECT CAST(x'ff' AS TEXT) AS bad").await;
+    assert!(stream.next().await.unwrap().is_err());
+}
+
+#[tokio::test]
+async fn drop_mid_result() {
+    let temp = temp_database("stream_drop").await;
+    let database = temp.database.as_ref();
+    database
+        .execute("CREATE TABLE numbers (i int)")
+        .await
+        .unwrap();
+    database
+        .execute(&format!("INSERT INTO numbers {NUMBERS}"))
+        .await
+        .unwrap();
+
+    let mut stream = stream_all(database, "SELECT i FROM numbers ORDER BY i").await;
+    assert_eq!(stream.next().await.unwrap().unwrap(), [json!(1)]);
+    // Only rows up to the buffer's size are fetched ahead
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(stream.rows_fetched() < 1000, "{}", stream.rows_fetched());
+    drop(stream);
+
+    // The rest of the result is left unread and the database can be used as before
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let outcome = database.execute("DELETE FROM numbers").await.unwrap();
+    assert_eq!(outcome.rows_affected, 1000);
+}
+
+#[tokio::test]
+async fn sqlite_cancel() {
+    let temp = temp_database("stream_cancel").await;
+    let mut stream = stream_all(temp.database.as_ref(), NUMBERS).await;
+    // SQLite runs in-process, dropping the stream is what stops it
+    assert!(!stream.cancel_handle().cancel().await.unwrap());
+    assert_eq!(collect(&mut stream).await.len(), 1000);
+}
+
+/// Wait until no backend other than `database`'s runs a query containing `marker`
+async fn wait_until_stopped(database: &dyn db::Database, marker: &str) {
+    let query = format!(
+        "SELECT count(*) FROM pg_stat_activity \
+         WHERE state = 'active' AND query LIKE '%{marker}%' AND pid <> pg_backend_pid()"
+    );
+    for _ in 0..50 {
+        let results = database.get_results(&query).await.unwrap();
+        if results.rows[0][0] == 0 {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("the query is still running");
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL"]
+async fn postgres_limits() {
+    // Read-only connections stream in a transaction, which is ended either way
+    for access in [Access::ReadWrite, Access::ReadOnly] {
+        let database = connect(&postgres_url(), access).await;
+        let limits = StreamLimits {
+            max_rows: Some(100),
+            max_bytes: None,
+        };
+        let mut stream = stream(
+            database.as_ref(),
+            "SELECT i, repeat('x', 10) AS text FROM generate_series(1, 1000) i",
+            limits,
+        )
+        .await;
+        assert_eq!(
+            stream.headers,
+            [
+                ("i".to_string(), "INT4".to_string()),
+                ("text".to_string(), "TEXT".to_string())
+            ]
+        );
+        let rows = collect(&mut stream).await;
+        assert_eq!(rows.len(), 100);
+        assert_eq!(rows[99], [json!(100), json!("xxxxxxxxxx")]);
+        assert_eq!(stream.truncated(), Some(Limit::Rows));
+
+        let mut stream = stream_all(database.as_ref(), "SELECT 1").await;
+        assert_eq!(collect(&mut stream).await, [[json!(1)]]);
+    }
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL"]
+async fn postgres_drop_mid_result() {
+    let database = connect(&postgres_url(), Access::ReadWrite).await;
+    let mut stream = stream_all(
+        database.as_ref(),
+        // In the select list the series isn't materialized before the first row is sent
+        "SELECT generate_series(1, 100000000), 'peek_test_drop'",
+    )
+    .await;
+    assert_eq!(
+        stream.next().await.unwrap().unwrap(),
+        [json!(1), json!("peek_test_drop")]
+    );
+    drop(stream);
+
+    // Closing the connection stops the query on the server
+    wait_until_stopped(database.as_ref(), "peek_test_drop").await;
+}
+
+fn server_code(error: &db::DbError) -> Option<&str> {
+    error.server_error().and_then(|error| error.code.as_deref())
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL"]
+async fn postgres_cancel() {
+    let database = connect(&postgres_url(), Access::ReadWrite).await;
+    let mut stream = stream_all(database.as_ref(), "SELECT pg_sleep(30), 'peek_test_cancel'").await;
+    let cancel = stream.cancel_handle();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(cancel.cancel().await.unwrap());
+
+    let error = stream.next().await.unwrap().unwrap_err();
+    assert_eq!(server_code(&error), Some("57014"), "{error}");
+    assert!(stream.next().await.is_none());
+    wait_until_stopped(database.as_ref(), "peek_test_cancel").await;
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL"]
+async fn postgres_cancel_on_full_pool() {
+    // The stream holds the only connection of the pool, the cancel request can't wait for it
+    let database = single_connection(&postgres_url()).await;
+    let mut stream = stream_all(database.as_ref(), "SELECT pg_sleep(30)").await;
+    let cancel = stream.cancel_handle();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    // Sent from another task, the way an app cancels
+    let cancelled = tokio::spawn(async move { cancel.cancel().await });
+    let cancelled = tokio::time::timeout(Duration::from_secs(5), cancelled).await;
+    assert!(
+        cancelled
+            .expect("the cancel request waited")
+            .unwrap()
+            .unwrap()
+    );
+
+    let error = stream.next().await.unwrap().unwrap_err();
+    assert_eq!(server_code(&error), Some("57014"), "{error}");
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL"]
+async fn postgres_cancel_after_finish() {
+    // One connection, so that the next query runs on the connection of the finished one
+    let database = single_connection(&postgres_url()).await;
+
+    let mut stream = stream_all(database.as_ref(), "SELECT 1").await;
+    let cancel = stream.cancel_handle();
+    assert_eq!(collect(&mut stream).await, [[json!(1)]]);
+
+    // The query is done, a cancel request now would hit the next query on the connection
+    assert!(!cancel.cancel().await.unwrap());
+    let next = database.get_results("SELECT pg_sleep(0.2), 2");
+    let (results, cancelled) = tokio::join!(next, async {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        cancel.cancel().await.unwrap()
+    });
+    assert!(!cancelled);
+    assert_eq!(results.unwrap().rows[0][1], 2);
+}
+
+async fn with_timeouts(
+    statement_timeout_secs: Option<u64>,
+    lock_timeout_secs: Option<u64>,
+) -> Box<dyn db::Database> {
+    connect_with(DatabaseConnection {
+        url: postgres_url(),
+        statement_timeout_secs,
+        lock_timeout_secs,
+        ..DatabaseConnection::default()
+    })
+    .await
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL"]
+async fn postgres_timeouts() {
+    let database = with_timeouts(Some(1), None).await;
+    let error = database
+        .get_results("SELECT pg_sleep(5)")
+        .await
+        .unwrap_err();
+    assert_eq!(server_code(&error), Some("57014"), "{error}");
+
+    // Waiting for a lock held by another transaction
+    let writer = connect(&postgres_url(), Access::ReadWrite).await;
+    writer
+        .execute("CREATE TABLE IF NOT EXISTS stream_locked (v int)")
+        .await
+        .unwrap();
+    let mut transaction = writer.begin().await.unwrap();
+    transaction
+        .execute("LOCK TABLE stream_locked")
+        .await
+        .unwrap();
+    let database = with_timeouts(None, Some(1)).await;
+    let error = database
+        .get_results("SELECT * FROM stream_locked")
+        .await
+        .unwrap_err();
+    assert_eq!(server_code(&error), Some("55P03"), "{error}");
+    transaction.rollback().await.unwrap();
+    writer.execute("DROP TABLE stream_locked").await.unwrap();
+}
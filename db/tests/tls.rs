@@ -0,0 +1,90 @@
+//! TLS settings of connections.
+//!
+//! Connecting with TLS needs a database that has it on, so that test is ignored by default. Run
+//! it with `cargo test -p db -- --ignored` and `PEEK_TEST_POSTGRES_URL` and
+//! `PEEK_TEST_TLS_ROOT_CERT` (the CA that signed the server's certificate) set.
+
+mod common;
+
+use config::{DatabaseConnection, TlsConfig, TlsMode};
+
+async fn connection_error(url: &str, tls: TlsConfig) -> String {
+    let connection = DatabaseConnection {
+        url: url.to_string(),
+        tls,
+        ..DatabaseConnection::default()
+    };
+    match db::connect(&connection).await {
+        Ok(_) => panic!("connected"),
+        Err(error) => error.to_string(),
+    }
+}
+
+#[tokio::test]
+async fn missing_files() {
+    let tls = TlsConfig {
+        root_cert: Some("/nonexistent/ca.crt".to_string()),
+        ..TlsConfig::default()
+    };
+    let error = connection_error("postgres://localhost/postgres", tls).await;
+    assert!(
+        error.contains("tls.root_cert /nonexistent/ca.crt"),
+        "{error}"
+    );
+
+    let tls = TlsConfig {
+        client_cert: Some("/nonexistent/client.crt".to_string()),
+        ..TlsConfig::default()
+    };
+    let error = connection_error("postgres://localhost/postgres", tls).await;
+    assert!(error.contains("have to be set together"), "{error}");
+}
+
+#[tokio::test]
+async fn unsupported_settings() {
+    let tls = TlsConfig {
+        server_name: Some("db.example.com".to_string()),
+        ..TlsConfig::default()
+    };
+    let error = connection_error("mysql://localhost/mysql", tls).await;
+    assert!(error.contains("only supported for Postgres"), "{error}");
+
+    let tls = TlsConfig {
+        mode: Some(TlsMode::Require),
+        ..TlsConfig::default()
+    };
+    let error = connection_error("sqlite::memory:", tls).await;
+    assert!(error.contains("don't use TLS"), "{error}");
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL and PEEK_TEST_TLS_ROOT_CERT"]
+async fn verify_full() {
+    let url = common::postgres_url();
+    let root_cert =
+        std::env::var("PEEK_TEST_TLS_ROOT_CERT").expect("PEEK_TEST_TLS_ROOT_CERT is not set");
+
+    // Without the CA the certificate can't be trusted
+    let tls = TlsConfig {
+        mode: Some(TlsMode::VerifyFull),
+        ..TlsConfig::default()
+    };
+    let error = connection_error(&url, tls.clone()).await;
+    assert!(error.starts_with("TLS handshake failed"), "{error}");
+    assert!(error.contains("tls.root_cert"), "{error}");
+
+    let connection = DatabaseConnection {
+        url,
+        tls: TlsConfig {
+            root_cert: Some(root_cert),
+            ..tls
+        },
+        ..DatabaseConnection::default()
+    };
+    let database = db::connect(&connection).await.unwrap();
+    let results = database
+        .get_results("SELECT ssl FROM pg_stat_ssl WHERE pid = pg_backend_pid()")
+        .await
+        .unwrap();
+    assert_eq!(results.rows[0][0], true);
+}
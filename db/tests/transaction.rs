@@ -0,0 +1,232 @@
+//! Telling statements that change rows apart from ones that only read, and previewing changes.
+//!
+//! Previewing on Postgres needs a database, those tests are ignored by default. Run them with
+//! `PEEK_TEST_POSTGRES_URL` set and `cargo test -p db -- --ignored`.
+
+mod common;
+
+use common::{connect, postgres_url, temp_database};
+use config::Access;
+use db::{ScriptOptions, can_preview, is_read_only, modifies_rows};
+
+#[test]
+fn modifying_statements() {
+    assert!(modifies_rows("UPDATE t SET v = 1"));
+    assert!(modifies_rows("/* bump */ delete from t returning *"));
+    assert!(modifies_rows("REPLACE INTO t VALUES (1)"));
+    assert!(modifies_rows(
+        "WITH d AS (DELETE FROM t RETURNING *) SELECT * FROM d"
+    ));
+    assert!(modifies_rows("EXPLAIN ANALYZE DELETE FROM t"));
+    assert!(modifies_rows(
+        "INSERT INTO t VALUES (1) ON CONFLICT (id) DO UPDATE SET v = 2"
+    ));
+
+    assert!(!modifies_rows("SELECT replace(name, 'a', 'b') FROM t"));
+    assert!(!modifies_rows("SELECT insert(name, 1, 2, 'x') FROM t"));
+    assert!(!modifies_rows("SELECT 'delete from t' AS \"update\""));
+    assert!(!modifies_rows("SELECT * FROM t FOR NO KEY UPDATE"));
+    assert!(!modifies_rows(
+        "CREATE TABLE t (id int REFERENCES u ON DELETE CASCADE)"
+    ));
+    assert!(!modifies_rows("DO $$ BEGIN DELETE FROM t; END $$"));
+}
+
+#[test]
+fn read_only_statements() {
+    assert!(is_read_only("SELECT * FROM t -- all of them"));
+    assert!(is_read_only("(SELECT 1) UNION (SELECT 2)"));
+    assert!(is_read_only("WITH x AS (SELECT 1) SELECT * FROM x"));
+    assert!(is_read_only("EXPLAIN SELECT 1"));
+    assert!(is_read_only("SHOW search_path"));
+
+    assert!(!is_read_only(
+        "WITH d AS (DELETE FROM t RETURNING *) SELECT * FROM d"
+    ));
+    assert!(!is_read_only("SELECT * INTO copy FROM t"));
+    assert!(!is_read_only("CREATE TABLE t (id int)"));
+    assert!(!is_read_only("DO $$ BEGIN DELETE FROM t; END $$"));
+    assert!(!is_read_only("VACUUM t"));
+    assert!(!is_read_only(""));
+}
+
+#[test]
+fn previewable_statements() {
+    assert!(can_preview("UPDATE t SET v = 1"));
+    assert!(can_preview("-- add one\nINSERT INTO t VALUES (1)"));
+    assert!(can_preview("REPLACE INTO t VALUES (1)"));
+    assert!(can_preview(
+        "WITH d AS (DELETE FROM t RETURNING *) SELECT * FROM d"
+    ));
+
+    assert!(!can_preview("DROP TABLE t"));
+    assert!(!can_preview("ALTER TABLE t ADD COLUMN v int"));
+    assert!(!can_preview("CREATE TABLE t AS SELECT 1"));
+    assert!(!can_preview("WITH x AS (SELECT 1) SELECT * FROM x"));
+    assert!(!can_preview("EXPLAIN ANALYZE DELETE FROM t"));
+}
+
+#[tokio::test]
+async fn dry_run_refuses_ddl() {
+    let temp = temp_database("dry_run").await;
+    let database = &temp.database;
+    database
+        .execute("CREATE TABLE dry_run (v int)")
+        .await
+        .unwrap();
+
+    let error = database
+        .dry_run("DROP TABLE dry_run", &[])
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("can be previewed"), "{error}");
+    let changes = database
+        .dry_run("INSERT INTO dry_run VALUES (1)", &[])
+        .await
+        .unwrap();
+    assert_eq!(changes.rows, [[1]]);
+    let results = database
+        .get_results("SELECT count(*) FROM dry_run")
+        .await
+        .unwrap();
+    assert_eq!(results.rows[0][0], 0);
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL"]
+async fn preview_after_comment() {
+    let database = connect(&postgres_url(), Access::ReadWrite).await;
+    let mut transaction = database.begin().await.unwrap();
+    transaction
+        .execute("CREATE TEMPORARY TABLE preview_comment (v int)")
+        .await
+        .unwrap();
+    transaction
+        .execute("INSERT INTO preview_comment VALUES (1), (2)")
+        .await
+        .unwrap();
+
+    for query in [
+        "UPDATE preview_comment SET v = 5 -- bump all",
+        "UPDATE preview_comment SET v = 5; /* bump all */",
+    ] {
+        let changes = transaction.preview(query, &[]).await.unwrap();
+        assert_eq!(changes.rows_affected, 2, "{query}");
+        assert_eq!(changes.rows, [[5], [5]], "{query}");
+    }
+    transaction.rollback().await.unwrap();
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL"]
+async fn preview_merge() {
+    let database = connect(&postgres_url(), Access::ReadWrite).await;
+    let version = database
+        .get_results("SELECT current_setting('server_version_num')::int")
+        .await
+        .unwrap();
+    let returning = version.rows[0][0].as_i64().unwrap() >= 170_000;
+
+    let mut transaction = database.begin().await.unwrap();
+    transaction
+        .execute("CREATE TEMPORARY TABLE preview_merge (id int, v int)")
+        .await
+        .unwrap();
+    transaction
+        .execute("INSERT INTO preview_merge VALUES (1, 1)")
+        .await
+        .unwrap();
+
+    // Before Postgres 17 MERGE can't return rows, so they are only counted
+    let changes = transaction
+        .preview(
+            "MERGE INTO preview_merge t USING (VALUES (1), (2)) s (id) ON t.id = s.id \
+             WHEN MATCHED THEN UPDATE SET v = 2 WHEN NOT MATCHED THEN INSERT VALUES (s.id, 0)",
+            &[],
+        )
+        .await
+        .unwrap();
+    assert_eq!(changes.rows_affected, 2);
+    assert_eq!(changes.rows.is_empty(), !returning);
+    transaction.rollback().await.unwrap();
+}
+
+#[tokio::test]
+#[ignore = "needs PEEK_TEST_POSTGRES_URL"]
+async fn read_only_transactions_stay_read_only() {
+    let writer = connect(&postgres_url(), Access::ReadWrite).await;
+    writer
+        .execute("DROP TABLE IF EXISTS read_only_target")
+        .await
+        .unwrap();
+    writer
+        .execute("CREATE TABLE read_only_target (v int)")
+        .await
+        .unwrap();
+    let database = connect(&postgres_url(), Access::ReadOnly).await;
+
+    // Postgres lets the first statement of a transaction make it read-write
+    for statement in [
+        "SET TRANSACTION READ WRITE",
+        "set transaction isolation level serializable, read write",
+        "SET SESSION CHARACTERISTICS AS TRANSACTION READ WRITE",
+        "SET LOCAL transaction_read_only = off",
+        "SET \"transaction_read_only\" TO off",
+        "SET default_transaction_read_only = off",
+    ] {
+        let mut transaction = database.begin().await.unwrap();
+        let error = transaction.execute(statement).await.unwrap_err();
+        assert!(
+            error.to_string().contains("access mode"),
+            "{statement}: {error}"
+        );
+        let error = transaction
+            .execute("INSERT INTO read_only_target VALUES (1)")
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.server_error().and_then(|error| error.code.as_deref()),
+            Some("25006"),
+            "{statement}: {error}"
+        );
+    }
+
+    // Other settings can still be changed
+    let mut transaction = database.begin().await.unwrap();
+    transaction
+        .execute("SET LOCAL statement_timeout = '5s'")
+        .await
+        .unwrap();
+    transaction.rollback().await.unwrap();
+
+    let error = database
+        .dry_run("INSERT INTO read_only_target VALUES (1)", &[])
+        .await
+        .unwrap_err();
+    assert_eq!(
+        error.server_error().and_then(|error| error.code.as_deref()),
+        Some("25006"),
+        "{error}"
+    );
+
+    // The ui's `\i` runs scripts in a transaction
+    let outcome = database
+        .run_script(
+            "SET TRANSACTION READ WRITE;\nINSERT INTO read_only_target VALUES (1);",
+            ScriptOptions {
+                transaction: true,
+                ..ScriptOptions::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert!(outcome.rolled_back);
+    assert_eq!(outcome.failed(), 1);
+
+    let results = writer
+        .get_results("SELECT count(*) FROM read_only_target")
+        .await
+        .unwrap();
+    assert_eq!(results.rows[0][0], 0);
+    writer.execute("DROP TABLE read_only_target").await.unwrap();
+}
@@ -3,7 +3,7 @@ use ai::{HashMap, LLM, Tool, Value, create_tool, json};
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Create the LLM instance
-    let mut llm = LLM::new().await;
+    let mut llm = LLM::new();
 
     // Define tools specific to this application
     let query_database_tool = create_query_database_tool();
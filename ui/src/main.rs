@@ -1,58 +1,138 @@
 mod tools;
 
-use cliclack::{Input, select, spinner};
+use cliclack::{Input, confirm, select, spinner};
 use colored::Colorize;
 use comfy_table::Table;
-use db::Database;
 use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
 
 use crate::tools::query_tool;
 
+/// Query results are capped so that a runaway query can't hang the REPL or exhaust memory
+const MAX_ROWS: usize = 1000;
+const MAX_BYTES: usize = 1024 * 1024;
+
+/// Rounds of tool calls per prompt, after which the model has to answer with what it has
+const MAX_TOOL_ROUNDS: usize = 10;
+
+/// Sends Ctrl-C to the query being fetched. Once tokio handles the signal it no longer ends the
+/// process, so without a query it exits the way it would have without a handler.
+#[derive(Clone, Default)]
+struct CtrlC {
+    query: Arc<Mutex<Option<mpsc::UnboundedSender<()>>>>,
+}
+
+impl CtrlC {
+    fn listen() -> Self {
+        let ctrl_c = Self::default();
+        let query = ctrl_c.query.clone();
+        tokio::spawn(async move {
+            while tokio::signal::ctrl_c().await.is_ok() {
+                let sent = query
+                    .lock()
+                    .unwrap()
+                    .as_ref()
+                    .is_some_and(|query| query.send(()).is_ok());
+                if !sent {
+                    println!();
+                    std::process::exit(130);
+                }
+            }
+        });
+        ctrl_c
+    }
+
+    /// Ctrl-C presses until the receiver is dropped
+    fn watch(&self) -> mpsc::UnboundedReceiver<()> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        *self.query.lock().unwrap() = Some(sender);
+        receiver
+    }
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    let conf = config::PeekConfig::get_or_default();
+
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    if args.first().is_some_and(|command| command == "diff") {
+        return diff(&conf, &args[1..]).await;
+    }
+
+    // Models load lazily, so the default one is loaded up front to show progress while it does
+    let mut llm = ai::LLM::new();
     let loading = spinner();
     loading.start("Loading LLM...");
-    let mut llm = ai::LLM::new().await;
-    loading.stop("Done!");
-    llm.add_tool(query_tool());
-
-    let conf = config::PeekConfig::get_or_default();
+    match llm.preload().await {
+        Ok(()) => loading.stop("Done!"),
+        Err(e) => loading.error(e),
+    }
 
-    let connection_options = conf
+    let connections = conf
         .workspaces
         .iter()
         .flat_map(|workspace| {
             workspace
                 .connections
                 .iter()
-                .map(|connection| {
-                    (
-                        connection.url.clone(),
-                        format!("[{}] {}", workspace.name.clone(), connection.name.clone()),
-                        connection.url.clone(),
-                    )
-                })
-                .collect::<Vec<_>>()
+                .map(move |connection| (workspace, connection))
         })
         .collect::<Vec<_>>();
 
-    let db_url = select("Select a connection")
+    let connection_options = connections
+        .iter()
+        .enumerate()
+        .map(|(index, (workspace, connection))| {
+            (
+                index,
+                format!("[{}] {}", workspace.name.clone(), connection.name.clone()),
+                connection.url.clone(),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let selected = select("Select a connection")
         .filter_mode()
         .items(&connection_options)
         .interact()?;
+    let (_, connection) = connections[selected];
+
+    // Queries can be anything, including writes, unless the database refuses them
+    let read_only = connection.access == config::Access::ReadOnly;
+    if read_only {
+        llm.add_tool(query_tool());
+    } else {
+        llm.add_write_tool(query_tool());
+    }
 
-    let mut database = db::postgres::PostgresDatabase::new(db_url).await;
-    let schema = database.get_schema().await.unwrap();
+    let database = db::connect(connection).await?;
+    let backend = database.backend();
+    let access = if read_only {
+        " The connection is read-only, statements that change data will fail."
+    } else {
+        ""
+    };
+    let schema_cache = db::SchemaCache::user();
+    let schema = load_schema(database.as_ref(), connection, schema_cache.as_ref(), false).await?;
 
     llm.set_system_prompt(format!(
         r#"
 You are a database expert and you have been tasked at helping with database queries as well
-as analysing results. You are currently working with a postgres database that has the following
-schema {schema:?}. The schema consists of table names and columns,
-as well as references (from table.col => [table.col])"#
+as analysing results. You are currently working with a {backend} database, its schema is listed
+below. Enum and domain types come first. Tables and views are listed by their schema-qualified
+name, followed by their columns with their types, then their primary key, unique constraints,
+foreign keys and indexes.{access}"#
     ))
     .await;
 
+    let recent_queries = ai::RecentQueries::new(10);
+    llm.add_context_provider(ai::StaticContext::new("Schema", schema.to_string()), 8000);
+    llm.add_context_provider(ai::CurrentDate, 20);
+    llm.add_context_provider(recent_queries.clone(), 1000);
+
+    let ctrl_c = CtrlC::listen();
+
     while let Ok(prompt) = Input::new("You: ")
         .validate(|value: &String| {
             if value.is_empty() {
@@ -62,6 +142,29 @@ as well as references (from table.col => [table.col])"#
         })
         .interact::<String>()
     {
+        // `\i file.sql` runs a script directly, without the assistant
+        if let Some(path) = prompt.strip_prefix("\\i ") {
+            if let Err(e) = run_file(database.as_ref(), path.trim()).await {
+                eprintln!("{}", format!("Error running {}: {e}", path.trim()).red());
+            }
+            continue;
+        }
+
+        // `\refresh` introspects the database again, e.g. after a migration ran elsewhere
+        if prompt.trim() == "\\refresh" {
+            match load_schema(database.as_ref(), connection, schema_cache.as_ref(), true).await {
+                Ok(schema) => {
+                    llm.add_context_provider(
+                        ai::StaticContext::new("Schema", schema.to_string()),
+                        8000,
+                    );
+                    println!("{}", "Schema refreshed".green());
+                }
+                Err(e) => eprintln!("{}", format!("Error refreshing the schema: {e}").red()),
+            }
+            continue;
+        }
+
         print!("\n[{}]", "[Assistant]".blue());
 
         let result = llm
@@ -84,70 +187,359 @@ as well as references (from table.col => [table.col])"#
 
         println!("\n");
 
-        match result {
-            Ok(tool_calls) if !tool_calls.is_empty() => {
-                for tool_call in tool_calls {
-                    println!("{}", format!("[{}]", tool_call.name).yellow());
-
-                    let tool_result = match tool_call.name.as_str() {
-                        "execute_query" => {
-                            match serde_json::from_str::<serde_json::Value>(&tool_call.arguments) {
-                                Ok(args) => {
-                                    if let Some(query) = args
-                                        .get("query")
-                                        .and_then(|v: &serde_json::Value| v.as_str())
-                                    {
-                                        println!("{}", format!("Running query: {}", query).cyan());
-                                        match database.get_results(query).await {
-                                            Ok(results) => {
-                                                let mut table = Table::new();
-                                                table.set_header(
-                                                    results
-                                                        .headers
-                                                        .iter()
-                                                        .map(|header| header.0.clone()),
-                                                );
-                                                for row in results.rows.iter() {
-                                                    table
-                                                        .add_row(row.iter().map(|r| r.to_string()));
-                                                }
-
-                                                println!("{table}");
-                                                format!("{results:?}")
-                                            }
-                                            Err(e) => format!("Error executing query: {e}"),
-                                        }
-                                    } else {
-                                        "Error: No query parameter provided".to_string()
+        let mut tool_calls = match result {
+            Ok(tool_calls) => tool_calls,
+            Err(err) => {
+                eprintln!("{}", err);
+                continue;
+            }
+        };
+
+        // The model can call tools again after seeing the results, until it answers without or
+        // runs out of rounds
+        let mut rounds = 0;
+        let limit = format!(
+            "Tool calls are limited to {MAX_TOOL_ROUNDS} rounds per prompt, answer with the results so far"
+        );
+        while !tool_calls.is_empty() {
+            if rounds >= MAX_TOOL_ROUNDS {
+                eprintln!("{}", "Stopped: the assistant kept calling tools".red());
+                print!("{}", "[Assistant]".blue());
+                llm.decline_tool_calls(&limit, |chunk| async move {
+                    if let ai::StreamChunk::Text(text) = chunk {
+                        print!("{}", text.blue());
+                        let _ = io::stdout().flush();
+                    }
+                })
+                .await
+                .unwrap_or_else(|e| eprintln!("Error declining tool calls: {e}"));
+                println!();
+                break;
+            }
+            rounds += 1;
+
+            let mut results = vec![];
+            for tool_call in tool_calls {
+                println!("{}", format!("[{}]", tool_call.name).yellow());
+
+                let tool_result = match tool_call.name.as_str() {
+                    name if !llm.is_tool_allowed(name) => {
+                        println!(
+                            "{}",
+                            "Blocked: the results contained instruction-like text".red()
+                        );
+                        Err(format!(
+                            "Tool {name} is blocked for this turn because earlier results contained instruction-like text"
+                        ))
+                    }
+                    "execute_query" => {
+                        match serde_json::from_str::<serde_json::Value>(&tool_call.arguments) {
+                            Ok(args) => {
+                                if let Some(query) = args
+                                    .get("query")
+                                    .and_then(|v: &serde_json::Value| v.as_str())
+                                {
+                                    let params: Vec<db::Param> = args
+                                        .get("params")
+                                        .and_then(|v| v.as_array())
+                                        .map(|values| {
+                                            values.iter().cloned().map(db::Param::from).collect()
+                                        })
+                                        .unwrap_or_default();
+                                    println!(
+                                        "{} {}",
+                                        format!("Running query: {}", query).cyan(),
+                                        "(Ctrl-C to cancel)".dimmed()
+                                    );
+                                    if !params.is_empty() {
+                                        println!("{}", format!("Params: {params:?}").cyan());
                                     }
+                                    recent_queries.push(query);
+                                    run_query(database.as_ref(), query, &params, read_only, &ctrl_c)
+                                        .await
+                                        .map_err(|e| format!("Error executing query: {e}"))
+                                } else {
+                                    Err("Error: No query parameter provided".to_string())
                                 }
-                                Err(e) => format!("Error parsing arguments: {e}"),
                             }
+                            Err(e) => Err(format!("Error parsing arguments: {e}")),
                         }
-                        _ => format!("Unknown tool: {}", tool_call.name),
-                    };
-
-                    print!("{}", "[Assistant]".blue());
-                    if let Err(e) = llm
-                        .add_tool_result(tool_call.id, tool_result, |chunk| async move {
-                            if let ai::StreamChunk::Text(text) = chunk {
-                                print!("{}", text.blue());
-                                let _ = io::stdout().flush();
-                            }
-                        })
-                        .await
-                    {
-                        eprintln!("Error adding tool result: {}", e);
                     }
+                    _ => Err(format!("Unknown tool: {}", tool_call.name)),
+                };
+                results.push((tool_call.id, tool_result));
+            }
+
+            print!("{}", "[Assistant]".blue());
+            tool_calls = llm
+                .add_tool_results(results, |chunk| async move {
+                    if let ai::StreamChunk::Text(text) = chunk {
+                        print!("{}", text.blue());
+                        let _ = io::stdout().flush();
+                    }
+                })
+                .await
+                .unwrap_or_else(|e| {
+                    eprintln!("Error adding tool results: {e}");
+                    vec![]
+                });
+            println!();
+        }
+    }
+
+    Ok(())
+}
+
+/// Run a query from the model and show its results, returning what the model gets to see
+async fn run_query(
+    database: &dyn db::Database,
+    query: &str,
+    params: &[db::Param],
+    read_only: bool,
+    ctrl_c: &CtrlC,
+) -> Result<String, db::DbError> {
+    // Changed rows are only committed once the user has seen them. Anything else that may write,
+    // such as DDL, can commit on its own, so it is confirmed before it runs instead.
+    if !read_only && !db::is_read_only(query) {
+        if db::can_preview(query) {
+            return apply_changes(database, query, params).await;
+        }
+        let run = confirm("Run this statement? It can't be rolled back")
+            .initial_value(false)
+            .interact()
+            .unwrap_or(false);
+        if !run {
+            println!("{}", "Not run".yellow());
+            return Ok("The user chose not to run the statement".to_string());
+        }
+    }
+
+    // Statements without a result, such as DDL, report what they did instead
+    let returns_rows = database
+        .describe(query)
+        .await
+        .map_or(true, |description| !description.columns.is_empty());
+    if !returns_rows {
+        let outcome = database.execute_with(query, params).await?;
+        println!("{outcome}");
+        return Ok(format!("{outcome:?}"));
+    }
+
+    let (results, truncated) = fetch_rows(database, query, params, ctrl_c).await?;
+    let note = match truncated {
+        Some(db::Limit::Rows) => format!(", stopped at {MAX_ROWS} rows"),
+        Some(db::Limit::Bytes) => format!(", stopped at {} KiB", MAX_BYTES / 1024),
+        None => String::new(),
+    };
+    println!("{}", results_table(&results.headers, &results.rows));
+    println!("({} rows{note})", results.rows.len());
+    Ok(format!("{results:?}{note}"))
+}
+
+/// Run the statements of a SQL file in a transaction, stopping at the first error
+async fn run_file(database: &dyn db::Database, path: &str) -> anyhow::Result<()> {
+    let script = std::fs::read_to_string(path)?;
+    let options = db::ScriptOptions {
+        transaction: true,
+        stop_on_error: true,
+    };
+    let outcome = database.run_script(&script, options).await?;
+
+    for statement in &outcome.statements {
+        let line = format!("line {}:", statement.statement.line).dimmed();
+        match &statement.result {
+            Ok(result) => println!("{line} {result}"),
+            Err(e) => println!("{line} {}", format!("{e}").red()),
+        }
+    }
+
+    // Scripts with transactions of their own run as written, and stop at the error instead
+    let ended = if outcome.rolled_back {
+        format!(", rolled back ({} not run)", outcome.skipped).red()
+    } else if outcome.failed() > 0 {
+        format!(", stopped at the error ({} not run)", outcome.skipped).red()
+    } else {
+        ", committed".green()
+    };
+    println!(
+        "{} statements in {} ms{ended}",
+        outcome.statements.len(),
+        outcome.elapsed.as_millis()
+    );
+    Ok(())
+}
+
+/// `ui diff <from> <to> [--sql]`: compare the schemas of two configured connections, and show
+/// what differs or, with `--sql`, a Postgres script that turns `from` into `to`
+async fn diff(conf: &config::PeekConfig, args: &[String]) -> anyhow::Result<()> {
+    let sql = args.iter().any(|arg| arg == "--sql");
+    let names = args
+        .iter()
+        .filter(|arg| *arg != "--sql")
+        .map(String::as_str)
+        .collect::<Vec<_>>();
+    let [from, to] = names[..] else {
+        anyhow::bail!(
+            "Usage: ui diff <from> <to> [--sql], naming connections as `connection` or `workspace/connection`"
+        );
+    };
+
+    let schema_cache = db::SchemaCache::user();
+    let introspect = async |name| {
+        let connection = find_connection(conf, name)?;
+        let database = db::connect(connection).await?;
+        anyhow::Ok(load_schema(database.as_ref(), connection, schema_cache.as_ref(), false).await?)
+    };
+    let (from, to) = tokio::try_join!(introspect(from), introspect(to))?;
+    let diff = from.diff(&to);
+
+    if sql {
+        print!("{}", diff.postgres_migration());
+    } else if diff.is_empty() {
+        println!("{}", "The schemas are the same".green());
+    } else {
+        for line in diff.to_string().lines() {
+            match line.trim_start().chars().next() {
+                Some('+') => println!("{}", line.green()),
+                Some('-') => println!("{}", line.red()),
+                Some('~') => println!("{}", line.yellow()),
+                _ => println!("{line}"),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The schema of a connection's database, taken from the user's schema cache while it's still
+/// current unless `refresh` is set
+async fn load_schema(
+    database: &dyn db::Database,
+    connection: &config::DatabaseConnection,
+    schema_cache: Option<&db::SchemaCache>,
+    refresh: bool,
+) -> Result<db::Schema, db::DbError> {
+    match schema_cache {
+        Some(cache) if refresh => cache.refresh(database, connection).await,
+        Some(cache) => cache.get_schema(database, connection).await,
+        None => database.get_schema().await,
+    }
+}
+
+/// A connection by its name, qualified with its workspace's if the name alone is ambiguous
+fn find_connection<'c>(
+    conf: &'c config::PeekConfig,
+    name: &str,
+) -> anyhow::Result<&'c config::DatabaseConnection> {
+    let found = conf
+        .workspaces
+        .iter()
+        .flat_map(|workspace| {
+            workspace.connections.iter().filter(move |connection| {
+                connection.name == name || format!("{}/{}", workspace.name, connection.name) == name
+            })
+        })
+        .collect::<Vec<_>>();
+
+    match found[..] {
+        [connection] => Ok(connection),
+        [] => anyhow::bail!("There is no connection named {name}"),
+        _ => anyhow::bail!("There are several connections named {name}, use workspace/{name}"),
+    }
+}
+
+fn results_table(headers: &[(String, String)], rows: &[Vec<serde_json::Value>]) -> Table {
+    let mut table = Table::new();
+    table.set_header(headers.iter().map(|header| header.0.clone()));
+    for row in rows {
+        table.add_row(row.iter().map(|value| value.to_string()));
+    }
+    table
+}
+
+/// Run a data-changing statement in a transaction and show what it changed, then let the user
+/// decide whether to commit it
+async fn apply_changes(
+    database: &dyn db::Database,
+    query: &str,
+    params: &[db::Param],
+) -> Result<String, db::DbError> {
+    let mut transaction = database.begin().await?;
+    let changes = transaction.preview(query, params).await?;
+
+    if !changes.headers.is_empty() {
+        println!("{}", results_table(&changes.headers, &changes.rows));
+    }
+    let sample = if (changes.rows.len() as u64) < changes.rows_affected && !changes.rows.is_empty()
+    {
+        format!(", showing the first {}", changes.rows.len())
+    } else {
+        String::new()
+    };
+    println!("({} rows changed{sample})", changes.rows_affected);
+
+    let commit = confirm("Commit these changes?")
+        .initial_value(false)
+        .interact()
+        .unwrap_or(false);
+    if commit {
+        transaction.commit().await?;
+        Ok(format!("{changes:?}, committed"))
+    } else {
+        transaction.rollback().await?;
+        Ok(format!("{changes:?}, rolled back by the user"))
+    }
+}
+
+/// Fetch the rows of a query as they arrive, showing how many have come in so far.
+///
+/// Ctrl-C cancels the query on the server, which then ends the stream with its error.
+async fn fetch_rows(
+    database: &dyn db::Database,
+    query: &str,
+    params: &[db::Param],
+    ctrl_c: &CtrlC,
+) -> Result<(db::DatabaseResult, Option<db::Limit>), db::DbError> {
+    let limits = db::StreamLimits {
+        max_rows: Some(MAX_ROWS),
+        max_bytes: Some(MAX_BYTES),
+    };
+    let mut interrupts = ctrl_c.watch();
+    let mut stream = database.stream_results(query, params, limits).await?;
+    let cancel = stream.cancel_handle();
+    let mut cancelled = false;
+
+    let mut rows = vec![];
+    loop {
+        let row = tokio::select! {
+            row = stream.next() => row,
+            _ = interrupts.recv(), if !cancelled => {
+                cancelled = true;
+                // Without a query to cancel on the server, stop reading instead
+                if !cancel.cancel().await? {
                     println!();
+                    return Err(db::DbError::Other("Query cancelled".to_string()));
                 }
+                continue;
             }
-            Err(err) => {
-                eprintln!("{}", err);
-            }
-            _ => {}
+        };
+        let Some(row) = row else {
+            break;
+        };
+
+        rows.push(row?);
+        if rows.len() % 100 == 0 {
+            print!("\rFetched {} rows", stream.rows_fetched());
+            let _ = io::stdout().flush();
         }
     }
+    if rows.len() >= 100 {
+        println!();
+    }
 
-    Ok(())
+    let truncated = stream.truncated();
+    let results = db::DatabaseResult {
+        headers: stream.headers,
+        rows,
+    };
+    Ok((results, truncated))
 }
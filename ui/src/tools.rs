@@ -1,4 +1,4 @@
-use ai::{create_tool, json, HashMap, Value};
+use ai::{HashMap, Value, create_tool, json};
 
 pub fn query_tool() -> ai::Tool {
     let parameters: HashMap<String, Value> = serde_json::from_value(json!({
@@ -8,6 +8,11 @@ pub fn query_tool() -> ai::Tool {
                 "type": "string",
                 "description": "The SQL query to execute against the database.",
             },
+            "params": {
+                "type": "array",
+                "description": "Values for the $1, $2, ... placeholders of the query (? for MySQL and SQLite), in order. Use placeholders instead of quoting literal values into the query. On Postgres a list of values is passed as an array param, e.g. `id = ANY($1)` with [1, 2, 3].",
+                "items": {},
+            },
         },
         "required": ["query"],
     }))